@@ -96,6 +96,7 @@ fn create_test_policies() -> Vec<Authorization> {
             scope: ztunnel::rbac::RbacScope::Global,
             namespace: "default".to_string(),
             rules: rules.clone(),
+            dry_run: false,
         });
     }
 
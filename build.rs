@@ -24,6 +24,11 @@ fn main() -> Result<(), anyhow::Error> {
         "proto/authorization.proto",
         "proto/citadel.proto",
         "proto/zds.proto",
+        "proto/ext_authz.proto",
+        "proto/opentelemetry/proto/common/v1/common.proto",
+        "proto/opentelemetry/proto/resource/v1/resource.proto",
+        "proto/opentelemetry/proto/trace/v1/trace.proto",
+        "proto/opentelemetry/proto/collector/trace/v1/trace_service.proto",
     ]
     .iter()
     .map(|name| std::env::current_dir().unwrap().join(name))
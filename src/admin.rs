@@ -15,6 +15,7 @@
 use crate::config::Config;
 use crate::hyper_util::{empty_response, plaintext_response, Server};
 use crate::identity::SecretManager;
+use crate::rbac;
 use crate::state::DemandProxyState;
 use crate::tls::Certificate;
 use crate::version::BuildInfo;
@@ -142,6 +143,7 @@ impl Service {
                     .await
                 }
                 "/logging" => Ok(handle_logging(req).await),
+                "/debug/authz" => Ok(handle_authz(req, &state.proxy_state, &state.config.network)),
                 "/" => Ok(handle_dashboard(req, &state.handlers).await),
                 _ => match Self::find_handler(state.as_ref(), req.uri().path()) {
                     Some(handler) => Ok(handler.handle(req).await),
@@ -177,6 +179,10 @@ async fn handle_dashboard(
         ("quitquitquit", "shut down the server"),
         ("config_dump", "dump the current Ztunnel configuration"),
         ("logging", "query/changing logging levels"),
+        (
+            "debug/authz",
+            "explain the authorization policy decision for a connection",
+        ),
     ];
     let handlers_api = handlers.iter().map(|h| (h.path(), h.description()));
 
@@ -381,6 +387,89 @@ fn change_log_level(reset: bool, level: &str) -> Response<Full<Bytes>> {
     }
 }
 
+static AUTHZ_HELP_STRING: &str = "
+usage: GET /debug/authz?src=<ip:port>&dst=<ip:port>\t\t\t(To explain the decision for a connection)
+usage: GET /debug/authz?src=<ip:port>&dst=<ip:port>&src_identity=<spiffe id>\t(For a connection with a verified identity)
+usage: GET /debug/authz?src=<ip:port>&dst=<ip:port>&network=<network>\t(For a destination on another network)
+";
+fn handle_authz(
+    req: Request<Incoming>,
+    proxy_state: &DemandProxyState,
+    network: &str,
+) -> Response<Full<Bytes>> {
+    if *req.method() != hyper::Method::GET {
+        return plaintext_response(
+            hyper::StatusCode::METHOD_NOT_ALLOWED,
+            format!("Invalid HTTP method\n {AUTHZ_HELP_STRING}"),
+        );
+    }
+    let qp: HashMap<String, String> = req
+        .uri()
+        .query()
+        .map(|v| {
+            url::form_urlencoded::parse(v.as_bytes())
+                .into_owned()
+                .collect()
+        })
+        .unwrap_or_default();
+    let conn = match authz_connection(&qp, network) {
+        Ok(conn) => conn,
+        Err(e) => {
+            return plaintext_response(
+                hyper::StatusCode::BAD_REQUEST,
+                format!("{e}\n{AUTHZ_HELP_STRING}"),
+            )
+        }
+    };
+    let Some(explanation) = proxy_state.explain_rbac(&conn) else {
+        return plaintext_response(
+            hyper::StatusCode::NOT_FOUND,
+            format!("destination workload for {} not found\n", conn.dst),
+        );
+    };
+    match serde_json::to_string_pretty(&explanation) {
+        Ok(body) => {
+            let mut response = plaintext_response(hyper::StatusCode::OK, body);
+            response
+                .headers_mut()
+                .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+            response
+        }
+        Err(e) => plaintext_response(
+            hyper::StatusCode::INTERNAL_SERVER_ERROR,
+            format!("failed to serialize the explanation: {e}\n"),
+        ),
+    }
+}
+
+// authz_connection builds the connection to explain from the query parameters.
+fn authz_connection(
+    qp: &HashMap<String, String>,
+    network: &str,
+) -> Result<rbac::Connection, String> {
+    let addr = |key: &str| -> Result<SocketAddr, String> {
+        let value = qp.get(key).ok_or(format!("missing {key}"))?;
+        value
+            .parse()
+            .map_err(|e| format!("invalid {key} {value}: {e}"))
+    };
+    Ok(rbac::Connection {
+        src: addr("src")?,
+        dst: addr("dst")?,
+        src_identity: qp
+            .get("src_identity")
+            .map(|id| {
+                id.parse()
+                    .map_err(|e| format!("invalid src_identity {id}: {e}"))
+            })
+            .transpose()?,
+        dst_network: qp
+            .get("network")
+            .cloned()
+            .unwrap_or_else(|| network.to_string()),
+    })
+}
+
 #[cfg(feature = "jemalloc")]
 async fn handle_jemalloc_pprof_heapgen(
     _req: Request<Incoming>,
@@ -416,6 +505,7 @@ fn base64_encode(data: String) -> String {
 
 #[cfg(test)]
 mod tests {
+    use super::authz_connection;
     use super::change_log_level;
     use super::dump_certs;
     use super::handle_config_dump;
@@ -433,6 +523,7 @@ mod tests {
     use crate::xds::istio::security::Rule as XdsRule;
     use crate::xds::istio::security::StringMatch as XdsStringMatch;
     use crate::xds::istio::workload::gateway_address::Destination as XdsDestination;
+    use crate::xds::istio::workload::BandwidthLimit as XdsBandwidthLimit;
     use crate::xds::istio::workload::GatewayAddress as XdsGatewayAddress;
     use crate::xds::istio::workload::LoadBalancing as XdsLoadBalancing;
     use crate::xds::istio::workload::Locality as XdsLocality;
@@ -631,6 +722,10 @@ mod tests {
                 zone: "zone".to_string(),
                 subzone: "subezone".to_string(),
             }),
+            bandwidth_limit: Some(XdsBandwidthLimit {
+                upload_bytes_per_second: 1048576,
+                download_bytes_per_second: 0,
+            }),
             // ..Default::default() // intentionally don't default. we want all fields populated
         };
 
@@ -651,6 +746,7 @@ mod tests {
             load_balancing: Some(XdsLoadBalancing {
                 routing_preference: vec![1, 2],
                 mode: 1,
+                algorithm: 2,
             }), // ..Default::default() // intentionally don't default. we want all fields populated
         };
 
@@ -699,6 +795,7 @@ mod tests {
                     }],
                 }],
             }],
+            dry_run: false,
             // ..Default::default() // intentionally don't default. we want all fields populated
         };
 
@@ -747,6 +844,51 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_authz_connection() {
+        let qp = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        let conn = authz_connection(
+            &qp(&[
+                ("src", "10.0.0.1:1234"),
+                ("dst", "10.0.0.2:8080"),
+                (
+                    "src_identity",
+                    "spiffe://cluster.local/ns/default/sa/client",
+                ),
+            ]),
+            "network",
+        )
+        .unwrap();
+        assert_eq!(conn.src, "10.0.0.1:1234".parse().unwrap());
+        assert_eq!(conn.dst, "10.0.0.2:8080".parse().unwrap());
+        assert_eq!(
+            conn.src_identity,
+            Some(identity::Identity::Spiffe {
+                trust_domain: "cluster.local".to_string(),
+                namespace: "default".to_string(),
+                service_account: "client".to_string(),
+            })
+        );
+        assert_eq!(conn.dst_network, "network");
+
+        assert!(authz_connection(&qp(&[("src", "10.0.0.1:1234")]), "").is_err());
+        assert!(authz_connection(&qp(&[("src", "10.0.0.1"), ("dst", "10.0.0.2:80")]), "").is_err());
+        assert!(authz_connection(
+            &qp(&[
+                ("src", "10.0.0.1:1234"),
+                ("dst", "10.0.0.2:80"),
+                ("src_identity", "not-spiffe")
+            ]),
+            ""
+        )
+        .is_err());
+    }
+
     // each of these tests assert that we can change the log level and the
     // appropriate response string is returned.
     //
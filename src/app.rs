@@ -101,6 +101,10 @@ pub async fn build_with_cert(
         std::mem::drop(state_mgr_task);
     });
     let state = state_mgr.state();
+    if config.proxy {
+        metrics::sub_registry(&mut registry)
+            .register_collector(Box::new(proxy::OutlierCollector(state.outliers())));
+    }
 
     // Run the XDS state manager in the current tokio worker pool.
     tokio::spawn(state_mgr.run());
@@ -117,6 +121,22 @@ pub async fn build_with_cert(
     .context("admin server starts")?;
     let admin_address = admin_server.address();
 
+    let access_log = if config.proxy {
+        let access_log =
+            proxy::access_log::AccessLog::new(&config, metrics::sub_registry(&mut registry))
+                .context("access log setup")?;
+        admin_server.add_handler(Arc::new(access_log.clone()));
+        access_log
+    } else {
+        Default::default()
+    };
+    let trace_exporter = if config.proxy {
+        proxy::otel::Exporter::new(&config, metrics::sub_registry(&mut registry))
+            .context("trace export setup")?
+    } else {
+        Default::default()
+    };
+
     // Optionally create the HBONE proxy.
     let mut proxy_addresses = None;
     let mut tcp_dns_proxy_address: Option<SocketAddr> = None;
@@ -129,8 +149,11 @@ pub async fn build_with_cert(
         proxy_metrics,
         dns_metrics,
         drain_rx.clone(),
+        access_log,
+        trace_exporter,
     )
     .map_err(|e| anyhow::anyhow!("failed to start proxy factory {:?}", e))?;
+    admin_server.add_handler(proxy_gen.pools());
 
     if config.inpod_enabled {
         tracing::info!("in-pod mode enabled");
@@ -27,6 +27,9 @@ use hyper::http::uri::InvalidUri;
 use hyper::Uri;
 
 use crate::identity;
+use crate::proxy::access_log;
+use crate::state::load_balancer::LoadBalancerAlgorithm;
+use crate::state::workload::BandwidthLimit;
 
 const ENABLE_PROXY: &str = "ENABLE_PROXY";
 const KUBERNETES_SERVICE_HOST: &str = "KUBERNETES_SERVICE_HOST";
@@ -49,6 +52,44 @@ const FAKE_CA: &str = "FAKE_CA";
 const ZTUNNEL_WORKER_THREADS: &str = "ZTUNNEL_WORKER_THREADS";
 const ENABLE_ORIG_SRC: &str = "ENABLE_ORIG_SRC";
 const PROXY_CONFIG: &str = "PROXY_CONFIG";
+const ENABLE_UDP_PROXY: &str = "ENABLE_UDP_PROXY";
+const ENABLE_SINGLE_TLS_INBOUND: &str = "ENABLE_SINGLE_TLS_INBOUND";
+const UDP_IDLE_TIMEOUT: &str = "UDP_IDLE_TIMEOUT";
+const CONNECTION_RETRY_BUDGET: &str = "CONNECTION_RETRY_BUDGET";
+const CONNECTION_ATTEMPT_TIMEOUT: &str = "CONNECTION_ATTEMPT_TIMEOUT";
+const OUTLIER_CONSECUTIVE_FAILURES: &str = "OUTLIER_CONSECUTIVE_FAILURES";
+const OUTLIER_BASE_EJECTION_TIME: &str = "OUTLIER_BASE_EJECTION_TIME";
+const OUTLIER_MAX_EJECTION_TIME: &str = "OUTLIER_MAX_EJECTION_TIME";
+const LOAD_BALANCING_ALGORITHM: &str = "LOAD_BALANCING_ALGORITHM";
+const LOAD_BALANCING_OVERRIDES: &str = "LOAD_BALANCING_OVERRIDES";
+const WORKLOAD_BANDWIDTH_LIMIT: &str = "WORKLOAD_BANDWIDTH_LIMIT";
+const SERVICE_BANDWIDTH_LIMITS: &str = "SERVICE_BANDWIDTH_LIMITS";
+const SERVICE_MAX_CONNECTIONS: &str = "SERVICE_MAX_CONNECTIONS";
+const SERVICE_MAX_CONNECTIONS_OVERRIDES: &str = "SERVICE_MAX_CONNECTIONS_OVERRIDES";
+const WORKLOAD_MAX_CONNECTIONS: &str = "WORKLOAD_MAX_CONNECTIONS";
+const CONNECTION_IDLE_TIMEOUT: &str = "CONNECTION_IDLE_TIMEOUT";
+const CONNECTION_MAX_DURATION: &str = "CONNECTION_MAX_DURATION";
+const CONNECTION_METRICS_INTERVAL: &str = "CONNECTION_METRICS_INTERVAL";
+const POOL_MAX_STREAMS_PER_CONNECTION: &str = "POOL_MAX_STREAMS_PER_CONNECTION";
+const POOL_IDLE_TIMEOUT: &str = "POOL_IDLE_TIMEOUT";
+const POOL_KEEPALIVE_INTERVAL: &str = "POOL_KEEPALIVE_INTERVAL";
+const POOL_KEEPALIVE_TIMEOUT: &str = "POOL_KEEPALIVE_TIMEOUT";
+const SOCKS5_ADDR: &str = "SOCKS5_ADDR";
+const SOCKS5_CREDENTIALS_FILE: &str = "SOCKS5_CREDENTIALS_FILE";
+const HTTP_CONNECT_ADDR: &str = "HTTP_CONNECT_ADDR";
+const PROXY_PROTOCOL_TRUSTED_CIDRS: &str = "PROXY_PROTOCOL_TRUSTED_CIDRS";
+const ACCESS_LOG: &str = "ACCESS_LOG";
+const ACCESS_LOG_FORMAT: &str = "ACCESS_LOG_FORMAT";
+const ACCESS_LOG_FIELDS: &str = "ACCESS_LOG_FIELDS";
+const ACCESS_LOG_SINK: &str = "ACCESS_LOG_SINK";
+const ACCESS_LOG_MAX_FILE_SIZE: &str = "ACCESS_LOG_MAX_FILE_SIZE";
+const ACCESS_LOG_MAX_FILES: &str = "ACCESS_LOG_MAX_FILES";
+const OTLP_TRACES_ENDPOINT: &str = "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT";
+const TRACE_SAMPLING_RATIO: &str = "TRACE_SAMPLING_RATIO";
+const EXT_AUTHZ_ADDRESS: &str = "EXT_AUTHZ_ADDRESS";
+const EXT_AUTHZ_TIMEOUT: &str = "EXT_AUTHZ_TIMEOUT";
+const EXT_AUTHZ_FAIL_OPEN: &str = "EXT_AUTHZ_FAIL_OPEN";
+const EXT_AUTHZ_CACHE_TTL: &str = "EXT_AUTHZ_CACHE_TTL";
 
 const DEFAULT_WORKER_THREADS: u16 = 2;
 const DEFAULT_ADMIN_PORT: u16 = 15000;
@@ -56,9 +97,26 @@ const DEFAULT_READINESS_PORT: u16 = 15021;
 const DEFAULT_STATS_PORT: u16 = 15020;
 const DEFAULT_DNS_PORT: u16 = 15053;
 const DEFAULT_SELFTERM_DEADLINE: Duration = Duration::from_secs(5);
+const DEFAULT_TERMINATION_DRAIN_DURATION: Duration = Duration::from_secs(5);
 const DEFAULT_CLUSTER_ID: &str = "Kubernetes";
 const DEFAULT_CLUSTER_DOMAIN: &str = "cluster.local";
 const DEFAULT_TTL: Duration = Duration::from_secs(60 * 60 * 24); // 24 hours
+const DEFAULT_UDP_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_CONNECTION_RETRY_BUDGET: usize = 2;
+const DEFAULT_CONNECTION_ATTEMPT_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_CONNECTION_METRICS_INTERVAL: Duration = Duration::from_secs(15);
+const DEFAULT_OUTLIER_CONSECUTIVE_FAILURES: u32 = 5;
+const DEFAULT_OUTLIER_BASE_EJECTION_TIME: Duration = Duration::from_secs(30);
+const DEFAULT_OUTLIER_MAX_EJECTION_TIME: Duration = Duration::from_secs(300);
+const DEFAULT_POOL_MAX_STREAMS_PER_CONNECTION: u16 = 100;
+const DEFAULT_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+const DEFAULT_POOL_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+const DEFAULT_POOL_KEEPALIVE_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_ACCESS_LOG_MAX_FILE_SIZE: u64 = 100 * 1024 * 1024;
+const DEFAULT_ACCESS_LOG_MAX_FILES: usize = 5;
+const DEFAULT_TRACE_SAMPLING_RATIO: f64 = 0.01;
+const DEFAULT_EXT_AUTHZ_TIMEOUT: Duration = Duration::from_millis(200);
+const DEFAULT_EXT_AUTHZ_CACHE_TTL: Duration = Duration::from_secs(10);
 
 const DEFAULT_INPOD_MARK: u32 = 1337;
 
@@ -116,12 +174,33 @@ pub struct Config {
     pub connection_window_size: u32,
     pub frame_size: u32,
 
+    /// The maximum number of concurrent HBONE streams to multiplex on a single pooled connection.
+    /// Once all connections to a destination are saturated, a new one is opened.
+    pub pool_max_streams_per_conn: u16,
+    /// Pooled HBONE connections without any streams are closed after this long.
+    pub pool_idle_timeout: Duration,
+    /// How often to send HTTP/2 PINGs on pooled HBONE connections, to detect dead peers.
+    pub pool_keepalive_interval: Duration,
+    /// How long to wait for a PING acknowledgement before closing the connection.
+    pub pool_keepalive_timeout: Duration,
+
     pub socks5_addr: SocketAddr,
+    /// If non-empty, SOCKS5 clients must authenticate with one of these username/password
+    /// credentials, keyed by username. Connections are then made as the credential's identity.
+    /// UDP ASSOCIATE datagrams are sent as plaintext, so they do not carry the identity.
+    pub socks5_credentials: HashMap<String, Socks5Credential>,
+    /// Address of the HTTP CONNECT forward proxy listener.
+    pub http_connect_addr: SocketAddr,
     pub admin_addr: SocketAddr,
     pub stats_addr: SocketAddr,
     pub readiness_addr: SocketAddr,
     pub inbound_addr: SocketAddr,
     pub inbound_plaintext_addr: SocketAddr,
+    /// Peers of the plaintext inbound listener, such as external load balancers, that must send a
+    /// PROXY protocol header. The client address it carries is then used as the connection source.
+    pub proxy_protocol_trusted_cidrs: Vec<ipnet::IpNet>,
+    /// The socket address for the single TLS HBONE listener. Only applies if `single_tls_inbound` is true.
+    pub inbound_single_tls_addr: SocketAddr,
     pub outbound_addr: SocketAddr,
     /// The socket address for the DNS proxy. Only applies if `dns_proxy` is true.
     pub dns_proxy_addr: SocketAddr,
@@ -163,6 +242,9 @@ pub struct Config {
     // How long ztunnel should wait for in-flight requesthandlers to finish processing
     // before giving up when ztunnel is self-terminating (when instructed via the Admin API)
     pub self_termination_deadline: Duration,
+    // How long outbound listeners wait for in-flight connections to complete when draining, before
+    // closing them. Configured by ProxyConfig.terminationDrainDuration.
+    pub termination_drain_duration: Duration,
 
     pub proxy_metadata: HashMap<String, String>,
 
@@ -172,6 +254,96 @@ pub struct Config {
     // If true, then use original source proxying
     pub enable_original_source: Option<bool>,
 
+    /// If true, UDP traffic is also captured on the outbound and inbound plaintext addresses.
+    /// This requires UDP traffic to be redirected with TPROXY. UDP is relayed as plaintext, so flows
+    /// to destinations which require HBONE or have a waypoint are refused.
+    pub udp_proxy: bool,
+    /// How long a UDP flow may be idle before its session is removed.
+    pub udp_idle_timeout: Duration,
+
+    /// If true, ztunnel also accepts single TLS HBONE on `inbound_single_tls_addr`, acting as a
+    /// network gateway for peers on other networks. Peers must present a valid workload certificate.
+    pub single_tls_inbound: bool,
+
+    /// How many times an outbound connection to a service may fail over to another endpoint, if
+    /// connecting to the selected endpoint fails.
+    pub connect_retry_budget: usize,
+    /// How long a single attempt to connect to an upstream may take.
+    pub connect_timeout: Duration,
+
+    /// How many consecutive failures eject a service endpoint from load balancing. Zero disables
+    /// outlier detection.
+    pub outlier_consecutive_failures: u32,
+    /// How long an endpoint is first ejected for. Repeated ejections double this, up to
+    /// `outlier_max_ejection_time`.
+    pub outlier_base_ejection_time: Duration,
+    pub outlier_max_ejection_time: Duration,
+
+    /// The algorithm used to pick between service endpoints.
+    pub load_balancing_algorithm: LoadBalancerAlgorithm,
+    /// Overrides `load_balancing_algorithm` for specific services, keyed by service hostname.
+    /// Configured as a comma separated list, like `svc.ns.svc.cluster.local=LEAST_CONNECTIONS`.
+    pub load_balancing_overrides: HashMap<String, LoadBalancerAlgorithm>,
+
+    /// The default bandwidth limit for each source workload, formatted as `upload:download` in bytes
+    /// per second. Workloads may override this in their own configuration.
+    pub workload_bandwidth_limit: BandwidthLimit,
+    /// Bandwidth limits for traffic to specific services, shared by all sources, keyed by service
+    /// hostname. Configured like `load_balancing_overrides`, with `upload:download` values.
+    pub service_bandwidth_limits: HashMap<String, BandwidthLimit>,
+
+    /// If set, the maximum number of concurrent connections to each destination service, in each
+    /// direction. Further connections are rejected.
+    pub service_max_connections: Option<usize>,
+    /// Overrides `service_max_connections` for specific services, keyed by service hostname.
+    /// Configured like `load_balancing_overrides`.
+    pub service_max_connections_overrides: HashMap<String, usize>,
+    /// If set, the maximum number of concurrent connections from each source workload, in each
+    /// direction. Further connections are rejected.
+    pub workload_max_connections: Option<usize>,
+
+    /// If set, proxied TCP connections are closed once no data has been sent in either direction
+    /// for this long.
+    pub connection_idle_timeout: Option<Duration>,
+    /// If set, proxied TCP connections are closed once they have been open for this long,
+    /// regardless of activity.
+    pub connection_max_duration: Option<Duration>,
+    /// How often the byte counters of open TCP connections are updated, rather than only once they
+    /// close. When set, bytes transferred by connections that end in an error are counted as well;
+    /// if unset (configured as 0), only connections that complete successfully are counted.
+    pub connection_metrics_interval: Option<Duration>,
+
+    /// If true, an access log entry is written for each connection. This can be changed at runtime
+    /// through the admin server.
+    pub access_log: bool,
+    pub access_log_format: access_log::Format,
+    /// The fields to include in access log entries. If empty, all fields are included.
+    pub access_log_fields: Vec<String>,
+    pub access_log_sink: access_log::Sink,
+    /// The size, in bytes, at which an access log file is rotated.
+    pub access_log_max_file_size: u64,
+    /// How many rotated access log files are kept.
+    pub access_log_max_files: usize,
+
+    /// If set, spans for proxied connections are exported to this OTLP/HTTP endpoint, such as
+    /// `http://otel-collector:4318/v1/traces`.
+    pub otlp_traces_endpoint: Option<String>,
+    /// The fraction of outbound connections to start a trace for. Inbound connections are traced
+    /// if, and only if, the sender sampled them.
+    pub trace_sampling_ratio: f64,
+
+    /// If set, connections matching a CUSTOM authorization policy are checked with this gRPC
+    /// external authorization service, such as `http://authz.example:9000`.
+    pub ext_authz_address: Option<String>,
+    /// How long to wait for the external authorization service to decide on a connection.
+    pub ext_authz_timeout: Duration,
+    /// If true, connections are allowed when the external authorization service fails or does not
+    /// decide in time. Otherwise, they are denied.
+    pub ext_authz_fail_open: bool,
+    /// How long decisions of the external authorization service are reused for, by connections
+    /// with the same source IP, identity and destination. If 0, every connection is checked.
+    pub ext_authz_cache_ttl: Duration,
+
     // CLI args passed to ztunnel at runtime
     pub proxy_args: String,
 
@@ -217,6 +389,107 @@ fn parse_default<T: FromStr>(env: &str, default: T) -> Result<T, Error> {
     parse(env).map(|v| v.unwrap_or(default))
 }
 
+fn parse_duration(env: &str) -> Result<Option<Duration>, Error> {
+    match parse::<String>(env)? {
+        Some(val) => duration_str::parse(&val)
+            .map(Some)
+            .map_err(|_| Error::EnvVar(env.to_string(), val)),
+        None => Ok(None),
+    }
+}
+
+/// Credentials a SOCKS5 client can authenticate with, and the identity it is then proxied as.
+#[derive(serde::Serialize, Clone, PartialEq, Eq)]
+pub struct Socks5Credential {
+    #[serde(skip_serializing)]
+    pub password: String,
+    pub identity: identity::Identity,
+}
+
+impl std::fmt::Debug for Socks5Credential {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Socks5Credential")
+            .field("identity", &self.identity)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Reads SOCKS5 credentials from the file named by `env`.
+fn parse_socks5_credentials(env: &str) -> Result<HashMap<String, Socks5Credential>, Error> {
+    let Some(path) = parse::<String>(env)? else {
+        return Ok(HashMap::new());
+    };
+    let contents = fs::read_to_string(&path)
+        .map_err(|e| Error::ProxyConfig(anyhow!("failed to read {env}={path}: {e}")))?;
+    parse_socks5_credentials_file(&path, &contents)
+}
+
+/// Parses the contents of a SOCKS5 credentials file. Each line is formatted as
+/// `username:password:identity`; blank lines and lines starting with `#` are ignored.
+fn parse_socks5_credentials_file(
+    path: &str,
+    contents: &str,
+) -> Result<HashMap<String, Socks5Credential>, Error> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| {
+            let mut parts = l.splitn(3, ':');
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(user), Some(password), Some(identity)) if !user.is_empty() => {
+                    let identity = identity::Identity::from_str(identity).map_err(|_| {
+                        Error::ProxyConfig(anyhow!("invalid identity for SOCKS5 user {user}"))
+                    })?;
+                    Ok((
+                        user.to_string(),
+                        Socks5Credential {
+                            password: password.to_string(),
+                            identity,
+                        },
+                    ))
+                }
+                // Don't echo the line back, it contains a password
+                _ => Err(Error::ProxyConfig(anyhow!(
+                    "invalid SOCKS5 credential in {path}"
+                ))),
+            }
+        })
+        .collect()
+}
+
+/// Parses a comma separated list.
+fn parse_list<T: FromStr>(env: &str) -> Result<Vec<T>, Error> {
+    let Some(list) = parse::<String>(env)? else {
+        return Ok(Vec::new());
+    };
+    list.split(',')
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(|v| {
+            v.parse()
+                .map_err(|_| Error::EnvVar(env.to_string(), v.to_string()))
+        })
+        .collect()
+}
+
+/// Parses per-service settings formatted as a comma separated list of `hostname=value`.
+fn parse_service_overrides<T: FromStr>(env: &str) -> Result<HashMap<String, T>, Error> {
+    let Some(overrides) = parse::<String>(env)? else {
+        return Ok(HashMap::new());
+    };
+    overrides
+        .split(',')
+        .map(str::trim)
+        .filter(|o| !o.is_empty())
+        .map(|o| {
+            o.split_once('=')
+                .and_then(|(svc, val)| Some((svc.trim().to_string(), val.trim().parse().ok()?)))
+                .ok_or_else(|| Error::EnvVar(env.to_string(), o.to_string()))
+        })
+        .collect()
+}
+
 fn parse_args() -> String {
     let cli_args: Vec<String> = env::args().collect();
     cli_args[1..].join(" ")
@@ -310,7 +583,20 @@ pub fn construct_config(pc: ProxyConfig) -> Result<Config, Error> {
         connection_window_size: 4 * 1024 * 1024,
         frame_size: 1024 * 1024,
 
+        pool_max_streams_per_conn: parse_default(
+            POOL_MAX_STREAMS_PER_CONNECTION,
+            DEFAULT_POOL_MAX_STREAMS_PER_CONNECTION,
+        )?,
+        pool_idle_timeout: parse_duration(POOL_IDLE_TIMEOUT)?.unwrap_or(DEFAULT_POOL_IDLE_TIMEOUT),
+        pool_keepalive_interval: parse_duration(POOL_KEEPALIVE_INTERVAL)?
+            .unwrap_or(DEFAULT_POOL_KEEPALIVE_INTERVAL),
+        pool_keepalive_timeout: parse_duration(POOL_KEEPALIVE_TIMEOUT)?
+            .unwrap_or(DEFAULT_POOL_KEEPALIVE_TIMEOUT),
+
         self_termination_deadline: DEFAULT_SELFTERM_DEADLINE,
+        termination_drain_duration: pc
+            .termination_drain_duration
+            .unwrap_or(DEFAULT_TERMINATION_DRAIN_DURATION),
 
         // admin API should only be accessible over localhost
         // todo: bind to both v4 localhost and v6
@@ -327,9 +613,15 @@ pub fn construct_config(pc: ProxyConfig) -> Result<Config, Error> {
             DEFAULT_READINESS_PORT, // There is no config for this in ProxyConfig currently
         ),
 
-        socks5_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 15080),
+        socks5_addr: parse(SOCKS5_ADDR)?
+            .unwrap_or(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 15080)),
+        socks5_credentials: parse_socks5_credentials(SOCKS5_CREDENTIALS_FILE)?,
+        http_connect_addr: parse(HTTP_CONNECT_ADDR)?
+            .unwrap_or(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 15081)),
         inbound_addr: SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 15008),
         inbound_plaintext_addr: SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 15006),
+        proxy_protocol_trusted_cidrs: parse_list(PROXY_PROTOCOL_TRUSTED_CIDRS)?,
+        inbound_single_tls_addr: SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 15003),
         outbound_addr: SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 15001),
         dns_proxy_addr,
 
@@ -368,6 +660,61 @@ pub fn construct_config(pc: ProxyConfig) -> Result<Config, Error> {
         )?,
 
         enable_original_source: parse(ENABLE_ORIG_SRC)?,
+        udp_proxy: parse_default(ENABLE_UDP_PROXY, false)?,
+        udp_idle_timeout: parse_duration(UDP_IDLE_TIMEOUT)?.unwrap_or(DEFAULT_UDP_IDLE_TIMEOUT),
+        single_tls_inbound: parse_default(ENABLE_SINGLE_TLS_INBOUND, false)?,
+        connect_retry_budget: parse_default(
+            CONNECTION_RETRY_BUDGET,
+            DEFAULT_CONNECTION_RETRY_BUDGET,
+        )?,
+        connect_timeout: parse_duration(CONNECTION_ATTEMPT_TIMEOUT)?
+            .unwrap_or(DEFAULT_CONNECTION_ATTEMPT_TIMEOUT),
+        outlier_consecutive_failures: parse_default(
+            OUTLIER_CONSECUTIVE_FAILURES,
+            DEFAULT_OUTLIER_CONSECUTIVE_FAILURES,
+        )?,
+        outlier_base_ejection_time: parse_duration(OUTLIER_BASE_EJECTION_TIME)?
+            .unwrap_or(DEFAULT_OUTLIER_BASE_EJECTION_TIME),
+        outlier_max_ejection_time: parse_duration(OUTLIER_MAX_EJECTION_TIME)?
+            .unwrap_or(DEFAULT_OUTLIER_MAX_EJECTION_TIME),
+        load_balancing_algorithm: parse_default(
+            LOAD_BALANCING_ALGORITHM,
+            LoadBalancerAlgorithm::default(),
+        )?,
+        load_balancing_overrides: parse_service_overrides(LOAD_BALANCING_OVERRIDES)?,
+        workload_bandwidth_limit: parse_default(
+            WORKLOAD_BANDWIDTH_LIMIT,
+            BandwidthLimit::default(),
+        )?,
+        service_bandwidth_limits: parse_service_overrides(SERVICE_BANDWIDTH_LIMITS)?,
+        service_max_connections: parse(SERVICE_MAX_CONNECTIONS)?,
+        service_max_connections_overrides: parse_service_overrides(
+            SERVICE_MAX_CONNECTIONS_OVERRIDES,
+        )?,
+        workload_max_connections: parse(WORKLOAD_MAX_CONNECTIONS)?,
+        connection_idle_timeout: parse_duration(CONNECTION_IDLE_TIMEOUT)?,
+        connection_max_duration: parse_duration(CONNECTION_MAX_DURATION)?,
+        connection_metrics_interval: match parse_duration(CONNECTION_METRICS_INTERVAL)? {
+            Some(d) if d.is_zero() => None,
+            d => Some(d.unwrap_or(DEFAULT_CONNECTION_METRICS_INTERVAL)),
+        },
+
+        access_log: parse_default(ACCESS_LOG, false)?,
+        access_log_format: parse_default(ACCESS_LOG_FORMAT, access_log::Format::default())?,
+        access_log_fields: parse_list(ACCESS_LOG_FIELDS)?,
+        access_log_sink: parse_default(ACCESS_LOG_SINK, access_log::Sink::default())?,
+        access_log_max_file_size: parse_default(
+            ACCESS_LOG_MAX_FILE_SIZE,
+            DEFAULT_ACCESS_LOG_MAX_FILE_SIZE,
+        )?,
+        access_log_max_files: parse_default(ACCESS_LOG_MAX_FILES, DEFAULT_ACCESS_LOG_MAX_FILES)?,
+        otlp_traces_endpoint: parse(OTLP_TRACES_ENDPOINT)?,
+        trace_sampling_ratio: parse_default(TRACE_SAMPLING_RATIO, DEFAULT_TRACE_SAMPLING_RATIO)?,
+        ext_authz_address: parse(EXT_AUTHZ_ADDRESS)?,
+        ext_authz_timeout: parse_duration(EXT_AUTHZ_TIMEOUT)?.unwrap_or(DEFAULT_EXT_AUTHZ_TIMEOUT),
+        ext_authz_fail_open: parse_default(EXT_AUTHZ_FAIL_OPEN, false)?,
+        ext_authz_cache_ttl: parse_duration(EXT_AUTHZ_CACHE_TTL)?
+            .unwrap_or(DEFAULT_EXT_AUTHZ_CACHE_TTL),
         proxy_args: parse_args(),
         dns_resolver_cfg,
         dns_resolver_opts,
@@ -391,6 +738,62 @@ fn validate_config(cfg: Config) -> Result<Config, Error> {
         )));
     }
 
+    if !cfg.socks5_addr.ip().is_loopback() && cfg.socks5_credentials.is_empty() {
+        return Err(Error::ProxyConfig(anyhow!(
+            "SOCKS5 listener on {} requires credentials to be configured",
+            cfg.socks5_addr
+        )));
+    }
+
+    if let Some(field) = cfg
+        .access_log_fields
+        .iter()
+        .find(|f| !access_log::FIELDS.contains(&f.as_str()))
+    {
+        return Err(Error::ProxyConfig(anyhow!(
+            "unknown access log field {field}, expected one of {}",
+            access_log::FIELDS.join(", ")
+        )));
+    }
+
+    if let Some(endpoint) = &cfg.otlp_traces_endpoint {
+        match endpoint.parse::<hyper::Uri>() {
+            Ok(uri) if uri.scheme() == Some(&hyper::http::uri::Scheme::HTTP) => {}
+            _ => {
+                return Err(Error::ProxyConfig(anyhow!(
+                    "OTLP traces endpoint {endpoint} must be an http:// URL"
+                )))
+            }
+        }
+    }
+
+    if let Some(address) = &cfg.ext_authz_address {
+        match address.parse::<hyper::Uri>() {
+            Ok(uri)
+                if uri.scheme() == Some(&hyper::http::uri::Scheme::HTTP)
+                    || uri.scheme() == Some(&hyper::http::uri::Scheme::HTTPS) => {}
+            _ => {
+                return Err(Error::ProxyConfig(anyhow!(
+                    "external authorization address {address} must be an http:// or https:// URL"
+                )))
+            }
+        }
+    }
+
+    if !(0.0..=1.0).contains(&cfg.trace_sampling_ratio) {
+        return Err(Error::ProxyConfig(anyhow!(
+            "trace sampling ratio {} must be between 0 and 1",
+            cfg.trace_sampling_ratio
+        )));
+    }
+
+    if !cfg.http_connect_addr.ip().is_loopback() {
+        return Err(Error::ProxyConfig(anyhow!(
+            "HTTP CONNECT listener on {} must be bound to a loopback address, as it is unauthenticated",
+            cfg.http_connect_addr
+        )));
+    }
+
     Ok(cfg)
 }
 
@@ -419,6 +822,7 @@ pub struct ProxyConfig {
     pub proxy_admin_port: Option<u16>,
     pub status_port: Option<u16>,
     pub concurrency: Option<u16>,
+    #[serde(default, deserialize_with = "duration_str::deserialize_option_duration")]
     pub termination_drain_duration: Option<Duration>,
     pub proxy_metadata: HashMap<String, String>,
 }
@@ -519,6 +923,7 @@ pub mod tests {
         let cfg = construct_config(pc).unwrap();
         assert_eq!(cfg.stats_addr.port(), 15888);
         assert_eq!(cfg.admin_addr.port(), 15099);
+        assert_eq!(cfg.termination_drain_duration, Duration::from_secs(10));
         // TODO remove prefix
         assert_eq!(cfg.proxy_metadata["FOO"], "foo");
         assert_eq!(cfg.cluster_id, "Kubernetes");
@@ -568,4 +973,32 @@ pub mod tests {
         assert_eq!(cfg.proxy_metadata["NO_PREFIX"], "no-prefix");
         assert_eq!(cfg.proxy_metadata["INCLUDE_THIS"], "foobar-env");
     }
+
+    #[test]
+    fn socks5_credentials() {
+        let creds = parse_socks5_credentials_file(
+            "creds",
+            "# user:password:identity\n\nalice:s3cret:spiffe://cluster.local/ns/jobs/sa/batch\n",
+        )
+        .unwrap();
+        assert_eq!(creds.len(), 1);
+        assert_eq!(creds["alice"].password, "s3cret");
+        assert_eq!(
+            creds["alice"].identity.to_string(),
+            "spiffe://cluster.local/ns/jobs/sa/batch"
+        );
+        assert!(parse_socks5_credentials_file("creds", "alice:s3cret\n").is_err());
+
+        // A listener reachable off-node must have credentials configured
+        let cfg = Config {
+            socks5_addr: "0.0.0.0:15080".parse().unwrap(),
+            ..construct_config(ProxyConfig::default()).unwrap()
+        };
+        assert!(validate_config(cfg.clone()).is_err());
+        assert!(validate_config(Config {
+            socks5_credentials: creds,
+            ..cfg
+        })
+        .is_ok());
+    }
 }
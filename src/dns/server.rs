@@ -157,21 +157,42 @@ impl Server {
 
 /// A DNS [Resolver] backed by the ztunnel [DemandProxyState].
 struct Store {
+    mesh: MeshResolver,
+    metrics: Arc<Metrics>,
+}
+
+impl Store {
+    fn new(
+        domain: String,
+        network: String,
+        state: DemandProxyState,
+        forwarder: Arc<dyn Forwarder>,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Self {
+            mesh: MeshResolver::new(domain, network, state, forwarder),
+            metrics,
+        }
+    }
+}
+
+/// Resolves hostnames to the services and workloads in the mesh, from the perspective of a client
+/// workload. Short names and search domains are expanded the same way for every caller, whether
+/// the lookup comes from the DNS proxy or elsewhere.
+pub struct MeshResolver {
     network: String,
     state: DemandProxyState,
     forwarder: Arc<dyn Forwarder>,
     domain: Name,
     svc_domain: Name,
-    metrics: Arc<Metrics>,
 }
 
-impl Store {
-    fn new(
+impl MeshResolver {
+    pub fn new(
         domain: String,
         network: String,
         state: DemandProxyState,
         forwarder: Arc<dyn Forwarder>,
-        metrics: Arc<Metrics>,
     ) -> Self {
         let domain = as_name(domain);
         let svc_domain = append_name(as_name("svc"), &domain);
@@ -182,10 +203,20 @@ impl Store {
             forwarder,
             domain,
             svc_domain,
-            metrics,
         }
     }
 
+    /// Resolves `hostname` for the client at `client_addr`. Returns None if the client is not a
+    /// known workload, or the hostname does not match any service or workload in the mesh.
+    pub fn resolve(&self, client_addr: SocketAddr, hostname: &str) -> Option<Vec<IpAddr>> {
+        let client = self.find_client(client_addr)?;
+        let name = Name::from_str(hostname).ok()?;
+        let server = self.find_server(&client, &name)?.server;
+        let mut addrs = self.get_addresses(&client, &server, RecordType::A);
+        addrs.extend(self.get_addresses(&client, &server, RecordType::AAAA));
+        Some(addrs)
+    }
+
     /// Find the workload for the client address.
     fn find_client(&self, client_addr: SocketAddr) -> Option<Workload> {
         let state = self.state.read();
@@ -428,7 +459,9 @@ impl Store {
 
         addrs
     }
+}
 
+impl Store {
     async fn forward(
         &self,
         client: Option<&Workload>,
@@ -459,7 +492,7 @@ impl Store {
             );
         });
 
-        match self.forwarder.forward(client, request).await {
+        match self.mesh.forwarder.forward(client, request).await {
             Ok(answer) => Ok(answer),
             Err(e) => {
                 // Increment counter for forwarding failures.
@@ -478,7 +511,7 @@ impl Store {
 impl Resolver for Store {
     async fn lookup(&self, request: &Request) -> Result<Answer, LookupError> {
         // Find the client workload.
-        let client = match self.find_client(to_canonical(request.src())) {
+        let client = match self.mesh.find_client(to_canonical(request.src())) {
             None => {
                 // TODO(nmittler): make forwarding optional here.
                 // Increment request counter.
@@ -501,7 +534,7 @@ impl Resolver for Store {
 
         // Find the service for the requested host.
         let requested_name = Name::from(request.query().name().clone());
-        let Some(service_match) = self.find_server(&client, &requested_name) else {
+        let Some(service_match) = self.mesh.find_server(&client, &requested_name) else {
             // Unknown host. Forward to the upstream resolver.
             return self.forward(Some(&client), request).await;
         };
@@ -514,7 +547,9 @@ impl Resolver for Store {
         });
 
         // Get the addresses for the service.
-        let addresses = self.get_addresses(&client, &service_match.server, record_type);
+        let addresses = self
+            .mesh
+            .get_addresses(&client, &service_match.server, record_type);
 
         // From this point on, we are the authority for the response.
         let is_authoritative = true;
@@ -858,17 +893,19 @@ mod tests {
             let state = state();
             let forwarder = forwarder();
             let store = Store {
-                domain: as_name("cluster.local"),
-                svc_domain: as_name("svc.cluster.local"),
-                network: NW1.to_string(),
-                state,
-                forwarder,
+                mesh: MeshResolver {
+                    domain: as_name("cluster.local"),
+                    svc_domain: as_name("svc.cluster.local"),
+                    network: NW1.to_string(),
+                    state,
+                    forwarder,
+                },
                 metrics: test_metrics(),
             };
 
             let namespaced_domain = n(format!("{}.svc.cluster.local", c.client_namespace));
 
-            let actual = store.to_kube_fqdns(&n(c.host), &namespaced_domain);
+            let actual = store.mesh.to_kube_fqdns(&n(c.host), &namespaced_domain);
             assert_eq!(c.expected, actual, "requested host: {}", c.host);
         }
     }
@@ -1187,11 +1224,13 @@ mod tests {
         let state = state();
         let forwarder = forwarder();
         let store = Store {
-            domain: as_name("cluster.local"),
-            svc_domain: as_name("svc.cluster.local"),
-            network: NW1.to_string(),
-            state,
-            forwarder,
+            mesh: MeshResolver {
+                domain: as_name("cluster.local"),
+                svc_domain: as_name("svc.cluster.local"),
+                network: NW1.to_string(),
+                state,
+                forwarder,
+            },
             metrics: test_metrics(),
         };
 
@@ -1209,6 +1248,37 @@ mod tests {
         }
     }
 
+    #[test]
+    fn mesh_resolver() {
+        let resolver = MeshResolver::new(
+            "cluster.local".to_string(),
+            NW1.to_string(),
+            state(),
+            forwarder(),
+        );
+        let client = SocketAddr::new(local_ips()[0], 0);
+
+        assert_eq!(
+            resolver.resolve(client, "productpage"),
+            Some(vec![ip("9.9.9.9")])
+        );
+        assert_eq!(
+            resolver.resolve(client, "productpage.ns1.svc.cluster.local"),
+            Some(vec![ip("9.9.9.9")])
+        );
+        assert_eq!(
+            resolver.resolve(client, "dual.localhost"),
+            Some(vec![ip("2.2.2.2"), ip("2001:db8:0:0:0:ff00:42:8329")])
+        );
+        // Not a mesh host, so left to the system resolver.
+        assert_eq!(resolver.resolve(client, "www.bing.com"), None);
+        // Unknown clients cannot be resolved for.
+        assert_eq!(
+            resolver.resolve(SocketAddr::new(ip("5.5.5.5"), 0), "productpage"),
+            None
+        );
+    }
+
     #[tokio::test]
     async fn system_forwarder() {
         let _guard = subscribe();
@@ -1283,11 +1353,13 @@ mod tests {
         let state = new_proxy_state(&fake_wls, &[], &[]);
         let forwarder = forwarder();
         let store = Store {
-            network: NW1.to_string(),
-            state,
-            forwarder,
-            domain: n("cluster.local"),
-            svc_domain: n("svc.cluster.local"),
+            mesh: MeshResolver {
+                network: NW1.to_string(),
+                state,
+                forwarder,
+                domain: n("cluster.local"),
+                svc_domain: n("svc.cluster.local"),
+            },
             metrics: test_metrics(),
         };
 
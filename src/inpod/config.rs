@@ -97,6 +97,15 @@ impl crate::proxy::SocketFactory for InPodSocketFactory {
         std_sock.set_nonblocking(true)?;
         tokio::net::UdpSocket::from_std(std_sock)
     }
+
+    fn udp_bind_transparent(
+        &self,
+        addr: std::net::SocketAddr,
+        peer: std::net::SocketAddr,
+    ) -> std::io::Result<tokio::net::UdpSocket> {
+        let std_sock = self.configure(|| crate::socket::udp_bind_transparent(addr, peer))?;
+        tokio::net::UdpSocket::from_std(std_sock)
+    }
 }
 
 // Same as socket factory, but sets SO_REUSEPORT
@@ -166,6 +175,14 @@ impl crate::proxy::SocketFactory for InPodSocketPortReuseFactory {
         std_sock.set_nonblocking(true)?;
         tokio::net::UdpSocket::from_std(std_sock)
     }
+
+    fn udp_bind_transparent(
+        &self,
+        addr: std::net::SocketAddr,
+        peer: std::net::SocketAddr,
+    ) -> std::io::Result<tokio::net::UdpSocket> {
+        self.sf.udp_bind_transparent(addr, peer)
+    }
 }
 
 #[cfg(test)]
@@ -82,6 +82,8 @@ impl Default for Fixture {
             Some(metrics),
             None,
             drain_rx.clone(),
+            Default::default(),
+            Default::default(),
         )
         .unwrap();
         Fixture {
@@ -22,19 +22,26 @@ use drain::Watch;
 
 use rand::Rng;
 
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::{TcpListener, TcpSocket, TcpStream};
 use tokio::time::timeout;
 use tracing::{error, trace, warn, Instrument};
 
-use inbound::Inbound;
+use inbound::{Inbound, InboundMode};
 pub use metrics::*;
 
 use crate::identity::{Identity, SecretManager};
 
-use crate::proxy::connection_manager::{ConnectionManager, PolicyWatcher};
+use crate::proxy::bandwidth::{BandwidthLimiter, ShapedIo};
+use crate::proxy::connection_manager::{
+    ConnectionGuard, ConnectionKey, ConnectionManager, PolicyWatcher,
+};
+use crate::proxy::http_connect::HttpConnect;
 use crate::proxy::inbound_passthrough::InboundPassthrough;
+use crate::proxy::limits::{Activity, ConnectionLimits, TrackedIo};
 use crate::proxy::outbound::Outbound;
 use crate::proxy::socks5::Socks5;
+use crate::proxy::udp::UdpProxy;
 use crate::rbac::Connection;
 use crate::state::service::{endpoint_uid, Service, ServiceDescription};
 use crate::state::workload::address::Address;
@@ -42,14 +49,22 @@ use crate::state::workload::{network_addr, GatewayAddress, Workload};
 use crate::state::{DemandProxyState, WorkloadInfo};
 use crate::{config, identity, socket, tls};
 
+pub mod access_log;
+pub mod bandwidth;
 pub mod connection_manager;
+mod http_connect;
 mod inbound;
 mod inbound_passthrough;
+pub mod limits;
 #[allow(non_camel_case_types)]
 pub mod metrics;
+pub mod otel;
 mod outbound;
-mod pool;
+pub mod pool;
+pub mod proxy_protocol;
+mod resolver;
 mod socks5;
+mod udp;
 mod util;
 
 pub trait SocketFactory {
@@ -60,6 +75,13 @@ pub trait SocketFactory {
     fn tcp_bind(&self, addr: SocketAddr) -> std::io::Result<TcpListener>;
 
     fn udp_bind(&self, addr: SocketAddr) -> std::io::Result<tokio::net::UdpSocket>;
+
+    /// Binds a transparent UDP socket to `addr`, connected to `peer`. See socket::udp_bind_transparent.
+    fn udp_bind_transparent(
+        &self,
+        addr: SocketAddr,
+        peer: SocketAddr,
+    ) -> std::io::Result<tokio::net::UdpSocket>;
 }
 
 #[derive(Clone, Copy, Default)]
@@ -85,13 +107,25 @@ impl SocketFactory for DefaultSocketFactory {
         std_sock.set_nonblocking(true)?;
         tokio::net::UdpSocket::from_std(std_sock)
     }
+
+    fn udp_bind_transparent(
+        &self,
+        addr: SocketAddr,
+        peer: SocketAddr,
+    ) -> std::io::Result<tokio::net::UdpSocket> {
+        tokio::net::UdpSocket::from_std(socket::udp_bind_transparent(addr, peer)?)
+    }
 }
 
 pub struct Proxy {
     inbound: Inbound,
+    inbound_single_tls: Option<Inbound>,
     inbound_passthrough: InboundPassthrough,
     outbound: Outbound,
     socks5: Socks5,
+    http_connect: HttpConnect,
+    udp_outbound: Option<UdpProxy>,
+    udp_inbound_passthrough: Option<UdpProxy>,
     policy_watcher: PolicyWatcher,
 }
 
@@ -106,9 +140,13 @@ pub(super) struct ProxyInputs {
     pool: pool::Pool,
     socket_factory: Arc<dyn SocketFactory + Send + Sync>,
     proxy_workload_info: Option<Arc<WorkloadInfo>>,
+    bandwidth: BandwidthLimiter,
+    access_log: access_log::AccessLog,
+    trace_exporter: otel::Exporter,
 }
 
 impl ProxyInputs {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         cfg: config::Config,
         cert_manager: Arc<SecretManager>,
@@ -117,17 +155,25 @@ impl ProxyInputs {
         metrics: Arc<Metrics>,
         socket_factory: Arc<dyn SocketFactory + Send + Sync>,
         proxy_workload_info: Option<WorkloadInfo>,
+        pools: &pool::PoolRegistry,
+        access_log: access_log::AccessLog,
+        trace_exporter: otel::Exporter,
     ) -> Self {
+        let pool = pool::Pool::new(&cfg);
+        pools.register(&pool);
         Self {
+            bandwidth: BandwidthLimiter::new(&cfg, metrics.clone()),
             cfg,
             state,
             cert_manager,
             metrics,
             connection_manager,
-            pool: pool::Pool::new(),
+            pool,
             hbone_port: 0,
             socket_factory,
             proxy_workload_info: proxy_workload_info.map(Arc::new),
+            access_log,
+            trace_exporter,
         }
     }
 }
@@ -139,48 +185,84 @@ impl Proxy {
         cert_manager: Arc<SecretManager>,
         metrics: Metrics,
         drain: Watch,
+        pools: &pool::PoolRegistry,
     ) -> Result<Proxy, Error> {
-        let metrics = Arc::new(metrics);
-        let pi = ProxyInputs {
+        let pi = ProxyInputs::new(
             cfg,
-            state,
             cert_manager,
-            connection_manager: ConnectionManager::default(),
-            metrics,
-            pool: pool::Pool::new(),
-            hbone_port: 0,
-            socket_factory: Arc::new(DefaultSocketFactory),
-            proxy_workload_info: None,
-        };
+            ConnectionManager::default(),
+            state,
+            Arc::new(metrics),
+            Arc::new(DefaultSocketFactory),
+            None,
+            pools,
+            access_log::AccessLog::default(),
+            otel::Exporter::default(),
+        );
         Self::from_inputs(pi, drain).await
     }
     pub(super) async fn from_inputs(mut pi: ProxyInputs, drain: Watch) -> Result<Self, Error> {
         // We setup all the listeners first so we can capture any errors that should block startup
-        let inbound = Inbound::new(pi.clone(), drain.clone()).await?;
+        let inbound = Inbound::new(pi.clone(), drain.clone(), InboundMode::Mtls).await?;
         pi.hbone_port = inbound.address().port();
+        let inbound_single_tls = if pi.cfg.single_tls_inbound {
+            Some(Inbound::new(pi.clone(), drain.clone(), InboundMode::SingleTls).await?)
+        } else {
+            None
+        };
 
         let inbound_passthrough = InboundPassthrough::new(pi.clone(), drain.clone()).await?;
         let outbound = Outbound::new(pi.clone(), drain.clone()).await?;
         let socks5 = Socks5::new(pi.clone(), drain.clone()).await?;
+        let http_connect = HttpConnect::new(pi.clone(), drain.clone()).await?;
+        let (udp_outbound, udp_inbound_passthrough) = if pi.cfg.udp_proxy {
+            (
+                Some(UdpProxy::new(pi.clone(), drain.clone(), udp::Direction::Outbound).await?),
+                Some(
+                    UdpProxy::new(
+                        pi.clone(),
+                        drain.clone(),
+                        udp::Direction::InboundPassthrough,
+                    )
+                    .await?,
+                ),
+            )
+        } else {
+            (None, None)
+        };
         let policy_watcher = PolicyWatcher::new(pi.state, drain, pi.connection_manager);
 
         Ok(Proxy {
             inbound,
+            inbound_single_tls,
             inbound_passthrough,
             outbound,
             socks5,
+            http_connect,
+            udp_outbound,
+            udp_inbound_passthrough,
             policy_watcher,
         })
     }
 
     pub async fn run(self) {
-        let tasks = vec![
+        let mut tasks = vec![
             tokio::spawn(self.inbound_passthrough.run().in_current_span()),
             tokio::spawn(self.inbound.run().in_current_span()),
             tokio::spawn(self.outbound.run().in_current_span()),
             tokio::spawn(self.socks5.run().in_current_span()),
+            tokio::spawn(self.http_connect.run().in_current_span()),
             tokio::spawn(self.policy_watcher.run().in_current_span()),
         ];
+        if let Some(inbound) = self.inbound_single_tls {
+            tasks.push(tokio::spawn(inbound.run().in_current_span()));
+        }
+        for udp in [self.udp_outbound, self.udp_inbound_passthrough]
+            .into_iter()
+            .flatten()
+        {
+            tasks.push(tokio::spawn(udp.run().in_current_span()));
+        }
 
         futures::future::join_all(tasks).await;
     }
@@ -190,6 +272,7 @@ impl Proxy {
             outbound: self.outbound.address(),
             inbound: self.inbound.address(),
             socks5: self.socks5.address(),
+            http_connect: self.http_connect.address(),
         }
     }
 }
@@ -199,6 +282,7 @@ pub struct Addresses {
     pub outbound: SocketAddr,
     pub inbound: SocketAddr,
     pub socks5: SocketAddr,
+    pub http_connect: SocketAddr,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -218,12 +302,6 @@ pub enum Error {
     #[error("connection closed due to policy rejection")]
     AuthorizationPolicyRejection,
 
-    #[error("pool is already connecting")]
-    PoolAlreadyConnecting,
-
-    #[error("pool: {0}")]
-    Pool(#[from] hyper_util::client::legacy::pool::Error),
-
     #[error("{0}")]
     Generic(Box<dyn std::error::Error + Send + Sync>),
 
@@ -254,9 +332,24 @@ pub enum Error {
     #[error("unknown source: {0}")]
     UnknownSource(IpAddr),
 
+    #[error("invalid PROXY protocol header: {0}")]
+    ProxyProtocol(String),
+
     #[error("unknown waypoint: {0}")]
     UnknownWaypoint(String),
 
+    #[error("connection limit reached for {0}")]
+    CircuitBreakerOpen(ConnectionKey),
+
+    #[error("connection idle for longer than {0:?}")]
+    IdleTimeout(Duration),
+
+    #[error("connection open for longer than the maximum of {0:?}")]
+    MaxDurationExceeded(Duration),
+
+    #[error("unknown network gateway: {0}")]
+    UnknownNetworkGateway(String),
+
     #[error("unknown destination: {0}")]
     UnknownDestination(IpAddr),
 
@@ -287,44 +380,66 @@ pub enum Error {
 // TLS record size max is 16k. But we also have a H2 frame header, so leave a bit of room for that.
 const HBONE_BUFFER_SIZE: usize = 16_384 - 64;
 
+impl Error {
+    /// termination_reason returns why ztunnel closed the connection, if it was closed for hitting one of
+    /// its limits rather than by either peer.
+    pub fn termination_reason(&self) -> Option<TerminationReason> {
+        match self {
+            Error::IdleTimeout(_) => Some(TerminationReason::idle_timeout),
+            Error::MaxDurationExceeded(_) => Some(TerminationReason::max_duration),
+            _ => None,
+        }
+    }
+}
+
 pub async fn copy_hbone(
-    upgraded: &mut hyper::upgrade::Upgraded,
+    upgraded: &mut (impl AsyncRead + AsyncWrite + Unpin),
     stream: &mut TcpStream,
+    limits: &ConnectionLimits,
 ) -> Result<(u64, u64), Error> {
     use tokio::io::AsyncWriteExt;
-    let (mut ri, mut wi) = tokio::io::split(hyper_util::rt::TokioIo::new(upgraded));
-    let (mut ro, mut wo) = stream.split();
-
-    let (mut sent, mut received): (u64, u64) = (0, 0);
+    let activity = Activity::default();
+    let (mut ri, mut wi) = tokio::io::split(upgraded);
+    let (ro, wo) = stream.split();
+    // All data passes through the TCP side, so tracking and shaping it alone is enough.
+    let shaper = limits.bandwidth.as_ref();
+    let mut ro = ShapedIo::new(TrackedIo::new(ro, &activity), shaper);
+    let mut wo = ShapedIo::new(TrackedIo::new(wo, &activity), shaper);
+
+    let copy = async {
+        let (mut sent, mut received): (u64, u64) = (0, 0);
+
+        let client_to_server = async {
+            let mut ri = tokio::io::BufReader::with_capacity(HBONE_BUFFER_SIZE, &mut ri);
+            let res = tokio::io::copy_buf(&mut ri, &mut wo).await;
+            trace!(?res, "hbone -> tcp");
+            received = res?;
+            wo.shutdown().await
+        };
 
-    let client_to_server = async {
-        let mut ri = tokio::io::BufReader::with_capacity(HBONE_BUFFER_SIZE, &mut ri);
-        let res = tokio::io::copy_buf(&mut ri, &mut wo).await;
-        trace!(?res, "hbone -> tcp");
-        received = res?;
-        wo.shutdown().await
-    };
+        let server_to_client = async {
+            let mut ro = tokio::io::BufReader::with_capacity(HBONE_BUFFER_SIZE, &mut ro);
+            let res = tokio::io::copy_buf(&mut ro, &mut wi).await;
+            trace!(?res, "tcp -> hbone");
+            sent = res?;
+            wi.shutdown().await
+        };
 
-    let server_to_client = async {
-        let mut ro = tokio::io::BufReader::with_capacity(HBONE_BUFFER_SIZE, &mut ro);
-        let res = tokio::io::copy_buf(&mut ro, &mut wi).await;
-        trace!(?res, "tcp -> hbone");
-        sent = res?;
-        wi.shutdown().await
+        tokio::try_join!(client_to_server, server_to_client)?;
+        Ok::<_, Error>((sent, received))
     };
-
-    tokio::try_join!(client_to_server, server_to_client)?;
+    let (sent, received) = limits.enforce(&activity, copy).await?;
 
     trace!(sent, recv = received, "copy hbone complete");
     Ok((sent, received))
 }
 
-const PROXY_PROTOCOL_AUTHORITY_TLV: u8 = 0xD0;
-
+/// Writes a PROXY protocol v2 header, carrying the connection attributes in `tlvs`. See
+/// `proxy_protocol` for the TLVs used, and how to decode them.
 pub async fn write_proxy_protocol<T>(
     stream: &mut TcpStream,
     addresses: T,
-    src_id: Option<Identity>,
+    tlvs: &proxy_protocol::ProxyTlvs,
 ) -> io::Result<()>
 where
     T: Into<ppp::v2::Addresses>,
@@ -332,14 +447,9 @@ where
     use ppp::v2::{Builder, Command, Protocol, Version};
     use tokio::io::AsyncWriteExt;
 
-    let mut builder =
+    let builder =
         Builder::with_addresses(Version::Two | Command::Proxy, Protocol::Stream, addresses);
-
-    if let Some(id) = src_id {
-        builder = builder.write_tlv(PROXY_PROTOCOL_AUTHORITY_TLV, id.to_string().as_bytes())?;
-    }
-
-    let header = builder.build()?;
+    let header = tlvs.write(builder)?.build()?;
     stream.write_all(&header).await
 }
 
@@ -496,14 +606,71 @@ pub async fn freebind_connect(
 pub async fn relay(
     downstream: &mut TcpStream,
     upstream: &mut TcpStream,
+    limits: &ConnectionLimits,
 ) -> Result<(u64, u64), Error> {
-    match socket::relay(downstream, upstream).await {
-        Ok(transferred) => {
-            trace!(sent = transferred.0, recv = transferred.1, "relay complete");
-            Ok(transferred)
+    let activity = Activity::default();
+    let copy = async {
+        // Zero-copy relaying happens entirely in the kernel. To observe activity for idle timeouts,
+        // bandwidth limits and live metrics, we still splice, but are called back after each chunk.
+        if limits.idle_timeout.is_some()
+            || limits.bandwidth.is_some()
+            || limits.live_metrics().is_some()
+        {
+            socket::relay_paced(downstream, upstream, |from_downstream, n| {
+                let shaper = limits.bandwidth.as_ref();
+                if from_downstream {
+                    activity.record(n, 0);
+                    shaper.map_or(Duration::ZERO, |s| s.on_read(n))
+                } else {
+                    activity.record(0, n);
+                    shaper.map_or(Duration::ZERO, |s| s.on_write(n))
+                }
+            })
+            .await
+            .map_err(Error::Io)
+        } else {
+            socket::relay(downstream, upstream).await.map_err(Error::Io)
+        }
+    };
+    let transferred = limits.enforce(&activity, copy).await?;
+    trace!(sent = transferred.0, recv = transferred.1, "relay complete");
+    Ok(transferred)
+}
+
+// check_circuit_breakers counts a connection against the concurrent connection limits for its
+// destination service and source workload, rejecting it if either is already reached.
+// The connection is counted until the returned guard is dropped.
+fn check_circuit_breakers(
+    pi: &ProxyInputs,
+    conn: &ConnectionOpen,
+) -> Result<ConnectionGuard, Error> {
+    let mut limits = Vec::new();
+    if let Some(svc) = &conn.destination_service {
+        let limit = pi
+            .cfg
+            .service_max_connections_overrides
+            .get(&svc.hostname)
+            .copied()
+            .or(pi.cfg.service_max_connections);
+        if limit.is_some() {
+            limits.push((
+                ConnectionKey::Service(conn.reporter, svc.hostname.clone()),
+                limit,
+            ));
         }
-        Err(e) => Err(Error::Io(e)),
     }
+    if let (Some(wl), Some(limit)) = (&conn.source, pi.cfg.workload_max_connections) {
+        limits.push((
+            ConnectionKey::SourceWorkload(conn.reporter, wl.uid.clone()),
+            Some(limit),
+        ));
+    }
+    pi.connection_manager
+        .try_track_limited(limits)
+        .map_err(|key| {
+            metrics::record_circuit_breaker_rejection(&pi.metrics, conn, &key);
+            Error::CircuitBreakerOpen(key)
+        })
 }
 
 // guess_inbound_service selects an upstream service for inbound metrics.
@@ -610,6 +777,35 @@ mod tests {
         },
     };
     use std::{collections::HashMap, net::Ipv4Addr, sync::RwLock};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    async fn tcp_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        (client, server)
+    }
+
+    #[tokio::test]
+    async fn relay_idle_timeout() {
+        let (mut client, mut downstream) = tcp_pair().await;
+        let (mut upstream, mut server) = tcp_pair().await;
+        let limits = ConnectionLimits {
+            idle_timeout: Some(Duration::from_millis(100)),
+            ..Default::default()
+        };
+        let relay =
+            tokio::spawn(async move { relay(&mut downstream, &mut upstream, &limits).await });
+        client.write_all(b"ping").await.unwrap();
+        let mut buf = [0; 4];
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        // Both peers stay connected, but nothing is sent anymore
+        let res = relay.await.unwrap();
+        assert!(matches!(res, Err(Error::IdleTimeout(_))), "{res:?}");
+    }
 
     #[tokio::test]
     async fn check_gateway() {
@@ -695,6 +891,7 @@ mod tests {
             native_tunnel: false,
             application_tunnel: None,
             locality: Default::default(),
+            bandwidth_limit: Default::default(),
         }
     }
 
@@ -724,6 +921,7 @@ mod tests {
             native_tunnel: false,
             application_tunnel: None,
             locality: Default::default(),
+            bandwidth_limit: Default::default(),
         }
     }
 
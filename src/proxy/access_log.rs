@@ -0,0 +1,541 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Structured access logs, with one entry per proxied connection.
+//!
+//! Unlike the `access` tracing target, entries are written in a stable format to a dedicated sink,
+//! and logging can be turned on and off at runtime through the admin server.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::SystemTime;
+
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::body::Incoming;
+use hyper::{Request, Response};
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::registry::Registry;
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::config::Config;
+use crate::hyper_util::plaintext_response;
+use crate::identity::Identity;
+
+/// All fields of an entry. In the key=value format, fields are written in this order.
+pub const FIELDS: &[&str] = &[
+    "start_time",
+    "direction",
+    "src.addr",
+    "src.workload",
+    "src.namespace",
+    "src.identity",
+    "dst.addr",
+    "dst.hbone_addr",
+    "dst.service",
+    "dst.workload",
+    "dst.namespace",
+    "dst.identity",
+    "bytes_sent",
+    "bytes_recv",
+    "duration_ms",
+    "termination_reason",
+    "policy",
+    "audit",
+    "error",
+];
+
+// Entries waiting to be written. Beyond this, entries are dropped rather than blocking the proxy.
+const QUEUE_SIZE: usize = 4096;
+// Syslog priority: facility local0, severity informational
+const SYSLOG_PRIORITY: u8 = 134;
+
+#[derive(serde::Serialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Json,
+    /// Space separated `key=value` pairs.
+    KeyValue,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Format::Json),
+            "kv" => Ok(Format::KeyValue),
+            _ => Err(format!(
+                "unknown access log format {s}, expected json or kv"
+            )),
+        }
+    }
+}
+
+/// Where entries are written to.
+#[derive(serde::Serialize, Default, Clone, Debug, PartialEq, Eq)]
+pub enum Sink {
+    #[default]
+    Stdout,
+    /// A file, which is rotated once it reaches the configured size.
+    File(PathBuf),
+    /// A syslog server listening on UDP.
+    SyslogUdp(SocketAddr),
+    /// A local syslog socket, such as `/dev/log`.
+    SyslogUnix(PathBuf),
+}
+
+impl FromStr for Sink {
+    type Err = String;
+
+    /// Parses `stdout`, `file:<path>`, `udp:<address>` or `unix:<path>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "stdout" {
+            return Ok(Sink::Stdout);
+        }
+        match s.split_once(':') {
+            Some(("file", path)) if !path.is_empty() => Ok(Sink::File(path.into())),
+            Some(("unix", path)) if !path.is_empty() => Ok(Sink::SyslogUnix(path.into())),
+            Some(("udp", addr)) => addr
+                .parse()
+                .map(Sink::SyslogUdp)
+                .map_err(|_| format!("invalid syslog address {addr}")),
+            _ => Err(format!(
+                "unknown access log sink {s}, expected stdout, file:<path>, udp:<address> or unix:<path>"
+            )),
+        }
+    }
+}
+
+/// Entry describes a single connection. Fields which are not known are omitted from the log.
+#[derive(Debug, Default)]
+pub struct Entry {
+    pub start_time: Option<SystemTime>,
+    pub direction: &'static str,
+    pub src_addr: Option<SocketAddr>,
+    pub src_workload: Option<String>,
+    pub src_namespace: Option<String>,
+    pub src_identity: Option<Identity>,
+    pub dst_addr: Option<SocketAddr>,
+    pub dst_hbone_addr: Option<SocketAddr>,
+    pub dst_service: Option<String>,
+    pub dst_workload: Option<String>,
+    pub dst_namespace: Option<String>,
+    pub dst_identity: Option<Identity>,
+    pub bytes_sent: Option<u64>,
+    pub bytes_recv: Option<u64>,
+    pub duration_ms: Option<u64>,
+    pub termination_reason: Option<String>,
+    /// The authorization policy decision, if policy was evaluated.
+    pub policy: Option<&'static str>,
+    /// The keys of the AUDIT authorization policies the connection matched.
+    pub audit_policies: Vec<String>,
+    pub error: Option<String>,
+}
+
+impl Entry {
+    fn value(&self, field: &str) -> Option<Value> {
+        fn string(v: &Option<impl ToString>) -> Option<Value> {
+            v.as_ref().map(|v| Value::String(v.to_string()))
+        }
+        match field {
+            "start_time" => self.start_time.map(|t| {
+                let t: chrono::DateTime<chrono::Utc> = t.into();
+                Value::String(t.to_rfc3339_opts(chrono::SecondsFormat::Millis, true))
+            }),
+            "direction" => Some(Value::String(self.direction.to_string())),
+            "src.addr" => string(&self.src_addr),
+            "src.workload" => string(&self.src_workload),
+            "src.namespace" => string(&self.src_namespace),
+            "src.identity" => string(&self.src_identity),
+            "dst.addr" => string(&self.dst_addr),
+            "dst.hbone_addr" => string(&self.dst_hbone_addr),
+            "dst.service" => string(&self.dst_service),
+            "dst.workload" => string(&self.dst_workload),
+            "dst.namespace" => string(&self.dst_namespace),
+            "dst.identity" => string(&self.dst_identity),
+            "bytes_sent" => self.bytes_sent.map(Value::from),
+            "bytes_recv" => self.bytes_recv.map(Value::from),
+            "duration_ms" => self.duration_ms.map(Value::from),
+            "termination_reason" => string(&self.termination_reason),
+            "policy" => string(&self.policy),
+            "audit" => (!self.audit_policies.is_empty())
+                .then(|| Value::String(self.audit_policies.join(","))),
+            "error" => string(&self.error),
+            _ => None,
+        }
+    }
+
+    fn format(&self, format: Format, fields: &[&'static str]) -> String {
+        let values = fields.iter().filter_map(|f| self.value(f).map(|v| (*f, v)));
+        match format {
+            Format::Json => {
+                let map: serde_json::Map<String, Value> =
+                    values.map(|(f, v)| (f.to_string(), v)).collect();
+                Value::Object(map).to_string()
+            }
+            Format::KeyValue => {
+                let mut line = String::new();
+                for (f, v) in values {
+                    if !line.is_empty() {
+                        line.push(' ');
+                    }
+                    match v {
+                        // Quote strings which would otherwise be ambiguous
+                        Value::String(s)
+                            if s.is_empty()
+                                || s.contains(|c: char| {
+                                    c.is_whitespace() || c == '"' || c == '='
+                                }) =>
+                        {
+                            let _ = write!(line, "{f}={}", Value::String(s));
+                        }
+                        Value::String(s) => {
+                            let _ = write!(line, "{f}={s}");
+                        }
+                        v => {
+                            let _ = write!(line, "{f}={v}");
+                        }
+                    }
+                }
+                line
+            }
+        }
+    }
+}
+
+/// AccessLog writes entries to the configured sink. It is cheap to clone; clones share the sink
+/// and whether logging is enabled. The default value has no sink, and never logs.
+#[derive(Clone, Default)]
+pub struct AccessLog(Option<Arc<Inner>>);
+
+struct Inner {
+    enabled: AtomicBool,
+    format: Format,
+    fields: Vec<&'static str>,
+    tx: mpsc::SyncSender<String>,
+    dropped: Counter,
+}
+
+impl AccessLog {
+    /// Sets up access logging from `cfg`, starting the thread that writes to the sink.
+    pub fn new(cfg: &Config, registry: &mut Registry) -> io::Result<AccessLog> {
+        let mut writer = Writer::new(cfg)?;
+        let (tx, rx) = mpsc::sync_channel::<String>(QUEUE_SIZE);
+        std::thread::Builder::new()
+            .name("access-log".to_string())
+            .spawn(move || {
+                for line in rx {
+                    if let Err(e) = writer.write(&line) {
+                        warn!("failed to write access log: {e}");
+                    }
+                }
+            })?;
+        let fields = if cfg.access_log_fields.is_empty() {
+            FIELDS.to_vec()
+        } else {
+            // Keep the canonical order, regardless of how the fields were listed
+            FIELDS
+                .iter()
+                .copied()
+                .filter(|f| cfg.access_log_fields.iter().any(|c| c == f))
+                .collect()
+        };
+        let dropped = Counter::default();
+        registry.register(
+            "access_log_entries_dropped",
+            "The total number of access log entries dropped because the queue was full (unstable)",
+            dropped.clone(),
+        );
+        Ok(AccessLog(Some(Arc::new(Inner {
+            enabled: AtomicBool::new(cfg.access_log),
+            format: cfg.access_log_format,
+            fields,
+            tx,
+            dropped,
+        }))))
+    }
+
+    /// Returns whether access logging is set up and currently enabled.
+    pub fn enabled(&self) -> bool {
+        self.0
+            .as_ref()
+            .is_some_and(|l| l.enabled.load(Ordering::Relaxed))
+    }
+
+    /// Turns access logging on or off. This has no effect if it has not been set up.
+    pub fn set_enabled(&self, enabled: bool) {
+        if let Some(l) = &self.0 {
+            l.enabled.store(enabled, Ordering::Relaxed);
+        }
+    }
+
+    /// Writes an entry, if access logging is enabled. `entry` is only called if so.
+    pub fn log(&self, entry: impl FnOnce() -> Entry) {
+        let Some(l) = &self.0 else { return };
+        if !l.enabled.load(Ordering::Relaxed) {
+            return;
+        }
+        let line = entry().format(l.format, &l.fields);
+        if l.tx.try_send(line).is_err() {
+            l.dropped.inc();
+        }
+    }
+}
+
+static HELP_STRING: &str = "
+usage: POST /accesslog\t\t\t\t(To show whether access logs are enabled)
+usage: POST /accesslog?enabled=<true|false>\t(To enable or disable access logs)
+";
+
+impl crate::admin::AdminHandler for AccessLog {
+    fn path(&self) -> &'static str {
+        "/accesslog"
+    }
+
+    fn description(&self) -> &'static str {
+        "query/toggle connection access logs"
+    }
+
+    fn handle(
+        &self,
+        req: Request<Incoming>,
+    ) -> Pin<Box<dyn futures_util::Future<Output = Response<Full<Bytes>>> + Sync + Send>> {
+        Box::pin(std::future::ready(handle_admin(self, req)))
+    }
+}
+
+fn handle_admin(log: &AccessLog, req: Request<Incoming>) -> Response<Full<Bytes>> {
+    if *req.method() != hyper::Method::POST {
+        return plaintext_response(
+            hyper::StatusCode::METHOD_NOT_ALLOWED,
+            format!("Invalid HTTP method\n {HELP_STRING}"),
+        );
+    }
+    let qp: HashMap<String, String> = req
+        .uri()
+        .query()
+        .map(|v| {
+            url::form_urlencoded::parse(v.as_bytes())
+                .into_owned()
+                .collect()
+        })
+        .unwrap_or_default();
+    if let Some(enabled) = qp.get("enabled") {
+        let Ok(enabled) = enabled.parse::<bool>() else {
+            return plaintext_response(
+                hyper::StatusCode::BAD_REQUEST,
+                format!("Invalid value provided: {enabled}\n{HELP_STRING}"),
+            );
+        };
+        log.set_enabled(enabled);
+        info!(enabled, "access logs toggled");
+    }
+    plaintext_response(
+        hyper::StatusCode::OK,
+        format!(
+            "access logs are {}\n",
+            if log.enabled() { "enabled" } else { "disabled" }
+        ),
+    )
+}
+
+enum Writer {
+    Stdout,
+    File(RotatingFile),
+    SyslogUdp(UdpSocket, SocketAddr),
+    SyslogUnix(UnixDatagram, PathBuf),
+}
+
+impl Writer {
+    fn new(cfg: &Config) -> io::Result<Writer> {
+        Ok(match &cfg.access_log_sink {
+            Sink::Stdout => Writer::Stdout,
+            Sink::File(path) => Writer::File(RotatingFile::open(
+                path,
+                cfg.access_log_max_file_size,
+                cfg.access_log_max_files,
+            )?),
+            Sink::SyslogUdp(addr) => {
+                let bind: SocketAddr = if addr.is_ipv4() {
+                    (Ipv4Addr::UNSPECIFIED, 0).into()
+                } else {
+                    (Ipv6Addr::UNSPECIFIED, 0).into()
+                };
+                Writer::SyslogUdp(UdpSocket::bind(bind)?, *addr)
+            }
+            Sink::SyslogUnix(path) => Writer::SyslogUnix(UnixDatagram::unbound()?, path.clone()),
+        })
+    }
+
+    fn write(&mut self, line: &str) -> io::Result<()> {
+        match self {
+            Writer::Stdout => writeln!(io::stdout().lock(), "{line}"),
+            Writer::File(f) => f.write_line(line),
+            Writer::SyslogUdp(socket, addr) => {
+                socket.send_to(syslog(line).as_bytes(), *addr).map(drop)
+            }
+            Writer::SyslogUnix(socket, path) => {
+                socket.send_to(syslog(line).as_bytes(), path).map(drop)
+            }
+        }
+    }
+}
+
+// Frames a line as an RFC 5424 syslog message.
+fn syslog(line: &str) -> String {
+    let now: chrono::DateTime<chrono::Utc> = SystemTime::now().into();
+    let hostname = std::env::var("HOSTNAME").unwrap_or_else(|_| "-".to_string());
+    format!(
+        "<{SYSLOG_PRIORITY}>1 {} {hostname} ztunnel {} access - {line}",
+        now.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        std::process::id(),
+    )
+}
+
+/// RotatingFile appends lines to a file. Once it would exceed `max_size`, the file is renamed to
+/// `<path>.1`, shifting older files up to `<path>.<max_files>`, and a new file is started.
+struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: &Path, max_size: u64, max_files: usize) -> io::Result<RotatingFile> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            max_size,
+            max_files,
+            file,
+            size,
+        })
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{n}"));
+        path.into()
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.size > 0 && self.size + len > self.max_size {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{line}")?;
+        self.size += len;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.max_files == 0 {
+            self.file.set_len(0)?;
+        } else {
+            for n in (1..self.max_files).rev() {
+                // Older files may not exist yet
+                let _ = std::fs::rename(self.rotated(n), self.rotated(n + 1));
+            }
+            std::fs::rename(&self.path, self.rotated(1))?;
+            self.file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry() -> Entry {
+        Entry {
+            direction: "inbound",
+            src_addr: Some("10.0.0.1:35000".parse().unwrap()),
+            src_identity: Some(Identity::from_str("spiffe://cluster.local/ns/ns1/sa/sa1").unwrap()),
+            dst_addr: Some("10.0.0.2:80".parse().unwrap()),
+            bytes_sent: Some(10),
+            policy: Some("deny"),
+            error: Some("connection closed due to policy rejection".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn format() {
+        let json = entry().format(Format::Json, FIELDS);
+        let parsed: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed["src.addr"], "10.0.0.1:35000");
+        assert_eq!(parsed["bytes_sent"], 10);
+        assert!(parsed.get("bytes_recv").is_none());
+        assert!(parsed.get("audit").is_none());
+
+        let kv = entry().format(Format::KeyValue, &["direction", "bytes_sent", "error"]);
+        assert_eq!(
+            kv,
+            r#"direction=inbound bytes_sent=10 error="connection closed due to policy rejection""#
+        );
+    }
+
+    #[test]
+    fn sink() {
+        assert_eq!(Sink::from_str("stdout").unwrap(), Sink::Stdout);
+        assert_eq!(
+            Sink::from_str("file:/var/log/access.log").unwrap(),
+            Sink::File("/var/log/access.log".into())
+        );
+        assert_eq!(
+            Sink::from_str("udp:127.0.0.1:514").unwrap(),
+            Sink::SyslogUdp("127.0.0.1:514".parse().unwrap())
+        );
+        assert_eq!(
+            Sink::from_str("unix:/dev/log").unwrap(),
+            Sink::SyslogUnix("/dev/log".into())
+        );
+        assert!(Sink::from_str("tcp:127.0.0.1:514").is_err());
+    }
+
+    #[test]
+    fn rotating_file() {
+        let dir = std::env::temp_dir().join(format!("access-log-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("access.log");
+        let mut f = RotatingFile::open(&path, 10, 2).unwrap();
+        for line in ["first", "second", "third", "fourth"] {
+            f.write_line(line).unwrap();
+        }
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "fourth\n");
+        assert_eq!(std::fs::read_to_string(f.rotated(1)).unwrap(), "third\n");
+        assert_eq!(std::fs::read_to_string(f.rotated(2)).unwrap(), "second\n");
+        assert!(!f.rotated(3).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
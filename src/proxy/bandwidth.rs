@@ -0,0 +1,391 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{Instant, Sleep};
+
+use crate::config::Config;
+use crate::proxy::metrics::{BandwidthDirection, BandwidthScope, BandwidthThrottleLabels, Metrics};
+use crate::state::workload::{BandwidthLimit, Workload};
+
+/// TokenBucket limits throughput to `rate` bytes per second, allowing bursts of up to one second of
+/// traffic.
+/// Transfers are allowed to put the bucket into debt; the caller then waits until it is paid back.
+/// This lets us account for data after it is transferred, which is all we can do with zero-copy.
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    state: Mutex<BucketState>,
+}
+
+#[derive(Debug)]
+struct BucketState {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: u64) -> Self {
+        TokenBucket {
+            rate: rate as f64,
+            state: Mutex::new(BucketState {
+                tokens: rate as f64,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// take removes `n` tokens from the bucket, and returns how long the caller must wait before
+    /// transferring more data.
+    pub fn take(&self, n: u64) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let refill = now.duration_since(state.updated).as_secs_f64() * self.rate;
+        state.tokens = (state.tokens + refill).min(self.rate) - n as f64;
+        state.updated = now;
+        if state.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-state.tokens / self.rate)
+        }
+    }
+}
+
+/// BandwidthLimiter hands out the token buckets shared by connections with the same limits.
+#[derive(Clone)]
+pub struct BandwidthLimiter {
+    workload_default: BandwidthLimit,
+    services: Arc<HashMap<String, BandwidthLimit>>,
+    // Buckets are keyed by the workload UID or service hostname they limit, and by their rate as well,
+    // so changing a limit takes effect for new connections.
+    // They are only held weakly, so they are dropped once no connections use them.
+    buckets: Arc<Mutex<HashMap<BucketKey, Weak<TokenBucket>>>>,
+    metrics: Arc<Metrics>,
+}
+
+type BucketKey = (BandwidthScope, String, BandwidthDirection, u64);
+
+/// StreamSide describes which peer a shaped stream is connected to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StreamSide {
+    // Reads from the stream are uploads by the source workload
+    Source,
+    // Reads from the stream are downloads to the source workload
+    Destination,
+}
+
+impl BandwidthLimiter {
+    pub fn new(cfg: &Config, metrics: Arc<Metrics>) -> Self {
+        BandwidthLimiter {
+            workload_default: cfg.workload_bandwidth_limit,
+            services: Arc::new(cfg.service_bandwidth_limits.clone()),
+            buckets: Default::default(),
+            metrics,
+        }
+    }
+
+    /// shaper returns the limits to apply to a stream of a connection from `source` to `service`,
+    /// or None if it is unlimited.
+    pub fn shaper(
+        &self,
+        source: Option<&Workload>,
+        service: Option<&str>,
+        side: StreamSide,
+    ) -> Option<Shaper> {
+        let mut upload = Vec::new();
+        let mut download = Vec::new();
+        // `key` identifies the bucket, while `name` is only used to label metrics.
+        let mut add = |scope, key: &str, name: &str, limit: BandwidthLimit| {
+            for (rate, direction, limits) in [
+                (limit.upload, BandwidthDirection::upload, &mut upload),
+                (limit.download, BandwidthDirection::download, &mut download),
+            ] {
+                if let Some(rate) = rate.filter(|r| *r > 0) {
+                    limits.push(ShaperLimit {
+                        bucket: self.bucket((scope, key.to_string(), direction, rate)),
+                        labels: BandwidthThrottleLabels {
+                            scope,
+                            name: name.to_string(),
+                            direction,
+                        },
+                    });
+                }
+            }
+        };
+        if let Some(wl) = source {
+            let limit = wl.bandwidth_limit.or(self.workload_default);
+            add(BandwidthScope::workload, &wl.uid, &wl.namespace, limit);
+        }
+        if let Some((hostname, limit)) = service.and_then(|s| self.services.get_key_value(s)) {
+            add(BandwidthScope::service, hostname, hostname, *limit);
+        }
+        if upload.is_empty() && download.is_empty() {
+            return None;
+        }
+        let (read, write) = match side {
+            StreamSide::Source => (upload, download),
+            StreamSide::Destination => (download, upload),
+        };
+        Some(Shaper {
+            read,
+            write,
+            metrics: self.metrics.clone(),
+        })
+    }
+
+    fn bucket(&self, key: BucketKey) -> Arc<TokenBucket> {
+        let rate = key.3;
+        let mut buckets = self.buckets.lock().unwrap();
+        if let Some(bucket) = buckets.get(&key).and_then(Weak::upgrade) {
+            return bucket;
+        }
+        buckets.retain(|_, b| b.strong_count() > 0);
+        let bucket = Arc::new(TokenBucket::new(rate));
+        buckets.insert(key, Arc::downgrade(&bucket));
+        bucket
+    }
+}
+
+#[derive(Clone)]
+struct ShaperLimit {
+    bucket: Arc<TokenBucket>,
+    labels: BandwidthThrottleLabels,
+}
+
+/// Shaper applies bandwidth limits to a single stream of a connection.
+#[derive(Clone)]
+pub struct Shaper {
+    read: Vec<ShaperLimit>,
+    write: Vec<ShaperLimit>,
+    metrics: Arc<Metrics>,
+}
+
+impl Shaper {
+    /// on_read records that `n` bytes were read from the stream, and returns how long to pause
+    /// reading.
+    pub fn on_read(&self, n: u64) -> Duration {
+        self.take(&self.read, n)
+    }
+
+    /// on_write records that `n` bytes were written to the stream, and returns how long to pause
+    /// writing.
+    pub fn on_write(&self, n: u64) -> Duration {
+        self.take(&self.write, n)
+    }
+
+    fn take(&self, limits: &[ShaperLimit], n: u64) -> Duration {
+        // Every limit is charged, but the pause is attributed to the most restrictive one.
+        let mut pause = Duration::ZERO;
+        let mut cause = None;
+        for limit in limits {
+            let wait = limit.bucket.take(n);
+            if wait > pause {
+                pause = wait;
+                cause = Some(&limit.labels);
+            }
+        }
+        if let Some(labels) = cause {
+            self.metrics
+                .bandwidth_throttled
+                .get_or_create(labels)
+                .inc_by(pause.as_secs_f64());
+        }
+        pause
+    }
+}
+
+/// ShapedIo wraps a stream, pausing reads and writes to keep them within the limits of a Shaper.
+pub struct ShapedIo<'a, S> {
+    inner: S,
+    shaper: Option<&'a Shaper>,
+    read_pause: Option<Pin<Box<Sleep>>>,
+    write_pause: Option<Pin<Box<Sleep>>>,
+}
+
+impl<'a, S> ShapedIo<'a, S> {
+    pub fn new(inner: S, shaper: Option<&'a Shaper>) -> Self {
+        ShapedIo {
+            inner,
+            shaper,
+            read_pause: None,
+            write_pause: None,
+        }
+    }
+}
+
+fn poll_pause(pause: &mut Option<Pin<Box<Sleep>>>, cx: &mut Context<'_>) -> Poll<()> {
+    if let Some(sleep) = pause {
+        ready!(sleep.as_mut().poll(cx));
+        *pause = None;
+    }
+    Poll::Ready(())
+}
+
+fn start_pause(pause: &mut Option<Pin<Box<Sleep>>>, wait: Duration) {
+    if !wait.is_zero() {
+        *pause = Some(Box::pin(tokio::time::sleep(wait)));
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for ShapedIo<'_, S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        ready!(poll_pause(&mut this.read_pause, cx));
+        let before = buf.filled().len();
+        let res = ready!(Pin::new(&mut this.inner).poll_read(cx, buf));
+        let n = (buf.filled().len() - before) as u64;
+        if let Some(shaper) = this.shaper.filter(|_| n > 0) {
+            start_pause(&mut this.read_pause, shaper.on_read(n));
+        }
+        Poll::Ready(res)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for ShapedIo<'_, S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        ready!(poll_pause(&mut this.write_pause, cx));
+        let res = ready!(Pin::new(&mut this.inner).poll_write(cx, buf));
+        if let (Some(shaper), Ok(n)) = (this.shaper, &res) {
+            start_pause(&mut this.write_pause, shaper.on_write(*n as u64));
+        }
+        Poll::Ready(res)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::helpers::test_proxy_metrics;
+    use crate::test_helpers::test_default_workload;
+
+    fn limiter(cfg: Config) -> BandwidthLimiter {
+        BandwidthLimiter::new(&cfg, test_proxy_metrics())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn token_bucket() {
+        let bucket = TokenBucket::new(1000);
+        // The initial burst is allowed
+        assert_eq!(bucket.take(1000), Duration::ZERO);
+        // Going into debt requires waiting it off
+        assert_eq!(bucket.take(500), Duration::from_millis(500));
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(bucket.take(100), Duration::from_millis(100));
+        // Tokens never accumulate beyond the burst
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert_eq!(bucket.take(1000), Duration::ZERO);
+        assert_eq!(bucket.take(1), Duration::from_millis(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shaper() {
+        let cfg = Config {
+            workload_bandwidth_limit: "1000:".parse().unwrap(),
+            service_bandwidth_limits: HashMap::from([(
+                "svc.default.svc.cluster.local".to_string(),
+                "100:2000".parse().unwrap(),
+            )]),
+            ..crate::config::parse_config().unwrap()
+        };
+        let limiter = limiter(cfg);
+        let wl = test_default_workload();
+        let unlimited = Workload {
+            bandwidth_limit: BandwidthLimit {
+                upload: Some(0),
+                download: None,
+            },
+            ..test_default_workload()
+        };
+
+        assert!(limiter.shaper(None, None, StreamSide::Source).is_none());
+        assert!(limiter
+            .shaper(Some(&unlimited), None, StreamSide::Source)
+            .is_none());
+
+        // Uploads are limited by the workload default; downloads are unlimited
+        let shaper = limiter.shaper(Some(&wl), None, StreamSide::Source).unwrap();
+        assert_eq!(shaper.on_read(1500), Duration::from_millis(500));
+        assert_eq!(shaper.on_write(1_000_000), Duration::ZERO);
+
+        // Connections from the same workload share its bucket, which is still in debt
+        let other = limiter
+            .shaper(Some(&wl), None, StreamSide::Destination)
+            .unwrap();
+        assert_eq!(other.on_write(500), Duration::from_secs(1));
+        assert_eq!(other.on_read(1_000_000), Duration::ZERO);
+
+        // The most restrictive limit applies
+        let svc = limiter
+            .shaper(
+                Some(&wl),
+                Some("svc.default.svc.cluster.local"),
+                StreamSide::Source,
+            )
+            .unwrap();
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert_eq!(svc.on_read(200), Duration::from_secs(1));
+        assert_eq!(svc.on_write(3000), Duration::from_millis(500));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shaped_io() {
+        use tokio::io::AsyncWriteExt;
+
+        let cfg = Config {
+            workload_bandwidth_limit: "1000:".parse().unwrap(),
+            ..crate::config::parse_config().unwrap()
+        };
+        let limiter = limiter(cfg);
+        let shaper = limiter
+            .shaper(
+                Some(&test_default_workload()),
+                None,
+                StreamSide::Destination,
+            )
+            .unwrap();
+        let mut io = ShapedIo::new(tokio::io::sink(), Some(&shaper));
+        let start = Instant::now();
+        for _ in 0..5 {
+            io.write_all(&[0; 1000]).await.unwrap();
+        }
+        // Writes are paused until the data already written fits within the limit. The first write
+        // fits in the initial burst, and the last one is never waited for.
+        assert_eq!(start.elapsed(), Duration::from_secs(3));
+    }
+}
@@ -13,15 +13,17 @@
 // limitations under the License.
 
 use crate::proxy::error;
+use crate::proxy::metrics::Reporter;
 use crate::rbac;
 use crate::state::DemandProxyState;
 use crate::state::ProxyRbacContext;
 use drain;
+use futures::StreamExt;
 use serde::{Serialize, Serializer};
 use std::collections::HashMap;
 use std::fmt::Formatter;
 use std::sync::Arc;
-use std::sync::RwLock;
+use std::sync::{Mutex, RwLock};
 use tracing::info;
 
 struct ConnectionDrain {
@@ -31,12 +33,19 @@ struct ConnectionDrain {
     tx: drain::Signal,
     rx: drain::Watch,
     count: usize,
+    // the decision that closed the connection, set before it is drained
+    decision: Arc<Mutex<Option<rbac::RbacDecision>>>,
 }
 
 impl ConnectionDrain {
     fn new() -> Self {
         let (tx, rx) = drain::channel();
-        ConnectionDrain { tx, rx, count: 0 }
+        ConnectionDrain {
+            tx,
+            rx,
+            count: 0,
+            decision: Default::default(),
+        }
     }
 
     /// drain drops the internal reference to rx and then signals drain on the tx
@@ -48,9 +57,71 @@ impl ConnectionDrain {
     }
 }
 
+/// ConnectionClose is signaled when a tracked connection must be closed, as it is no longer allowed
+/// after a policy update.
+pub struct ConnectionClose {
+    rx: drain::Watch,
+    decision: Arc<Mutex<Option<rbac::RbacDecision>>>,
+}
+
+impl ConnectionClose {
+    /// signaled waits until the connection must be closed, returning the decision that closed it.
+    pub async fn signaled(self) -> rbac::RbacDecision {
+        let _release = self.rx.signaled().await;
+        self.decision
+            .lock()
+            .expect("mutex")
+            .clone()
+            .unwrap_or_default()
+    }
+}
+
 #[derive(Clone)]
 pub struct ConnectionManager {
     drains: Arc<RwLock<HashMap<ProxyRbacContext, ConnectionDrain>>>,
+    // count of active connections in each group we track
+    counts: Arc<Mutex<HashMap<ConnectionKey, usize>>>,
+}
+
+/// ConnectionKey identifies a group of active connections counted by the ConnectionManager.
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub enum ConnectionKey {
+    /// Outbound connections to a service endpoint, by workload UID
+    Endpoint(String),
+    /// Connections to a service, by hostname
+    Service(Reporter, String),
+    /// Connections from a workload, by workload UID
+    SourceWorkload(Reporter, String),
+}
+
+impl std::fmt::Display for ConnectionKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectionKey::Endpoint(uid) => write!(f, "endpoint {uid}"),
+            ConnectionKey::Service(_, hostname) => write!(f, "service {hostname}"),
+            ConnectionKey::SourceWorkload(_, uid) => write!(f, "source workload {uid}"),
+        }
+    }
+}
+
+/// ConnectionGuard keeps a connection counted until it is dropped.
+pub struct ConnectionGuard {
+    counts: Arc<Mutex<HashMap<ConnectionKey, usize>>>,
+    keys: Vec<ConnectionKey>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut counts = self.counts.lock().expect("mutex");
+        for key in &self.keys {
+            if let Some(count) = counts.get_mut(key) {
+                *count -= 1;
+                if *count == 0 {
+                    counts.remove(key);
+                }
+            }
+        }
+    }
 }
 
 impl std::fmt::Debug for ConnectionManager {
@@ -63,6 +134,7 @@ impl Default for ConnectionManager {
     fn default() -> Self {
         ConnectionManager {
             drains: Arc::new(RwLock::new(HashMap::new())),
+            counts: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 }
@@ -83,7 +155,7 @@ impl ConnectionManager {
     // get a channel to receive close on for your connection
     // requires that the connection be registered first
     // if you receive None this connection is invalid and should close
-    pub fn track(&self, c: &ProxyRbacContext) -> Option<drain::Watch> {
+    pub fn track(&self, c: &ProxyRbacContext) -> Option<ConnectionClose> {
         match self
             .drains
             .write()
@@ -91,10 +163,10 @@ impl ConnectionManager {
             .entry(c.to_owned())
             .and_modify(|cd| cd.count += 1)
         {
-            std::collections::hash_map::Entry::Occupied(cd) => {
-                let rx = cd.get().rx.clone();
-                Some(rx)
-            }
+            std::collections::hash_map::Entry::Occupied(cd) => Some(ConnectionClose {
+                rx: cd.get().rx.clone(),
+                decision: cd.get().decision.clone(),
+            }),
             std::collections::hash_map::Entry::Vacant(_) => None,
         }
     }
@@ -113,9 +185,10 @@ impl ConnectionManager {
     }
 
     // signal all connections listening to this channel to take action (typically terminate traffic)
-    async fn close(&self, c: &ProxyRbacContext) {
+    async fn close(&self, c: &ProxyRbacContext, decision: rbac::RbacDecision) {
         let drain = { self.drains.write().expect("mutex").remove(c) };
         if let Some(cd) = drain {
+            *cd.decision.lock().expect("mutex") = Some(decision);
             cd.drain().await;
         } else {
             // this is bad, possibly drain called twice
@@ -123,6 +196,54 @@ impl ConnectionManager {
         }
     }
 
+    // count an outbound connection to a service endpoint until the returned guard is dropped
+    pub fn track_outbound(&self, endpoint_uid: &str) -> ConnectionGuard {
+        self.try_track_limited(vec![(
+            ConnectionKey::Endpoint(endpoint_uid.to_string()),
+            None,
+        )])
+        .expect("unlimited")
+    }
+
+    // get the number of active outbound connections to a service endpoint
+    pub fn outbound_connections(&self, endpoint_uid: &str) -> usize {
+        self.active(&ConnectionKey::Endpoint(endpoint_uid.to_string()))
+    }
+
+    // get the number of active connections in a group
+    pub fn active(&self, key: &ConnectionKey) -> usize {
+        self.counts
+            .lock()
+            .expect("mutex")
+            .get(key)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    // count a connection in each of the groups until the returned guard is dropped.
+    // If any group is already at its limit, nothing is counted and that group is returned instead.
+    pub fn try_track_limited(
+        &self,
+        keys: Vec<(ConnectionKey, Option<usize>)>,
+    ) -> Result<ConnectionGuard, ConnectionKey> {
+        let mut counts = self.counts.lock().expect("mutex");
+        for (key, limit) in &keys {
+            if let Some(limit) = limit {
+                if counts.get(key).copied().unwrap_or_default() >= *limit {
+                    return Err(key.clone());
+                }
+            }
+        }
+        let keys: Vec<_> = keys.into_iter().map(|(key, _)| key).collect();
+        for key in &keys {
+            *counts.entry(key.clone()).or_default() += 1;
+        }
+        Ok(ConnectionGuard {
+            counts: self.counts.clone(),
+            keys,
+        })
+    }
+
     //  get a list of all connections being tracked
     pub fn connections(&self) -> Vec<ProxyRbacContext> {
         // potentially large copy under read lock, could require optimization
@@ -153,6 +274,9 @@ impl Serialize for ConnectionManager {
     }
 }
 
+// Connections re-evaluated at once after a policy update.
+const MAX_CONCURRENT_REEVALUATIONS: usize = 64;
+
 pub struct PolicyWatcher {
     state: DemandProxyState,
     stop: drain::Watch,
@@ -174,19 +298,24 @@ impl PolicyWatcher {
 
     pub async fn run(self) {
         let mut policies_changed = self.state.read().policies.subscribe();
+        let state = &self.state;
+        let connection_manager = &self.connection_manager;
         loop {
             tokio::select! {
                 _ = self.stop.clone().signaled() => {
                     break;
                 }
                 _ = policies_changed.changed() => {
-                    let connections = self.connection_manager.connections();
-                    for conn in connections {
-                        if !self.state.assert_rbac(&conn).await {
-                            self.connection_manager.close(&conn).await;
-                            info!("connection {conn} closed because it's no longer allowed after a policy update");
-                        }
-                    }
+                    let connections = connection_manager.connections();
+                    futures::stream::iter(connections)
+                        .for_each_concurrent(MAX_CONCURRENT_REEVALUATIONS, |conn| async move {
+                            let decision = state.reassert_rbac(&conn).await;
+                            if !decision.allowed {
+                                info!(%decision, "connection {conn} closed because it's no longer allowed after a policy update");
+                                connection_manager.close(&conn, decision).await;
+                            }
+                        })
+                        .await;
                 }
             }
         }
@@ -195,18 +324,19 @@ impl PolicyWatcher {
 
 #[cfg(test)]
 mod tests {
-    use drain::Watch;
     use hickory_resolver::config::{ResolverConfig, ResolverOpts};
     use std::net::{Ipv4Addr, SocketAddrV4};
     use std::sync::{Arc, RwLock};
     use std::time::Duration;
 
+    use crate::rbac;
     use crate::rbac::Connection;
     use crate::state::{DemandProxyState, ProxyState};
     use crate::xds::istio::security::{Action, Authorization, Scope};
     use crate::xds::ProxyStateUpdateMutator;
 
-    use super::{ConnectionManager, PolicyWatcher};
+    use super::{ConnectionClose, ConnectionKey, ConnectionManager, PolicyWatcher};
+    use crate::proxy::metrics::Reporter;
 
     #[tokio::test]
     async fn test_connection_manager_close() {
@@ -291,7 +421,9 @@ mod tests {
         tokio::spawn(assert_close(close1));
         tokio::spawn(assert_close(another_close1));
         // close rbac_ctx1
-        connection_manager.close(&rbac_ctx1).await;
+        connection_manager
+            .close(&rbac_ctx1, rbac::RbacDecision::deny())
+            .await;
         // ensure drains contains exactly 1 item
         assert_eq!(connection_manager.drains.read().unwrap().len(), 1);
         assert_eq!(connection_manager.connections().len(), 1);
@@ -300,7 +432,9 @@ mod tests {
         // spawn a task to assert that we close in a timely manner for rbac_ctx2
         tokio::spawn(assert_close(close2));
         // close rbac_ctx2
-        connection_manager.close(&rbac_ctx2).await;
+        connection_manager
+            .close(&rbac_ctx2, rbac::RbacDecision::deny())
+            .await;
         // assert that drains is empty again
         assert_eq!(connection_manager.drains.read().unwrap().len(), 0);
         assert_eq!(connection_manager.connections().len(), 0);
@@ -458,6 +592,7 @@ mod tests {
             scope: Scope::Global as i32,
             namespace: "default".to_string(),
             rules: vec![],
+            dry_run: false,
         };
 
         // spawn an assertion that our connection close is received
@@ -478,9 +613,73 @@ mod tests {
         tx.drain().await;
     }
 
+    #[test]
+    fn test_connection_manager_outbound() {
+        let connection_manager = ConnectionManager::default();
+        let a1 = connection_manager.track_outbound("a");
+        let a2 = connection_manager.track_outbound("a");
+        let _b = connection_manager.track_outbound("b");
+        assert_eq!(connection_manager.outbound_connections("a"), 2);
+        assert_eq!(connection_manager.outbound_connections("b"), 1);
+        assert_eq!(connection_manager.outbound_connections("c"), 0);
+
+        drop(a1);
+        assert_eq!(connection_manager.outbound_connections("a"), 1);
+        drop(a2);
+        assert_eq!(connection_manager.outbound_connections("a"), 0);
+        assert!(!connection_manager
+            .counts
+            .lock()
+            .unwrap()
+            .contains_key(&ConnectionKey::Endpoint("a".to_string())));
+    }
+
+    #[test]
+    fn test_connection_manager_limits() {
+        let connection_manager = ConnectionManager::default();
+        let svc = ConnectionKey::Service(Reporter::source, "svc".to_string());
+        let wl = |uid: &str| ConnectionKey::SourceWorkload(Reporter::source, uid.to_string());
+
+        let a = connection_manager
+            .try_track_limited(vec![(svc.clone(), Some(2)), (wl("a"), Some(1))])
+            .unwrap();
+        // The workload is at its limit
+        assert_eq!(
+            connection_manager
+                .try_track_limited(vec![(svc.clone(), Some(2)), (wl("a"), Some(1))])
+                .err(),
+            Some(wl("a"))
+        );
+        // Rejected connections are not counted
+        assert_eq!(connection_manager.active(&svc), 1);
+        let _b = connection_manager
+            .try_track_limited(vec![(svc.clone(), Some(2)), (wl("b"), Some(1))])
+            .unwrap();
+        // The service is at its limit
+        assert_eq!(
+            connection_manager
+                .try_track_limited(vec![(svc.clone(), Some(2)), (wl("c"), Some(1))])
+                .err(),
+            Some(svc.clone())
+        );
+        // Limits apply separately to inbound connections
+        let _inbound = connection_manager
+            .try_track_limited(vec![(
+                ConnectionKey::Service(Reporter::destination, "svc".to_string()),
+                Some(2),
+            )])
+            .unwrap();
+
+        drop(a);
+        assert_eq!(connection_manager.active(&wl("a")), 0);
+        assert!(connection_manager
+            .try_track_limited(vec![(svc.clone(), Some(2)), (wl("c"), Some(1))])
+            .is_ok());
+    }
+
     // small helper to assert that the Watches are working in a timely manner
-    async fn assert_close(c: Watch) {
+    async fn assert_close(c: ConnectionClose) {
         let result = tokio::time::timeout(Duration::from_secs(1), c.signaled()).await;
-        assert!(result.is_ok())
+        assert!(matches!(result, Ok(decision) if !decision.allowed))
     }
 }
@@ -0,0 +1,259 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::Bytes;
+use drain::Watch;
+use http_body_util::Empty;
+use hyper::body::Incoming;
+use hyper::service::service_fn;
+use hyper::upgrade::OnUpgrade;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::{debug, error, info, warn};
+
+use crate::dns::server::MeshResolver;
+use crate::proxy::outbound::OutboundConnection;
+use crate::proxy::resolver::{mesh_resolver, resolve};
+use crate::proxy::util::InFlight;
+use crate::proxy::{util, Error, ProxyInputs, TraceParent};
+use crate::socket;
+
+/// HttpConnect is a plaintext HTTP/1.1 forward proxy, for clients that support `HTTPS_PROXY` but not
+/// SOCKS. Only CONNECT requests are supported; the tunnel is then proxied like captured traffic.
+pub(super) struct HttpConnect {
+    pi: ProxyInputs,
+    listener: TcpListener,
+    drain: Watch,
+    resolver: Arc<MeshResolver>,
+}
+
+impl HttpConnect {
+    pub(super) async fn new(pi: ProxyInputs, drain: Watch) -> Result<HttpConnect, Error> {
+        let listener: TcpListener = pi
+            .socket_factory
+            .tcp_bind(pi.cfg.http_connect_addr)
+            .map_err(|e| Error::Bind(pi.cfg.http_connect_addr, e))?;
+
+        info!(
+            address=%listener.local_addr().expect("local_addr available"),
+            component="http_connect",
+            "listener established",
+        );
+
+        let resolver = mesh_resolver(&pi)?;
+
+        Ok(HttpConnect {
+            pi,
+            listener,
+            drain,
+            resolver,
+        })
+    }
+
+    pub(super) fn address(&self) -> SocketAddr {
+        self.listener.local_addr().expect("local_addr available")
+    }
+
+    pub async fn run(self) {
+        let HttpConnect {
+            pi,
+            listener,
+            drain,
+            resolver,
+        } = self;
+        let connections = util::ConnectionDrain::default();
+        let handshakes = Arc::new(Semaphore::new(MAX_CONCURRENT_HANDSHAKES));
+        let accept = async {
+            loop {
+                // Stop accepting while too many clients have yet to establish a tunnel, leaving new
+                // ones in the listen backlog.
+                let permit = handshakes
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("semaphore is never closed");
+                // Asynchronously wait for an inbound socket.
+                let socket = listener.accept().await;
+                match socket {
+                    Ok((stream, remote)) => {
+                        debug!("accepted http connect connection from {}", remote);
+                        let oc = OutboundConnection {
+                            pi: pi.clone(),
+                            id: TraceParent::new(),
+                            authenticated_source: None,
+                        };
+                        let (in_flight, stream_drain) = connections.track();
+                        let resolver = resolver.clone();
+                        tokio::spawn(async move {
+                            if let Err(err) =
+                                handle(oc, stream, stream_drain, in_flight, resolver, permit).await
+                            {
+                                warn!("http connect error: {}", err);
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        if util::is_runtime_shutdown(&e) {
+                            return;
+                        }
+                        error!("Failed TCP handshake {}", e);
+                    }
+                }
+            }
+        };
+
+        // Stop accepting once we drain, then wait for in-flight connections to complete.
+        let release = tokio::select! {
+            _ = accept => return,
+            release = drain.signaled() => { release }
+        };
+        drop(listener);
+        let cut_off = connections.drain(pi.cfg.termination_drain_duration).await;
+        if cut_off > 0 {
+            warn!(
+                connections = cut_off,
+                "http connect drain deadline exceeded, closed remaining connections"
+            );
+        }
+        info!("http connect drained");
+        drop(release);
+    }
+}
+
+// Clients must send a CONNECT request, and have it accepted, within this time.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Connections still in the handshake beyond this many are left in the listen backlog.
+const MAX_CONCURRENT_HANDSHAKES: usize = 1024;
+
+// A CONNECT request that was accepted, waiting for the connection to be handed over to us.
+type Accepted = Arc<Mutex<Option<(OnUpgrade, SocketAddr)>>>;
+
+// handle serves HTTP requests on a connection until a CONNECT request is accepted, and then proxies
+// the tunnel to the requested target.
+async fn handle(
+    mut oc: OutboundConnection,
+    stream: TcpStream,
+    out_drain: Watch,
+    in_flight: InFlight,
+    resolver: Arc<MeshResolver>,
+    handshake_permit: OwnedSemaphorePermit,
+) -> Result<(), anyhow::Error> {
+    let remote_addr = socket::to_canonical(stream.peer_addr().expect("must receive peer addr"));
+
+    let accepted: Accepted = Default::default();
+    let serve = hyper::server::conn::http1::Builder::new()
+        .serve_connection(
+            TokioIo::new(stream),
+            service_fn(|req| {
+                let accepted = accepted.clone();
+                let resolver = resolver.clone();
+                async move {
+                    let status = serve_connect(req, remote_addr, &resolver, &accepted).await;
+                    Ok::<_, hyper::Error>(
+                        Response::builder()
+                            .status(status)
+                            .body(Empty::<Bytes>::new())
+                            .expect("builder with known status code should not fail"),
+                    )
+                }
+            }),
+        )
+        // Once the CONNECT response is written, serving completes and the connection is handed over.
+        .with_upgrades();
+    tokio::select! {
+        res = tokio::time::timeout(HANDSHAKE_TIMEOUT, serve) => {
+            res.map_err(|_| anyhow::anyhow!("handshake timed out"))??
+        }
+        _ = out_drain.clone().signaled() => return Ok(()),
+    }
+    drop(handshake_permit);
+
+    let Some((on_upgrade, host)) = accepted.lock().unwrap().take() else {
+        // The client closed the connection without a successful CONNECT
+        return Ok(());
+    };
+    let parts = on_upgrade
+        .await?
+        .downcast::<TokioIo<TcpStream>>()
+        .map_err(|_| anyhow::anyhow!("unexpected connection type"))?;
+    let stream = parts.io.into_inner();
+
+    info!("accepted connection from {remote_addr} to {host}");
+    // The connection stays in flight until it completes. When we drain, it gets until the drain
+    // deadline to complete normally before `out_drain` is signaled to cancel it.
+    // Anything the client sent after the CONNECT request, without waiting for the response, was
+    // already read by hyper, and is sent ahead of the rest of the stream.
+    oc.proxy_to_cancellable(
+        stream,
+        parts.read_buf,
+        remote_addr,
+        host,
+        true,
+        Some(out_drain),
+    )
+    .await;
+    drop(in_flight);
+    Ok(())
+}
+
+// serve_connect validates a request and resolves its target. Accepted requests are recorded in
+// `accepted`, to be proxied once the response has been sent.
+async fn serve_connect(
+    mut req: Request<Incoming>,
+    remote_addr: SocketAddr,
+    resolver: &MeshResolver,
+    accepted: &Accepted,
+) -> StatusCode {
+    if req.method() != Method::CONNECT {
+        debug!(method=%req.method(), "rejecting non-CONNECT request");
+        return StatusCode::METHOD_NOT_ALLOWED;
+    }
+    let Some((host, port)) = req
+        .uri()
+        .authority()
+        .and_then(|a| Some((a.host().to_string(), a.port_u16()?)))
+    else {
+        debug!(uri=%req.uri(), "rejecting CONNECT without host and port");
+        return StatusCode::BAD_REQUEST;
+    };
+    // IPv6 literals are bracketed in the authority
+    let target = match host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+    {
+        Ok(ip) => SocketAddr::new(ip, port),
+        Err(_) => match resolve(resolver, remote_addr, &host, port).await {
+            Ok(addr) => addr,
+            Err(e) => {
+                debug!("failed to resolve {host}: {e}");
+                return StatusCode::BAD_GATEWAY;
+            }
+        },
+    };
+    let mut accepted = accepted.lock().unwrap();
+    if accepted.is_some() {
+        // A connection can only be upgraded once
+        return StatusCode::BAD_REQUEST;
+    }
+    *accepted = Some((hyper::upgrade::on(&mut req), target));
+    StatusCode::OK
+}
@@ -32,13 +32,17 @@ use tokio::time::timeout;
 
 use tracing::{debug, error, info, instrument, trace_span, Instrument};
 
-use super::connection_manager::ConnectionManager;
+use super::connection_manager::{ConnectionGuard, ConnectionManager};
 use super::{ConnectionResult, Error, SocketFactory};
 use crate::baggage::parse_baggage_header;
 use crate::identity::{Identity, SecretManager};
 
+use crate::proxy::bandwidth::StreamSide;
 use crate::proxy::inbound::InboundConnect::{Hbone, Proxy};
+use crate::proxy::limits::ConnectionLimits;
 use crate::proxy::metrics::{ConnectionOpen, Reporter};
+use crate::proxy::otel::ConnectionTrace;
+use crate::proxy::proxy_protocol::ProxyTlvs;
 use crate::proxy::{metrics, ProxyInputs, TraceParent, BAGGAGE_HEADER, TRACEPARENT_HEADER};
 use crate::rbac::Connection;
 use crate::socket::to_canonical;
@@ -51,24 +55,44 @@ use crate::state::workload::{self, NetworkAddress, Workload};
 use crate::state::DemandProxyState;
 use crate::tls::TlsError;
 
+/// The kind of TLS an inbound HBONE listener accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum InboundMode {
+    /// Mutual TLS, the standard HBONE listener.
+    Mtls,
+    /// Single TLS: the CONNECT target may be any workload on our network, and the payload is not wrapped
+    /// in another mTLS connection. This allows acting as a gateway for peers on other networks. Clients
+    /// must still present a certificate, which is verified as on the HBONE listener.
+    SingleTls,
+}
+
 pub(super) struct Inbound {
     listener: TcpListener,
     drain: Watch,
     pi: ProxyInputs,
+    mode: InboundMode,
 }
 
 impl Inbound {
-    pub(super) async fn new(mut pi: ProxyInputs, drain: Watch) -> Result<Inbound, Error> {
+    pub(super) async fn new(
+        mut pi: ProxyInputs,
+        drain: Watch,
+        mode: InboundMode,
+    ) -> Result<Inbound, Error> {
+        let (addr, component) = match mode {
+            InboundMode::Mtls => (pi.cfg.inbound_addr, "inbound"),
+            InboundMode::SingleTls => (pi.cfg.inbound_single_tls_addr, "inbound_single_tls"),
+        };
         let listener: TcpListener = pi
             .socket_factory
-            .tcp_bind(pi.cfg.inbound_addr)
-            .map_err(|e| Error::Bind(pi.cfg.inbound_addr, e))?;
+            .tcp_bind(addr)
+            .map_err(|e| Error::Bind(addr, e))?;
         let transparent = super::maybe_set_transparent(&pi, &listener)?;
         // Override with our explicitly configured setting
         pi.cfg.enable_original_source = Some(transparent);
         info!(
             address=%listener.local_addr().expect("local_addr available"),
-            component="inbound",
+            component,
             transparent,
             "listener established",
         );
@@ -76,6 +100,7 @@ impl Inbound {
             listener,
             drain,
             pi,
+            mode,
         })
     }
 
@@ -104,6 +129,7 @@ impl Inbound {
             let drain = sub_drain.clone();
             let network = self.pi.cfg.network.clone();
             let drain_deadline = self.pi.cfg.self_termination_deadline;
+            let mode = self.mode;
             tokio::task::spawn(async move {
                 let conn = Connection {
                     src_identity,
@@ -125,6 +151,7 @@ impl Inbound {
                         service_fn(move |req| {
                             Self::serve_connect(
                                 pi.clone(),
+                                mode,
                                 conn.clone(),
                                 enable_original_source.unwrap_or_default(),
                                 req,
@@ -181,12 +208,19 @@ impl Inbound {
         request_type: InboundConnect,
         orig_src: Option<IpAddr>,
         addr: SocketAddr,
-        result_tracker: ConnectionResult,
+        mut result_tracker: ConnectionResult,
         socket_factory: &(dyn SocketFactory + Send + Sync),
         connection_manager: ConnectionManager,
         rbac_ctx: crate::state::ProxyRbacContext,
+        limits: ConnectionLimits,
+        circuit_guard: ConnectionGuard,
     ) -> Result<(), ()> {
-        let stream = super::freebind_connect(orig_src, addr, socket_factory)
+        let trace = result_tracker.trace();
+        let stream = trace
+            .traced(
+                "connect",
+                super::freebind_connect(orig_src, addr, socket_factory),
+            )
             .await
             .and_then(|s| {
                 s.set_nodelay(true)?;
@@ -203,6 +237,8 @@ impl Inbound {
 
         tokio::task::spawn(
             (async move {
+                // Keep the connection counted against its limits until it is closed
+                let _circuit_guard = circuit_guard;
                 let close = match connection_manager.track(&rbac_ctx) {
                     Some(c) => c,
                     None => {
@@ -212,39 +248,44 @@ impl Inbound {
                         return;
                     }
                 };
-                let send = async {
+                let send = trace.traced("relay", async {
                     match request_type {
                         Hbone(req) => {
                             hyper::upgrade::on(req)
                                 .map_err(Error::NoUpgrade)
-                                .and_then(|mut upgraded| async move {
-                                    super::copy_hbone(&mut upgraded, &mut stream)
+                                .and_then(|upgraded| async move {
+                                    let mut upgraded = hyper_util::rt::TokioIo::new(upgraded);
+                                    super::copy_hbone(&mut upgraded, &mut stream, &limits)
                                         .instrument(trace_span!("hbone server"))
                                         .await
                                 })
                                 .await
                         }
-                        Proxy(req, (src, dst), src_id) => {
+                        Proxy(req, (src, dst), tlvs) => {
                             hyper::upgrade::on(req)
                                 .map_err(Error::NoUpgrade)
-                                .and_then(|mut upgraded| async move {
-                                    super::write_proxy_protocol(&mut stream, (src, dst), src_id)
+                                .and_then(|upgraded| async move {
+                                    let mut upgraded = hyper_util::rt::TokioIo::new(upgraded);
+                                    super::write_proxy_protocol(&mut stream, (src, dst), &tlvs)
                                         .instrument(trace_span!("proxy protocol"))
                                         .await?;
-                                    super::copy_hbone(&mut upgraded, &mut stream)
+                                    super::copy_hbone(&mut upgraded, &mut stream, &limits)
                                         .instrument(trace_span!("hbone server"))
                                         .await
                                 })
                                 .await
                         }
                     }
-                };
+                });
                 let res = tokio::select! {
                      res = send => {
                         connection_manager.release(&rbac_ctx);
                         res
                     }
-                    _c = close.signaled() => Err(Error::AuthorizationPolicyLateRejection)
+                    decision = close.signaled() => {
+                        result_tracker.set_late_rejection(decision);
+                        Err(Error::AuthorizationPolicyLateRejection)
+                    }
                 };
                 result_tracker.record(res);
             })
@@ -272,6 +313,7 @@ impl Inbound {
     ))]
     async fn serve_connect(
         pi: ProxyInputs,
+        mode: InboundMode,
         conn: Connection,
         enable_original_source: bool,
         req: Request<Incoming>,
@@ -279,6 +321,7 @@ impl Inbound {
     ) -> StatusCode {
         if req.method() != Method::CONNECT {
             metrics::log_early_deny(
+                &pi.access_log,
                 conn.src,
                 conn.dst,
                 Reporter::destination,
@@ -287,8 +330,11 @@ impl Inbound {
             return StatusCode::NOT_FOUND;
         }
         let start = Instant::now();
+        let id = Self::extract_traceparent(&req);
+        let trace = ConnectionTrace::inbound(&pi.trace_exporter, &id);
         let Ok(hbone_addr) = req.uri().to_string().as_str().parse::<SocketAddr>() else {
             metrics::log_early_deny(
+                &pi.access_log,
                 conn.src,
                 conn.dst,
                 Reporter::destination,
@@ -299,9 +345,10 @@ impl Inbound {
 
         // Determine the next hop.
         let Ok((upstream_addr, inbound_protocol, upstream, upstream_service)) =
-            Self::find_inbound_upstream(pi.state.clone(), &conn, hbone_addr).await
+            Self::find_inbound_upstream(pi.state.clone(), mode, &conn, hbone_addr).await
         else {
             metrics::log_early_deny(
+                &pi.access_log,
                 conn.src,
                 conn.dst,
                 Reporter::destination,
@@ -335,6 +382,14 @@ impl Inbound {
 
         let baggage =
             parse_baggage_header(req.headers().get_all(BAGGAGE_HEADER)).unwrap_or_default();
+        trace.set_baggage(&baggage);
+        let mut tlvs = ProxyTlvs {
+            source_identity: rbac_ctx.conn.src_identity.clone(),
+            source_workload: baggage.workload_name.clone(),
+            source_namespace: baggage.namespace.clone(),
+            trace_id: Some(id.to_string()),
+            ..Default::default()
+        };
 
         let source = match from_gateway {
             true => None, // we cannot lookup source workload since we don't know the network, see https://github.com/istio/ztunnel/issues/515
@@ -358,6 +413,7 @@ impl Inbound {
             ..Default::default()
         };
         let ds = proxy::guess_inbound_service(&rbac_ctx.conn, upstream_service, &upstream);
+        tlvs.destination_service = ds.as_ref().map(|s| s.hostname.clone());
         let connection_metrics = ConnectionOpen {
             reporter: Reporter::destination,
             source,
@@ -366,30 +422,60 @@ impl Inbound {
             connection_security_policy: metrics::SecurityPolicy::mutual_tls,
             destination_service: ds,
         };
-        let result_tracker = metrics::ConnectionResult::new(
+        let circuit_guard = match proxy::check_circuit_breakers(&pi, &connection_metrics) {
+            Ok(guard) => guard,
+            Err(err) => {
+                metrics::log_early_deny(
+                    &pi.access_log,
+                    rbac_ctx.conn.src,
+                    rbac_ctx.conn.dst,
+                    Reporter::destination,
+                    err,
+                );
+                return StatusCode::SERVICE_UNAVAILABLE;
+            }
+        };
+        let shaper = pi.bandwidth.shaper(
+            connection_metrics.source.as_ref(),
+            connection_metrics
+                .destination_service
+                .as_ref()
+                .map(|s| s.hostname.as_str()),
+            StreamSide::Destination,
+        );
+        let mut result_tracker = metrics::ConnectionResult::new(
             rbac_ctx.conn.src,
             rbac_ctx.conn.dst,
             Some(hbone_addr),
             start,
             &connection_metrics,
             pi.metrics,
-        );
+            pi.access_log.clone(),
+        )
+        .with_trace(trace.clone());
+        let limits = ConnectionLimits::new(&pi.cfg)
+            .with_bandwidth(shaper)
+            .with_live_metrics(result_tracker.live());
 
         //register before assert_rbac to ensure the connection is tracked during it's entire valid span
         connection_manager.register(&rbac_ctx);
-        if !pi.state.assert_rbac(&rbac_ctx).await {
-            info!(%rbac_ctx.conn, "RBAC rejected");
+        let mut rbac_phase = trace.phase("rbac");
+        let decision = pi.state.assert_rbac(&rbac_ctx).await;
+        rbac_phase.set_attribute(
+            "rbac.decision",
+            if decision.allowed { "allow" } else { "deny" },
+        );
+        drop(rbac_phase);
+        result_tracker = result_tracker.with_rbac_decision(&decision);
+        if !decision.allowed {
+            info!(%rbac_ctx.conn, %decision, "RBAC rejected");
             connection_manager.release(&rbac_ctx);
             result_tracker.record(Err(Error::AuthorizationPolicyRejection));
             return StatusCode::UNAUTHORIZED;
         }
 
         let request_type = match inbound_protocol {
-            AppProtocol::PROXY => Proxy(
-                req,
-                (rbac_ctx.conn.src, rbac_ctx.conn.dst),
-                rbac_ctx.conn.src_identity.clone(),
-            ),
+            AppProtocol::PROXY => Proxy(req, (rbac_ctx.conn.src, rbac_ctx.conn.dst), tlvs),
             _ => Hbone(req),
         };
 
@@ -401,6 +487,8 @@ impl Inbound {
             pi.socket_factory.as_ref(),
             connection_manager,
             rbac_ctx,
+            limits,
+            circuit_guard,
         )
         .in_current_span()
         .await
@@ -412,6 +500,7 @@ impl Inbound {
 
     async fn find_inbound_upstream(
         state: DemandProxyState,
+        mode: InboundMode,
         conn: &Connection,
         hbone_addr: SocketAddr,
     ) -> Result<(SocketAddr, AppProtocol, Workload, Vec<Service>), Error> {
@@ -420,22 +509,24 @@ impl Inbound {
             address: hbone_addr.ip(),
         };
 
-        let (upstream_addr, upstream, services) = if conn.dst.ip() == hbone_addr.ip() {
-            // If the IPs match, this is not sandwich.
-            let Some((us_wl, us_svc)) = state.fetch_workload_services(dst).await else {
-                return Err(Error::UnknownDestination(hbone_addr.ip()));
+        let (upstream_addr, upstream, services) =
+            if conn.dst.ip() == hbone_addr.ip() || mode == InboundMode::SingleTls {
+                // If the IPs match, this is not sandwich.
+                // As a single TLS gateway, we forward to the HBONE target rather than ourselves.
+                let Some((us_wl, us_svc)) = state.fetch_workload_services(dst).await else {
+                    return Err(Error::UnknownDestination(hbone_addr.ip()));
+                };
+                (hbone_addr, us_wl, us_svc)
+            } else if let Some((us_wl, us_svc)) =
+                // For sandwich, we redirect the connection to target this waypoint instance
+                // and the HBONE target remains the same. Walk the WDS graph to see if they're related.
+                Self::find_sandwich_upstream(state, conn, hbone_addr).await
+            {
+                let next_hop = SocketAddr::new(conn.dst.ip(), hbone_addr.port());
+                (next_hop, us_wl, us_svc)
+            } else {
+                return Err(Error::IPMismatch(conn.dst.ip(), hbone_addr.ip()));
             };
-            (hbone_addr, us_wl, us_svc)
-        } else if let Some((us_wl, us_svc)) =
-            // For sandwich, we redirect the connection to target this waypoint instance
-            // and the HBONE target remains the same. Walk the WDS graph to see if they're related.
-            Self::find_sandwich_upstream(state, conn, hbone_addr).await
-        {
-            let next_hop = SocketAddr::new(conn.dst.ip(), hbone_addr.port());
-            (next_hop, us_wl, us_svc)
-        } else {
-            return Err(Error::IPMismatch(conn.dst.ip(), hbone_addr.ip()));
-        };
 
         // Application tunnel may override the port.
         let (upstream_addr, inbound_protocol) = match upstream.application_tunnel.clone() {
@@ -467,9 +558,7 @@ impl Inbound {
             address: hbone_addr.ip(),
         };
 
-        // Outer option tells us whether or not we can retry
-        // Some(None) means we have enough information to decide this isn't sandwich
-        let lookup = || -> Option<Option<(Workload, Vec<Service>)>> {
+        let lookup = || {
             let state = state.read();
 
             // TODO Allow HBONE address to be a hostname. We have to respect rules about
@@ -478,57 +567,54 @@ impl Inbound {
 
             // We can only sandwich a Workload waypoint
             let conn_wl = state.workloads.find_address(connection_dst);
+            (hbone_target, conn_wl)
+        };
 
-            // on-demand fetch then retry
-            let (Some(hbone_target), Some(conn_wl)) = (hbone_target, conn_wl) else {
-                return None;
-            };
-
-            let Some(target_waypoint) = (match hbone_target {
-                Address::Service(svc) => svc.waypoint.clone(),
-                Address::Workload(wl) => wl.waypoint,
-            }) else {
-                // can't sandwich if the HBONE target doesn't want a Waypoint.
-                return Some(None);
-            };
-
-            // Resolve the reference from our HBONE target
-            let target_waypoint = state.find_destination(&target_waypoint.destination);
-
-            let Some(target_waypoint) = target_waypoint else {
-                // don't need to fetch/retry this; we found conn_wl and this must match conn_wl.
-                return Some(None);
-            };
+        let (hbone_target, conn_wl) = match lookup() {
+            (Some(hbone_target), Some(conn_wl)) => (hbone_target, conn_wl),
+            _ => {
+                // on-demand fetch then retry
+                tokio::join![
+                    state.fetch_on_demand(connection_dst.to_string()),
+                    state.fetch_on_demand(hbone_dst.to_string()),
+                ];
+                let (Some(hbone_target), Some(conn_wl)) = lookup() else {
+                    return None;
+                };
+                (hbone_target, conn_wl)
+            }
+        };
 
-            // Validate that the HBONE target references the Waypoint we're connecting to
-            Some(match target_waypoint {
-                Address::Service(svc) => {
-                    if !svc.contains_endpoint(&conn_wl, Some(connection_dst)) {
-                        // target points to a different waypoint
-                        return Some(None);
-                    }
-                    Some((conn_wl, vec![*svc]))
+        // can't sandwich if the HBONE target doesn't want a Waypoint.
+        let target_waypoint = match hbone_target {
+            Address::Service(svc) => svc.waypoint,
+            Address::Workload(wl) => wl.waypoint,
+        }?;
+
+        // Resolve the reference from our HBONE target. Waypoints may be referenced by hostname,
+        // which we may need to fetch on-demand.
+        let target_waypoint = state
+            .fetch_destination(&target_waypoint.destination)
+            .await?;
+
+        // Validate that the HBONE target references the Waypoint we're connecting to
+        match target_waypoint {
+            Address::Service(svc) => {
+                if !svc.contains_endpoint(&conn_wl, Some(connection_dst)) {
+                    // target points to a different waypoint
+                    return None;
                 }
-                Address::Workload(wl) => {
-                    if !wl.workload_ips.contains(&conn.dst.ip()) {
-                        // target points to a different waypoint
-                        return Some(None);
-                    }
-                    let svc = state.services.get_by_workload(&wl);
-                    Some((*wl, svc))
+                Some((conn_wl, vec![*svc]))
+            }
+            Address::Workload(wl) => {
+                if !wl.workload_ips.contains(&conn.dst.ip()) {
+                    // target points to a different waypoint
+                    return None;
                 }
-            })
-        };
-
-        if let Some(res) = lookup() {
-            return res;
+                let svc = state.read().services.get_by_workload(&wl);
+                Some((*wl, svc))
+            }
         }
-
-        tokio::join![
-            state.fetch_on_demand(connection_dst.to_string()),
-            state.fetch_on_demand(hbone_dst.to_string()),
-        ];
-        lookup().flatten()
     }
 }
 
@@ -546,12 +632,8 @@ impl<'a, T: Display> Display for OptionDisplay<'a, T> {
 pub(super) enum InboundConnect {
     /// Hbone is a standard HBONE request coming from the network.
     Hbone(Request<Incoming>),
-    // PROXY adds source and dest headers and source identity before forwarding bytes.
-    Proxy(
-        Request<Incoming>,
-        (SocketAddr, SocketAddr),
-        Option<Identity>,
-    ),
+    // PROXY adds source and dest headers and connection attributes before forwarding bytes.
+    Proxy(Request<Incoming>, (SocketAddr, SocketAddr), ProxyTlvs),
 }
 
 #[derive(Clone)]
@@ -588,7 +670,7 @@ impl crate::tls::ServerCertProvider for InboundCertProvider {
 
 #[cfg(test)]
 mod tests {
-    use super::Inbound;
+    use super::{Inbound, InboundMode};
 
     use std::{
         net::SocketAddr,
@@ -661,6 +743,7 @@ mod tests {
         };
         let res = Inbound::find_inbound_upstream(
             state,
+            InboundMode::Mtls,
             &conn,
             format!("{hbone_dst}:{TARGET_PORT}").parse().unwrap(),
         )
@@ -677,6 +760,30 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_find_inbound_upstream_single_tls() {
+        let state = test_state(Waypoint::None).expect("state setup");
+        // As a gateway, the connection is addressed to us rather than the HBONE target
+        let conn = Connection {
+            src_identity: None,
+            src: format!("{CLIENT_POD_IP}:1234").parse().unwrap(),
+            dst_network: "".to_string(),
+            dst: "10.0.0.100:15003".parse().unwrap(),
+        };
+        let hbone_addr: SocketAddr = format!("{SERVER_POD_IP}:{TARGET_PORT}").parse().unwrap();
+        let res = Inbound::find_inbound_upstream(
+            state.clone(),
+            InboundMode::SingleTls,
+            &conn,
+            hbone_addr,
+        )
+        .await;
+        assert_eq!(res.expect("found upstream").0, hbone_addr);
+
+        let res = Inbound::find_inbound_upstream(state, InboundMode::Mtls, &conn, hbone_addr).await;
+        res.expect_err("mTLS requires the HBONE target to match the connection");
+    }
+
     fn test_state(server_waypoint: Waypoint) -> anyhow::Result<state::DemandProxyState> {
         let mut state = state::ProxyState::default();
 
@@ -20,10 +20,12 @@ use tokio::net::{TcpListener, TcpStream};
 use tracing::{error, info, trace, Instrument};
 
 use crate::config::ProxyMode;
+use crate::proxy::bandwidth::StreamSide;
 use crate::proxy::connection_manager::ConnectionManager;
+use crate::proxy::limits::ConnectionLimits;
 use crate::proxy::metrics::Reporter;
 use crate::proxy::Error;
-use crate::proxy::{metrics, util, ProxyInputs};
+use crate::proxy::{metrics, proxy_protocol, util, ProxyInputs};
 use crate::rbac;
 use crate::state::workload::NetworkAddress;
 use crate::{proxy, socket};
@@ -107,15 +109,41 @@ impl InboundPassthrough {
 
     async fn proxy_inbound_plaintext(
         pi: ProxyInputs,
-        source_addr: SocketAddr,
+        peer_addr: SocketAddr,
         mut inbound_stream: TcpStream,
         connection_manager: ConnectionManager,
     ) {
         let start = Instant::now();
         let dest_addr = socket::orig_dst_addr_or_default(&inbound_stream);
+        // Behind a trusted load balancer, the real client address is carried in a PROXY protocol
+        // header. It is used as the source for policy, workload lookup and metrics, but the
+        // upstream connection is still bound to the peer that actually connected to us.
+        let source_addr =
+            if proxy_protocol::is_trusted(&pi.cfg.proxy_protocol_trusted_cidrs, peer_addr.ip()) {
+                match proxy_protocol::read_source(&mut inbound_stream).await {
+                    Ok(Some(client)) => {
+                        trace!(peer=%peer_addr, %client, "decoded PROXY protocol source");
+                        socket::to_canonical(client)
+                    }
+                    Ok(None) => peer_addr,
+                    Err(e) => {
+                        metrics::log_early_deny(
+                            &pi.access_log,
+                            peer_addr,
+                            dest_addr,
+                            Reporter::destination,
+                            e,
+                        );
+                        return;
+                    }
+                }
+            } else {
+                peer_addr
+            };
         // Check if it is a recursive call when proxy mode is Node.
         if pi.cfg.proxy_mode == ProxyMode::Shared && Some(dest_addr.ip()) == pi.cfg.local_ip {
             metrics::log_early_deny(
+                &pi.access_log,
                 source_addr,
                 dest_addr,
                 Reporter::destination,
@@ -131,6 +159,7 @@ impl InboundPassthrough {
             pi.state.fetch_workload_services(&network_addr).await
         else {
             metrics::log_early_deny(
+                &pi.access_log,
                 source_addr,
                 dest_addr,
                 Reporter::destination,
@@ -176,18 +205,32 @@ impl InboundPassthrough {
             connection_security_policy: metrics::SecurityPolicy::unknown,
             destination_service: ds,
         };
-        let result_tracker = metrics::ConnectionResult::new(
+        let shaper = pi.bandwidth.shaper(
+            connection_metrics.source.as_ref(),
+            connection_metrics
+                .destination_service
+                .as_ref()
+                .map(|s| s.hostname.as_str()),
+            StreamSide::Destination,
+        );
+        let mut result_tracker = metrics::ConnectionResult::new(
             source_addr,
             dest_addr,
             None,
             start,
             &connection_metrics,
             pi.metrics,
+            pi.access_log.clone(),
         );
+        let limits = ConnectionLimits::new(&pi.cfg)
+            .with_bandwidth(shaper)
+            .with_live_metrics(result_tracker.live());
 
         //register before assert_rbac to ensure the connection is tracked during it's entire valid span
         connection_manager.register(&rbac_ctx);
-        if !pi.state.assert_rbac(&rbac_ctx).await {
+        let decision = pi.state.assert_rbac(&rbac_ctx).await;
+        result_tracker = result_tracker.with_rbac_decision(&decision);
+        if !decision.allowed {
             connection_manager.release(&rbac_ctx);
             result_tracker.record(Err(Error::AuthorizationPolicyRejection));
             return;
@@ -202,7 +245,7 @@ impl InboundPassthrough {
         };
 
         let orig_src = if pi.cfg.enable_original_source.unwrap_or_default() {
-            Some(source_addr.ip())
+            Some(peer_addr.ip())
         } else {
             None
         };
@@ -216,7 +259,7 @@ impl InboundPassthrough {
                     .map_err(Error::ConnectionFailed)?;
 
             trace!(%source_addr, destination=%dest_addr, component="inbound plaintext", "connected");
-            proxy::relay(&mut outbound, &mut inbound_stream).await
+            proxy::relay(&mut outbound, &mut inbound_stream, &limits).await
         };
 
         let res = tokio::select! {
@@ -224,7 +267,10 @@ impl InboundPassthrough {
                 connection_manager.release(&rbac_ctx);
                 res
             }
-            _signaled = close.signaled() => Err(Error::AuthorizationPolicyLateRejection)
+            decision = close.signaled() => {
+                result_tracker.set_late_rejection(decision);
+                Err(Error::AuthorizationPolicyLateRejection)
+            }
         };
         result_tracker.record(res);
     }
@@ -0,0 +1,313 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::Instant;
+
+use crate::config::Config;
+use crate::proxy::bandwidth::Shaper;
+use crate::proxy::metrics::LiveConnection;
+use crate::proxy::Error;
+
+/// ConnectionLimits bounds how long a proxied connection may stay open, and how fast it may transfer
+/// data. It also controls how the connection's progress is reported while it is open.
+#[derive(Clone, Default)]
+pub struct ConnectionLimits {
+    /// Close the connection once no data has been transferred in either direction for this long.
+    pub idle_timeout: Option<Duration>,
+    /// Close the connection once it has been open for this long.
+    pub max_duration: Option<Duration>,
+    /// Bandwidth limits, applied to the stream the connection is relayed from.
+    pub bandwidth: Option<Shaper>,
+    /// How often the bytes transferred are recorded to `live`, if set.
+    pub metrics_interval: Option<Duration>,
+    pub live: Option<LiveConnection>,
+}
+
+impl ConnectionLimits {
+    pub fn new(cfg: &Config) -> Self {
+        ConnectionLimits {
+            idle_timeout: cfg.connection_idle_timeout,
+            max_duration: cfg.connection_max_duration,
+            bandwidth: None,
+            metrics_interval: cfg.connection_metrics_interval,
+            live: None,
+        }
+    }
+
+    pub fn with_bandwidth(mut self, shaper: Option<Shaper>) -> Self {
+        self.bandwidth = shaper;
+        self
+    }
+
+    pub fn with_live_metrics(mut self, live: LiveConnection) -> Self {
+        self.live = Some(live);
+        self
+    }
+
+    /// live_metrics returns where to periodically record the bytes transferred, and how often.
+    /// If None, the connection's bytes only need to be known once it completes.
+    pub fn live_metrics(&self) -> Option<(&LiveConnection, Duration)> {
+        Some((self.live.as_ref()?, self.metrics_interval?))
+    }
+
+    /// enforce drives `copy` to completion, unless one of the limits is hit first.
+    /// `copy` is expected to update `activity` as it transfers data, which is periodically recorded
+    /// to metrics if `live_metrics` is set.
+    pub async fn enforce<F>(&self, activity: &Activity, copy: F) -> Result<(u64, u64), Error>
+    where
+        F: Future<Output = Result<(u64, u64), Error>>,
+    {
+        let max_duration = async {
+            match self.max_duration {
+                Some(d) => {
+                    tokio::time::sleep_until(activity.start + d).await;
+                    d
+                }
+                None => std::future::pending().await,
+            }
+        };
+        let idle = async {
+            match self.idle_timeout {
+                Some(t) => loop {
+                    let deadline = activity.last() + t;
+                    if deadline <= Instant::now() {
+                        return t;
+                    }
+                    tokio::time::sleep_until(deadline).await;
+                },
+                None => std::future::pending().await,
+            }
+        };
+        let report = async {
+            match self.live_metrics() {
+                Some((live, interval)) => {
+                    let mut ticker = tokio::time::interval_at(activity.start + interval, interval);
+                    loop {
+                        ticker.tick().await;
+                        let (read, written) = activity.transferred();
+                        live.record_bytes(read, written);
+                    }
+                }
+                None => std::future::pending::<()>().await,
+            }
+        };
+        let res = tokio::select! {
+            res = copy => res,
+            d = max_duration => Err(Error::MaxDurationExceeded(d)),
+            t = idle => Err(Error::IdleTimeout(t)),
+            _ = report => unreachable!("reporting never completes"),
+        };
+        if let Some((live, _)) = self.live_metrics() {
+            // Record any bytes since the last report, even if the connection failed. If it succeeded,
+            // the caller records the final totals, which only adds what is missing.
+            let (read, written) = activity.transferred();
+            live.record_bytes(read, written);
+        }
+        res
+    }
+}
+
+/// Activity records when data was last transferred on a connection, and how much.
+#[derive(Debug)]
+pub struct Activity {
+    start: Instant,
+    // Time of the last transfer, in milliseconds since start
+    last: AtomicU64,
+    // Bytes read from and written to the tracked stream
+    read: AtomicU64,
+    written: AtomicU64,
+}
+
+impl Default for Activity {
+    fn default() -> Self {
+        Activity {
+            start: Instant::now(),
+            last: AtomicU64::new(0),
+            read: AtomicU64::new(0),
+            written: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Activity {
+    /// record notes a transfer of `read` bytes from, and `written` bytes to, the tracked stream.
+    pub fn record(&self, read: u64, written: u64) {
+        self.read.fetch_add(read, Ordering::Relaxed);
+        self.written.fetch_add(written, Ordering::Relaxed);
+        self.last
+            .store(self.start.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// transferred returns the total bytes read from and written to the tracked stream.
+    pub fn transferred(&self) -> (u64, u64) {
+        (
+            self.read.load(Ordering::Relaxed),
+            self.written.load(Ordering::Relaxed),
+        )
+    }
+
+    fn last(&self) -> Instant {
+        self.start + Duration::from_millis(self.last.load(Ordering::Relaxed))
+    }
+}
+
+/// TrackedIo wraps a stream, recording activity whenever data is read from or written to it.
+pub struct TrackedIo<'a, S> {
+    inner: S,
+    activity: &'a Activity,
+}
+
+impl<'a, S> TrackedIo<'a, S> {
+    pub fn new(inner: S, activity: &'a Activity) -> Self {
+        TrackedIo { inner, activity }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for TrackedIo<'_, S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let n = buf.filled().len() - before;
+        if n > 0 {
+            self.activity.record(n as u64, 0);
+        }
+        res
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for TrackedIo<'_, S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let res = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n @ 1..)) = res {
+            self.activity.record(0, n as u64);
+        }
+        res
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::metrics::{CommonTrafficLabels, Metrics};
+    use prometheus_client::registry::Registry;
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_timeout() {
+        let limits = ConnectionLimits {
+            idle_timeout: Some(Duration::from_secs(10)),
+            ..Default::default()
+        };
+        let activity = Activity::default();
+        let (client, mut server) = tokio::io::duplex(64);
+        let mut client = TrackedIo::new(client, &activity);
+        let copy = async {
+            // Keep the connection active for a while, then go quiet
+            for _ in 0..5 {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                client.write_all(b"ping").await?;
+                let mut buf = [0; 4];
+                server.read_exact(&mut buf).await?;
+            }
+            std::future::pending::<()>().await;
+            Ok::<_, Error>((0, 0))
+        };
+        let start = Instant::now();
+        let res = limits.enforce(&activity, copy).await;
+        assert!(matches!(res, Err(Error::IdleTimeout(_))), "{res:?}");
+        assert_eq!(start.elapsed(), Duration::from_secs(35));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn max_duration() {
+        let limits = ConnectionLimits {
+            idle_timeout: Some(Duration::from_secs(10)),
+            max_duration: Some(Duration::from_secs(30)),
+            ..Default::default()
+        };
+        let activity = Activity::default();
+        let mut client = TrackedIo::new(tokio::io::sink(), &activity);
+        let copy = async {
+            // The connection never goes idle, but runs for longer than allowed
+            for _ in 0..60 {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                client.write_all(b"ping").await?;
+            }
+            Ok::<_, Error>((0, 0))
+        };
+        let start = Instant::now();
+        let res = limits.enforce(&activity, copy).await;
+        assert!(matches!(res, Err(Error::MaxDurationExceeded(_))), "{res:?}");
+        assert_eq!(start.elapsed(), Duration::from_secs(30));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn live_metrics() {
+        let metrics = Arc::new(Metrics::new(&mut Registry::default()));
+        let tl = CommonTrafficLabels::default();
+        let limits = ConnectionLimits {
+            metrics_interval: Some(Duration::from_secs(10)),
+            live: Some(LiveConnection::new(metrics.clone(), tl.clone())),
+            ..Default::default()
+        };
+        // The default labels are for the source, which flips sent and received
+        let sent = || metrics.sent_bytes.get_or_create(&tl).get();
+        let open = || metrics.connections_open.get_or_create(&tl).get();
+        let activity = Activity::default();
+        let mut client = TrackedIo::new(tokio::io::sink(), &activity);
+        let copy = async {
+            client.write_all(b"ping").await?;
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            assert_eq!(sent(), 0);
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            assert_eq!(sent(), 4);
+            client.write_all(b"ping").await?;
+            Ok::<_, Error>((0, 8))
+        };
+        let res = limits.enforce(&activity, copy).await;
+        assert_eq!(res.unwrap(), (0, 8));
+        // Remaining bytes are recorded once complete, and the final totals don't double count
+        assert_eq!(sent(), 8);
+        limits.live.as_ref().unwrap().record_bytes(0, 8);
+        assert_eq!(sent(), 8);
+        assert_eq!(open(), 1);
+        drop(limits);
+        assert_eq!(open(), 0);
+    }
+}
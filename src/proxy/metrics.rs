@@ -14,27 +14,48 @@
 
 use std::fmt::Write;
 use std::net::SocketAddr;
-use std::sync::Arc;
-use std::time::Instant;
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
 
-use prometheus_client::encoding::{EncodeLabelSet, EncodeLabelValue, LabelValueEncoder};
+use prometheus_client::collector::Collector;
+use prometheus_client::encoding::{
+    DescriptorEncoder, EncodeLabelSet, EncodeLabelValue, EncodeMetric, LabelValueEncoder,
+};
 use prometheus_client::metrics::counter::Counter;
 use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::{ConstGauge, Gauge};
 use prometheus_client::registry::Registry;
 
-use tracing::event;
+use tracing::{event, warn};
 
 use crate::identity::Identity;
 use crate::metrics::{DefaultedUnknown, DeferRecorder, Deferred, IncrementRecorder, Recorder};
+use crate::proxy::access_log;
+use crate::proxy::connection_manager::ConnectionKey;
+use crate::proxy::otel::ConnectionTrace;
+use crate::rbac;
 
+use crate::state::outlier::OutlierDetector;
 use crate::state::service::ServiceDescription;
 use crate::state::workload::Workload;
 
 pub struct Metrics {
     pub connection_opens: Family<CommonTrafficLabels, Counter>,
     pub connection_close: Family<CommonTrafficLabels, Counter>,
+    pub connections_open: Family<CommonTrafficLabels, Gauge>,
     pub received_bytes: Family<CommonTrafficLabels, Counter>,
     pub sent_bytes: Family<CommonTrafficLabels, Counter>,
+    pub connection_attempts: Family<ConnectionAttemptLabels, Counter>,
+    pub connection_terminations: Family<ConnectionTerminationLabels, Counter>,
+    pub connections_audited: Family<ConnectionAuditLabels, Counter>,
+    pub shadow_decisions: Family<ShadowDecisionLabels, Counter>,
+    pub connections_denied: Family<ConnectionDenialLabels, Counter>,
+    pub circuit_breaker_rejections: Family<CircuitBreakerLabels, Counter>,
+    pub bandwidth_throttled: Family<BandwidthThrottleLabels, Counter<f64, AtomicU64>>,
+    pub socks5_auth_failures: Family<Socks5AuthFailureLabels, Counter>,
+    pub outlier_ejections: Counter,
+    pub udp_flows_refused: Counter,
 
     // on-demand DNS is not a part of DNS proxy, but part of ztunnel proxy itself
     pub on_demand_dns: Family<OnDemandDnsLabels, Counter>,
@@ -232,6 +253,167 @@ pub struct CommonTrafficLabels {
     connection_security_policy: SecurityPolicy,
 }
 
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum AttemptResult {
+    success,
+    failure,
+}
+
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct ConnectionAttemptLabels {
+    #[prometheus(flatten)]
+    common: CommonTrafficLabels,
+    result: AttemptResult,
+}
+
+/// TerminationReason describes why ztunnel closed a connection on its own.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum TerminationReason {
+    idle_timeout,
+    max_duration,
+}
+
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct ConnectionTerminationLabels {
+    #[prometheus(flatten)]
+    common: CommonTrafficLabels,
+    reason: TerminationReason,
+}
+
+/// PolicyTargetLabels identify the workload that authorization policy was applied for. Unlike
+/// CommonTrafficLabels, they leave out the source, so that metrics which are also labeled by policy
+/// stay bounded.
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct PolicyTargetLabels {
+    reporter: Reporter,
+    destination_workload: DefaultedUnknown<String>,
+    destination_workload_namespace: DefaultedUnknown<String>,
+}
+
+impl From<&CommonTrafficLabels> for PolicyTargetLabels {
+    fn from(tl: &CommonTrafficLabels) -> Self {
+        PolicyTargetLabels {
+            reporter: tl.reporter,
+            destination_workload: tl.destination_workload.clone(),
+            destination_workload_namespace: tl.destination_workload_namespace.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct ConnectionAuditLabels {
+    #[prometheus(flatten)]
+    target: PolicyTargetLabels,
+    // The key of the matching AUDIT policy
+    policy: String,
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum ShadowDecision {
+    allow,
+    deny,
+}
+
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct ShadowDecisionLabels {
+    #[prometheus(flatten)]
+    target: PolicyTargetLabels,
+    decision: ShadowDecision,
+    // The key of the policy that decided the outcome, or empty if it was the default
+    policy: String,
+}
+
+/// DenialReason describes why authorization policy denied a connection.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum DenialReason {
+    /// A DENY policy matched
+    deny,
+    /// The external authorization service denied a connection matching a CUSTOM policy
+    custom,
+    /// ALLOW policies apply to the destination, but none matched
+    default_deny,
+    /// The destination workload could not be found or verified
+    unknown,
+}
+
+impl DenialReason {
+    fn of(decision: &rbac::RbacDecision) -> Self {
+        match decision.action {
+            Some(rbac::RbacAction::Deny) => DenialReason::deny,
+            Some(rbac::RbacAction::Custom) => DenialReason::custom,
+            _ if decision.default_deny => DenialReason::default_deny,
+            _ => DenialReason::unknown,
+        }
+    }
+}
+
+/// DenialStage describes when authorization policy denied a connection.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum DenialStage {
+    /// When the connection was established
+    connect,
+    /// While the connection was open, after a policy update
+    policy_update,
+}
+
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct ConnectionDenialLabels {
+    #[prometheus(flatten)]
+    target: PolicyTargetLabels,
+    reason: DenialReason,
+    stage: DenialStage,
+    // The key of the policy that denied the connection, if any
+    policy: String,
+    // The index of the rule of the policy that matched the connection, if any
+    rule: String,
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum CircuitBreakerLimit {
+    service,
+    source_workload,
+}
+
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct CircuitBreakerLabels {
+    #[prometheus(flatten)]
+    common: CommonTrafficLabels,
+    limit: CircuitBreakerLimit,
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum BandwidthScope {
+    workload,
+    service,
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum BandwidthDirection {
+    upload,
+    download,
+}
+
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct BandwidthThrottleLabels {
+    pub scope: BandwidthScope,
+    // The service hostname the limit applies to. For workload limits, this is the namespace of the
+    // workload, as workload UIDs would make the cardinality unbounded.
+    pub name: String,
+    pub direction: BandwidthDirection,
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum Socks5AuthFailure {
+    // The client did not offer username/password authentication
+    unsupported_method,
+    invalid_credentials,
+}
+
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct Socks5AuthFailureLabels {
+    pub reason: Socks5AuthFailure,
+}
+
 #[derive(Clone, Hash, Default, Debug, PartialEq, Eq, EncodeLabelSet)]
 pub struct OnDemandDnsLabels {
     // on-demand DNS client information is just nice-to-have
@@ -285,6 +467,12 @@ impl Metrics {
             "The total number of TCP connections closed",
             connection_close.clone(),
         );
+        let connections_open = Family::default();
+        registry.register(
+            "tcp_connections_open",
+            "The number of TCP connections currently open (unstable)",
+            connections_open.clone(),
+        );
 
         let received_bytes = Family::default();
         registry.register(
@@ -298,6 +486,66 @@ impl Metrics {
             "The size of total bytes sent during response in case of a TCP connection",
             sent_bytes.clone(),
         );
+        let connection_attempts = Family::default();
+        registry.register(
+            "tcp_connection_attempts",
+            "The total number of attempts to connect to an upstream, including retries (unstable)",
+            connection_attempts.clone(),
+        );
+        let connection_terminations = Family::default();
+        registry.register(
+            "tcp_connections_terminated",
+            "The total number of TCP connections closed by ztunnel for exceeding a limit (unstable)",
+            connection_terminations.clone(),
+        );
+        let connections_audited = Family::default();
+        registry.register(
+            "tcp_connections_audited",
+            "The total number of TCP connections that matched an AUDIT authorization policy (unstable)",
+            connections_audited.clone(),
+        );
+        let shadow_decisions = Family::default();
+        registry.register(
+            "tcp_connections_shadow_decisions",
+            "The total number of TCP connections evaluated with dry-run authorization policies, by the decision they would have made (unstable)",
+            shadow_decisions.clone(),
+        );
+        let connections_denied = Family::default();
+        registry.register(
+            "tcp_connections_denied",
+            "The total number of TCP connections denied by authorization policy, by the policy and rule that decided it (unstable)",
+            connections_denied.clone(),
+        );
+        let circuit_breaker_rejections = Family::default();
+        registry.register(
+            "circuit_breaker_rejections",
+            "The total number of connections rejected for exceeding a concurrent connection limit (unstable)",
+            circuit_breaker_rejections.clone(),
+        );
+        let bandwidth_throttled = Family::default();
+        registry.register(
+            "bandwidth_throttled_seconds",
+            "The total time connections were paused to stay within bandwidth limits (unstable)",
+            bandwidth_throttled.clone(),
+        );
+        let socks5_auth_failures = Family::default();
+        registry.register(
+            "socks5_auth_failures",
+            "The total number of SOCKS5 clients that failed to authenticate (unstable)",
+            socks5_auth_failures.clone(),
+        );
+        let outlier_ejections = Counter::default();
+        registry.register(
+            "outlier_ejections",
+            "The total number of service endpoints ejected by outlier detection (unstable)",
+            outlier_ejections.clone(),
+        );
+        let udp_flows_refused = Counter::default();
+        registry.register(
+            "udp_flows_refused",
+            "The total number of UDP flows refused because the destination requires HBONE or has a waypoint (unstable)",
+            udp_flows_refused.clone(),
+        );
         let on_demand_dns = Family::default();
         registry.register(
             "on_demand_dns",
@@ -314,8 +562,19 @@ impl Metrics {
         Self {
             connection_opens,
             connection_close,
+            connections_open,
             received_bytes,
             sent_bytes,
+            connection_attempts,
+            connection_terminations,
+            connections_audited,
+            shadow_decisions,
+            connections_denied,
+            circuit_breaker_rejections,
+            bandwidth_throttled,
+            socks5_auth_failures,
+            outlier_ejections,
+            udp_flows_refused,
             on_demand_dns,
             on_demand_dns_cache_misses,
         }
@@ -340,11 +599,76 @@ pub struct ConnectionResult {
     start: Instant,
     tl: CommonTrafficLabels,
     metrics: Arc<Metrics>,
+    access_log: access_log::AccessLog,
+    live: LiveConnection,
+    trace: ConnectionTrace,
+    // The authorization policy decision for the connection, if policy was evaluated
+    rbac: Option<rbac::RbacDecision>,
+    // The decision that closed the connection after a policy update, if any
+    late_rejection: Option<rbac::RbacDecision>,
+}
+
+/// LiveConnection tracks an open connection for metrics. The connection is counted as open until
+/// all handles are dropped, and the bytes it has transferred can be recorded while in progress, so
+/// that the byte counters of long-lived connections do not stay flat until they close.
+#[derive(Clone)]
+pub struct LiveConnection(Arc<LiveConnectionInner>);
+
+struct LiveConnectionInner {
+    metrics: Arc<Metrics>,
+    tl: CommonTrafficLabels,
+    // Bytes sent and received that have already been added to the counters
+    recorded: Mutex<(u64, u64)>,
+}
+
+impl LiveConnection {
+    pub fn new(metrics: Arc<Metrics>, tl: CommonTrafficLabels) -> Self {
+        metrics.connections_open.get_or_create(&tl).inc();
+        LiveConnection(Arc::new(LiveConnectionInner {
+            metrics,
+            tl,
+            recorded: Mutex::new((0, 0)),
+        }))
+    }
+
+    /// record_bytes records the total bytes sent and received by the connection so far. Only the
+    /// increase since the last call is added to the counters.
+    pub fn record_bytes(&self, sent: u64, recv: u64) {
+        let (sent, recv) = {
+            let mut recorded = self.0.recorded.lock().unwrap();
+            let delta = (
+                sent.saturating_sub(recorded.0),
+                recv.saturating_sub(recorded.1),
+            );
+            *recorded = (recorded.0.max(sent), recorded.1.max(recv));
+            delta
+        };
+        let tl = &self.0.tl;
+        let (sent, recv) = if tl.reporter == Reporter::source {
+            // Istio flips the metric for source: https://github.com/istio/istio/issues/32399
+            (recv, sent)
+        } else {
+            (sent, recv)
+        };
+        if sent != 0 {
+            self.0.metrics.sent_bytes.get_or_create(tl).inc_by(sent);
+        }
+        if recv != 0 {
+            self.0.metrics.received_bytes.get_or_create(tl).inc_by(recv);
+        }
+    }
+}
+
+impl Drop for LiveConnectionInner {
+    fn drop(&mut self) {
+        self.metrics.connections_open.get_or_create(&self.tl).dec();
+    }
 }
 
 // log_early_deny allows logging a connection is denied before we have enough information to emit proper
 // access logs/metrics
 pub fn log_early_deny<E: std::error::Error>(
+    access_log: &access_log::AccessLog,
     src: SocketAddr,
     dst: SocketAddr,
     reporter: Reporter,
@@ -358,16 +682,104 @@ pub fn log_early_deny<E: std::error::Error>(
             src.addr = %src,
             dst.addr = %dst,
 
-            direction = if reporter == Reporter::source {
-                "outbound"
-            } else {
-                "inbound"
-            },
+            direction = direction(reporter),
 
             error = %err,
 
             "connection failed"
     );
+    access_log.log(|| access_log::Entry {
+        start_time: Some(SystemTime::now()),
+        direction: direction(reporter),
+        src_addr: Some(src),
+        dst_addr: Some(dst),
+        error: Some(err.to_string()),
+        ..Default::default()
+    });
+}
+
+fn direction(reporter: Reporter) -> &'static str {
+    if reporter == Reporter::source {
+        "outbound"
+    } else {
+        "inbound"
+    }
+}
+
+/// Records the outcome of a single attempt to connect to an upstream. A connection may make multiple
+/// attempts if it fails over to other endpoints.
+pub fn record_connection_attempt<T, E>(
+    metrics: &Metrics,
+    conn: &ConnectionOpen,
+    res: &Result<T, E>,
+) {
+    let labels = ConnectionAttemptLabels {
+        common: CommonTrafficLabels::from(conn),
+        result: if res.is_ok() {
+            AttemptResult::success
+        } else {
+            AttemptResult::failure
+        },
+    };
+    metrics.connection_attempts.get_or_create(&labels).inc();
+}
+
+/// Records a connection rejected because the group `key` was at its concurrent connection limit.
+pub fn record_circuit_breaker_rejection(
+    metrics: &Metrics,
+    conn: &ConnectionOpen,
+    key: &ConnectionKey,
+) {
+    let limit = match key {
+        ConnectionKey::SourceWorkload(..) => CircuitBreakerLimit::source_workload,
+        _ => CircuitBreakerLimit::service,
+    };
+    let labels = CircuitBreakerLabels {
+        common: CommonTrafficLabels::from(conn),
+        limit,
+    };
+    metrics
+        .circuit_breaker_rejections
+        .get_or_create(&labels)
+        .inc();
+}
+
+/// record_endpoint_outcome feeds the outcome of connecting to a service endpoint into outlier
+/// detection. Only the connect phase (including the HBONE handshake) reflects the health of the
+/// endpoint, so errors once the connection is established are not recorded.
+pub fn record_endpoint_outcome(
+    metrics: &Metrics,
+    outliers: &OutlierDetector,
+    endpoint_uid: &str,
+    success: bool,
+) {
+    if success {
+        outliers.record_success(endpoint_uid);
+    } else if outliers.record_failure(endpoint_uid) {
+        warn!(
+            endpoint = endpoint_uid,
+            "ejecting endpoint after consecutive failures"
+        );
+        metrics.outlier_ejections.inc();
+    }
+}
+
+/// OutlierCollector reports the number of currently ejected endpoints when scraped, so expired
+/// ejections and removed workloads are reflected without waiting for more traffic.
+#[derive(Debug)]
+pub struct OutlierCollector(pub OutlierDetector);
+
+impl Collector for OutlierCollector {
+    fn encode(&self, mut encoder: DescriptorEncoder) -> Result<(), std::fmt::Error> {
+        let gauge = ConstGauge::new(self.0.ejected() as i64);
+        let metric_encoder = encoder.encode_descriptor(
+            "outlier_ejected_endpoints",
+            "The number of service endpoints currently ejected by outlier detection (unstable)",
+            None,
+            gauge.metric_type(),
+        )?;
+        gauge.encode(metric_encoder)
+    }
 }
 
 macro_rules! access_log {
@@ -406,6 +818,7 @@ impl ConnectionResult {
         start: Instant,
         conn: &ConnectionOpen,
         metrics: Arc<Metrics>,
+        access_log: access_log::AccessLog,
     ) -> Self {
         let tl = CommonTrafficLabels::from(conn);
         metrics.connection_opens.get_or_create(&tl).inc();
@@ -441,14 +854,11 @@ impl ConnectionResult {
             dst.namespace = tl.destination_canonical_service.as_ref(),
             dst.identity = tl.destination_principal.as_ref().filter(|_| mtls).map(|id| id.to_string()),
 
-            direction = if tl.reporter == Reporter::source {
-                "outbound"
-            } else {
-                "inbound"
-            },
+            direction = direction(tl.reporter),
 
             "connection opened"
         );
+        let live = LiveConnection::new(metrics.clone(), tl.clone());
         Self {
             src,
             dst,
@@ -456,35 +866,177 @@ impl ConnectionResult {
             start,
             tl,
             metrics,
+            access_log,
+            live,
+            trace: ConnectionTrace::default(),
+            rbac: None,
+            late_rejection: None,
         }
     }
 
-    pub fn record<E: std::error::Error>(self, res: Result<(u64, u64), E>) {
+    /// with_trace records the connection span to `trace` once the connection completes.
+    pub fn with_trace(mut self, trace: ConnectionTrace) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    /// with_rbac_decision records the parts of the authorization decision that do not affect the
+    /// connection itself: matching AUDIT policies, and the decision of dry-run policies.
+    pub fn with_rbac_decision(mut self, decision: &rbac::RbacDecision) -> Self {
+        for policy in &decision.audit_policies {
+            self.metrics
+                .connections_audited
+                .get_or_create(&ConnectionAuditLabels {
+                    target: PolicyTargetLabels::from(&self.tl),
+                    policy: policy.clone(),
+                })
+                .inc();
+        }
+        if let Some(shadow) = &decision.shadow {
+            self.metrics
+                .shadow_decisions
+                .get_or_create(&ShadowDecisionLabels {
+                    target: PolicyTargetLabels::from(&self.tl),
+                    decision: if shadow.allowed {
+                        ShadowDecision::allow
+                    } else {
+                        ShadowDecision::deny
+                    },
+                    policy: shadow.policy.clone().unwrap_or_default(),
+                })
+                .inc();
+        }
+        self.rbac = Some(decision.clone());
+        self
+    }
+
+    /// set_late_rejection records that the connection was closed after a policy update, due to
+    /// `decision`.
+    pub fn set_late_rejection(&mut self, decision: rbac::RbacDecision) {
+        self.late_rejection = Some(decision);
+    }
+
+    pub fn trace(&self) -> ConnectionTrace {
+        self.trace.clone()
+    }
+
+    /// live returns a handle to record the bytes transferred while the connection is in progress.
+    pub fn live(&self) -> LiveConnection {
+        self.live.clone()
+    }
+
+    pub fn record<E: std::error::Error + 'static>(self, res: Result<(u64, u64), E>) {
         let tl = self.tl;
 
         // Unconditionally record the connection was closed
         self.metrics.connection_close.get_or_create(&tl).inc();
 
-        // If the connection succeeded, record bytes sent/recv
+        // If we closed the connection for hitting a limit, record why
+        let termination = res
+            .as_ref()
+            .err()
+            .and_then(|e| (e as &dyn std::error::Error).downcast_ref::<super::Error>())
+            .and_then(super::Error::termination_reason);
+        if let Some(reason) = termination {
+            self.metrics
+                .connection_terminations
+                .get_or_create(&ConnectionTerminationLabels {
+                    common: tl.clone(),
+                    reason,
+                })
+                .inc();
+        }
+
+        // If authorization policy denied the connection, record what decided it
+        let denial = res
+            .as_ref()
+            .err()
+            .and_then(|e| (e as &dyn std::error::Error).downcast_ref::<super::Error>())
+            .and_then(|e| match e {
+                super::Error::AuthorizationPolicyRejection => {
+                    Some((DenialStage::connect, self.rbac.as_ref()))
+                }
+                super::Error::AuthorizationPolicyLateRejection => {
+                    Some((DenialStage::policy_update, self.late_rejection.as_ref()))
+                }
+                _ => None,
+            })
+            .map(|(stage, decision)| {
+                // The decision may be missing or stale if policy changed while the connection was set up
+                let decision = decision
+                    .filter(|d| !d.allowed)
+                    .cloned()
+                    .unwrap_or_else(rbac::RbacDecision::deny);
+                (stage, decision)
+            });
+        if let Some((stage, decision)) = &denial {
+            self.metrics
+                .connections_denied
+                .get_or_create(&ConnectionDenialLabels {
+                    target: PolicyTargetLabels::from(&tl),
+                    reason: DenialReason::of(decision),
+                    stage: *stage,
+                    policy: decision.policy.clone().unwrap_or_default(),
+                    rule: decision.rule.map(|r| r.to_string()).unwrap_or_default(),
+                })
+                .inc();
+        }
+
+        // If the connection succeeded, record the bytes sent/recv not yet recorded while it was open
         if let Ok((sent, recv)) = res {
-            let (sent, recv) = if tl.reporter == Reporter::source {
-                // Istio flips the metric for source: https://github.com/istio/istio/issues/32399
-                (recv, sent)
-            } else {
-                (sent, recv)
-            };
-            if sent != 0 {
-                self.metrics.sent_bytes.get_or_create(&tl).inc_by(sent);
-            }
-            if recv != 0 {
-                self.metrics.received_bytes.get_or_create(&tl).inc_by(recv);
-            }
+            self.live.record_bytes(sent, recv);
         }
 
+        self.trace.set_attribute("source.address", self.src.0);
+        self.trace.set_attribute("destination.address", self.dst.0);
+        if let Some(svc) = tl.destination_service.as_ref() {
+            self.trace.set_attribute("destination.service", svc);
+        }
+        if let Some(wl) = &self.dst.1 {
+            self.trace.set_attribute("destination.workload", wl);
+        }
+        self.trace.finish(res.as_ref().err().map(|e| e.to_string()));
+
         // Unconditionally write out an access log
         let mtls = tl.connection_security_policy == SecurityPolicy::mutual_tls;
+        let audit_policies = self
+            .rbac
+            .as_ref()
+            .map(|d| d.audit_policies.as_slice())
+            .unwrap_or_default();
         let bytes = res.as_ref().ok();
-        let dur = format!("{}ms", self.start.elapsed().as_millis());
+        let elapsed = self.start.elapsed();
+        let dur = format!("{}ms", elapsed.as_millis());
+        self.access_log.log(|| {
+            let error = res.as_ref().err();
+            let rejected = denial.is_some();
+            access_log::Entry {
+                start_time: SystemTime::now().checked_sub(elapsed),
+                direction: direction(tl.reporter),
+                src_addr: Some(self.src.0),
+                src_workload: self.src.1.clone(),
+                src_namespace: tl.source_workload_namespace.as_ref().cloned(),
+                src_identity: tl.source_principal.as_ref().filter(|_| mtls).cloned(),
+                dst_addr: Some(self.dst.0),
+                dst_hbone_addr: self.hbone_target,
+                dst_service: tl.destination_service.as_ref().cloned(),
+                dst_workload: self.dst.1.clone(),
+                dst_namespace: tl.destination_workload_namespace.as_ref().cloned(),
+                dst_identity: tl.destination_principal.as_ref().filter(|_| mtls).cloned(),
+                bytes_sent: bytes.map(|r| r.0),
+                bytes_recv: bytes.map(|r| r.1),
+                duration_ms: Some(elapsed.as_millis() as u64),
+                termination_reason: termination.map(|r| format!("{r:?}")),
+                // Only inbound connections are subject to authorization policy
+                policy: match (tl.reporter, rejected) {
+                    (_, true) => Some("deny"),
+                    (Reporter::destination, false) => Some("allow"),
+                    _ => None,
+                },
+                audit_policies: audit_policies.to_vec(),
+                error: error.map(|e| e.to_string()),
+            }
+        });
         // We use our own macro to allow setting the level dynamically
         access_log!(
             res,
@@ -501,16 +1053,15 @@ impl ConnectionResult {
             dst.namespace = tl.destination_canonical_service.as_ref(),
             dst.identity = tl.destination_principal.as_ref().filter(|_| mtls).map(|id| id.to_string()),
 
-            direction = if tl.reporter == Reporter::source {
-                "outbound"
-            } else {
-                "inbound"
-            },
+            direction = direction(tl.reporter),
 
             // Note: here we are *not* inverting them, which was only to comply with legacy decisions
             bytes_sent = bytes.map(|r| r.0),
             bytes_recv = bytes.map(|r| r.1),
             duration = dur,
+            termination_reason = termination.map(|r| format!("{r:?}")),
+            audit = (!audit_policies.is_empty()).then(|| audit_policies.join(",")),
+            rbac = denial.as_ref().map(|(_, d)| d.to_string()),
         );
     }
 }
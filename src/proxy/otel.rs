@@ -0,0 +1,467 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! OpenTelemetry spans for proxied connections, exported over OTLP/HTTP.
+//!
+//! A sampled connection gets a span covering its lifetime, with a child span for each phase, such as
+//! connection establishment, the HBONE handshake, the RBAC decision and the relay. The trace is
+//! propagated between ztunnels in the HBONE `traceparent` header, so the hops of a request are
+//! stitched together by the tracing backend.
+
+use std::fmt::Display;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::header::CONTENT_TYPE;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::registry::Registry;
+use prost::Message;
+use rand::Rng;
+use tokio::sync::mpsc;
+use tracing::warn;
+
+use crate::baggage::Baggage;
+use crate::config::Config;
+use crate::proxy::TraceParent;
+
+// We don't control the codegen, so disable any code warnings in the
+// proto modules.
+#[allow(warnings)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+mod proto {
+    pub mod common {
+        pub mod v1 {
+            tonic::include_proto!("opentelemetry.proto.common.v1");
+        }
+    }
+    pub mod resource {
+        pub mod v1 {
+            tonic::include_proto!("opentelemetry.proto.resource.v1");
+        }
+    }
+    pub mod trace {
+        pub mod v1 {
+            tonic::include_proto!("opentelemetry.proto.trace.v1");
+        }
+    }
+    pub mod collector {
+        pub mod trace {
+            pub mod v1 {
+                tonic::include_proto!("opentelemetry.proto.collector.trace.v1");
+            }
+        }
+    }
+}
+
+use proto::collector::trace::v1::ExportTraceServiceRequest;
+use proto::common::v1::{any_value, AnyValue, InstrumentationScope, KeyValue};
+use proto::resource::v1::Resource;
+use proto::trace::v1::{span::SpanKind, status::StatusCode, ResourceSpans, ScopeSpans, Status};
+
+// Spans waiting to be exported. Beyond this, spans are dropped rather than blocking the proxy.
+const QUEUE_SIZE: usize = 4096;
+// Spans are sent in batches of up to this many, waiting at most BATCH_DELAY to fill a batch.
+const MAX_BATCH_SIZE: usize = 512;
+const BATCH_DELAY: Duration = Duration::from_secs(5);
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The sampled flag of a traceparent
+const FLAG_SAMPLED: u8 = 0x01;
+
+/// Exporter sends finished spans to the collector. It is cheap to clone; the default value has no
+/// collector, so no connection is sampled.
+#[derive(Clone, Default)]
+pub struct Exporter(Option<Arc<ExporterInner>>);
+
+struct ExporterInner {
+    tx: mpsc::Sender<SpanData>,
+    sampling_ratio: f64,
+    dropped: Counter,
+}
+
+impl Exporter {
+    /// Sets up span export from `cfg`, if an endpoint is configured. Must be called from within a
+    /// tokio runtime.
+    pub fn new(cfg: &Config, registry: &mut Registry) -> anyhow::Result<Exporter> {
+        let Some(endpoint) = &cfg.otlp_traces_endpoint else {
+            return Ok(Exporter::default());
+        };
+        let endpoint: hyper::Uri = endpoint.parse()?;
+        let mut resource = vec![
+            attribute("service.name", "ztunnel"),
+            attribute("k8s.cluster.name", &cfg.cluster_id),
+        ];
+        if let Some(node) = &cfg.local_node {
+            resource.push(attribute("k8s.node.name", node));
+        }
+        let dropped = Counter::default();
+        registry.register(
+            "trace_spans_dropped",
+            "The total number of spans dropped because the export queue was full (unstable)",
+            dropped.clone(),
+        );
+        let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+        tokio::spawn(run_export(rx, endpoint, resource));
+        Ok(Exporter(Some(Arc::new(ExporterInner {
+            tx,
+            sampling_ratio: cfg.trace_sampling_ratio,
+            dropped,
+        }))))
+    }
+
+    fn export(&self, span: SpanData) {
+        let Some(exporter) = &self.0 else { return };
+        if exporter.tx.try_send(span).is_err() {
+            exporter.dropped.inc();
+        }
+    }
+}
+
+/// ConnectionTrace records the spans of a proxied connection. Connections that are not sampled get
+/// an empty trace, for which recording does nothing.
+#[derive(Clone, Default)]
+pub struct ConnectionTrace(Option<Arc<Trace>>);
+
+struct Trace {
+    exporter: Exporter,
+    trace_id: u128,
+    span_id: u64,
+    // The connection span, until it is finished
+    span: Mutex<Option<SpanData>>,
+}
+
+impl ConnectionTrace {
+    /// Starts the trace of an outbound connection, subject to the sampling ratio. If sampled, `id`
+    /// is marked as such, with the connection span as the parent, so the destination continues it.
+    pub fn outbound(exporter: &Exporter, id: &mut TraceParent) -> Self {
+        let Some(inner) = &exporter.0 else {
+            return Self::default();
+        };
+        if !rand::thread_rng().gen_bool(inner.sampling_ratio) {
+            return Self::default();
+        }
+        id.flags |= FLAG_SAMPLED;
+        Self::start(
+            exporter,
+            "outbound",
+            SpanKind::Client,
+            id.trace_id,
+            id.parent_id,
+            None,
+        )
+    }
+
+    /// Starts the trace of an inbound connection, continuing the trace in `id` if, and only if,
+    /// the sender sampled it.
+    pub fn inbound(exporter: &Exporter, id: &TraceParent) -> Self {
+        if exporter.0.is_none() || id.flags & FLAG_SAMPLED == 0 {
+            return Self::default();
+        }
+        Self::start(
+            exporter,
+            "inbound",
+            SpanKind::Server,
+            id.trace_id,
+            rand::thread_rng().gen(),
+            Some(id.parent_id),
+        )
+    }
+
+    fn start(
+        exporter: &Exporter,
+        name: &'static str,
+        kind: SpanKind,
+        trace_id: u128,
+        span_id: u64,
+        parent_id: Option<u64>,
+    ) -> Self {
+        let span = SpanData::new(name, kind, trace_id, span_id, parent_id);
+        ConnectionTrace(Some(Arc::new(Trace {
+            exporter: exporter.clone(),
+            trace_id,
+            span_id,
+            span: Mutex::new(Some(span)),
+        })))
+    }
+
+    pub fn set_attribute(&self, key: &str, value: impl ToString) {
+        let Some(trace) = &self.0 else { return };
+        if let Some(span) = trace.span.lock().unwrap().as_mut() {
+            span.attributes.push(attribute(key, value));
+        }
+    }
+
+    /// Records the source workload attributes carried in baggage.
+    pub fn set_baggage(&self, baggage: &Baggage) {
+        for (key, value) in [
+            ("source.k8s.cluster.name", &baggage.cluster_id),
+            ("source.k8s.namespace.name", &baggage.namespace),
+            ("source.k8s.workload.name", &baggage.workload_name),
+            ("source.service.name", &baggage.service_name),
+            ("source.service.version", &baggage.revision),
+        ] {
+            if let Some(value) = value {
+                self.set_attribute(key, value);
+            }
+        }
+    }
+
+    /// Starts a child span covering a phase of the connection. It ends when the Phase is dropped.
+    pub fn phase(&self, name: &'static str) -> Phase {
+        Phase(self.0.as_ref().map(|trace| {
+            let span = SpanData::new(
+                name,
+                SpanKind::Internal,
+                trace.trace_id,
+                rand::thread_rng().gen(),
+                Some(trace.span_id),
+            );
+            (span, trace.exporter.clone())
+        }))
+    }
+
+    /// Runs `fut` as a phase of the connection, which is marked as failed if it returns an error.
+    pub async fn traced<T, E: Display>(
+        &self,
+        name: &'static str,
+        fut: impl Future<Output = Result<T, E>>,
+    ) -> Result<T, E> {
+        let mut phase = self.phase(name);
+        let res = fut.await;
+        if let Err(e) = &res {
+            phase.set_error(e);
+        }
+        res
+    }
+
+    /// Ends the connection span and exports it, marked as failed if `error` is set.
+    pub fn finish(&self, error: Option<String>) {
+        let Some(trace) = &self.0 else { return };
+        if let Some(mut span) = trace.span.lock().unwrap().take() {
+            span.error = error;
+            span.end = SystemTime::now();
+            trace.exporter.export(span);
+        }
+    }
+}
+
+/// Phase is a span covering part of a connection.
+pub struct Phase(Option<(SpanData, Exporter)>);
+
+impl Phase {
+    pub fn set_attribute(&mut self, key: &str, value: impl ToString) {
+        if let Some((span, _)) = &mut self.0 {
+            span.attributes.push(attribute(key, value));
+        }
+    }
+
+    pub fn set_error(&mut self, error: impl Display) {
+        if let Some((span, _)) = &mut self.0 {
+            span.error = Some(error.to_string());
+        }
+    }
+}
+
+impl Drop for Phase {
+    fn drop(&mut self) {
+        if let Some((mut span, exporter)) = self.0.take() {
+            span.end = SystemTime::now();
+            exporter.export(span);
+        }
+    }
+}
+
+struct SpanData {
+    name: &'static str,
+    kind: SpanKind,
+    trace_id: u128,
+    span_id: u64,
+    parent_id: Option<u64>,
+    start: SystemTime,
+    end: SystemTime,
+    attributes: Vec<KeyValue>,
+    error: Option<String>,
+}
+
+impl SpanData {
+    fn new(
+        name: &'static str,
+        kind: SpanKind,
+        trace_id: u128,
+        span_id: u64,
+        parent_id: Option<u64>,
+    ) -> Self {
+        let now = SystemTime::now();
+        SpanData {
+            name,
+            kind,
+            trace_id,
+            span_id,
+            parent_id,
+            start: now,
+            end: now,
+            attributes: Vec::new(),
+            error: None,
+        }
+    }
+
+    fn into_proto(self) -> proto::trace::v1::Span {
+        let unix_nanos =
+            |t: SystemTime| t.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
+        proto::trace::v1::Span {
+            trace_id: self.trace_id.to_be_bytes().to_vec(),
+            span_id: self.span_id.to_be_bytes().to_vec(),
+            parent_span_id: self
+                .parent_id
+                .map(|id| id.to_be_bytes().to_vec())
+                .unwrap_or_default(),
+            name: self.name.to_string(),
+            kind: self.kind as i32,
+            start_time_unix_nano: unix_nanos(self.start),
+            end_time_unix_nano: unix_nanos(self.end),
+            attributes: self.attributes,
+            status: Some(match self.error {
+                Some(message) => Status {
+                    message,
+                    code: StatusCode::Error as i32,
+                },
+                None => Status {
+                    message: String::new(),
+                    code: StatusCode::Unset as i32,
+                },
+            }),
+            ..Default::default()
+        }
+    }
+}
+
+fn attribute(key: &str, value: impl ToString) -> KeyValue {
+    KeyValue {
+        key: key.to_string(),
+        value: Some(AnyValue {
+            value: Some(any_value::Value::StringValue(value.to_string())),
+        }),
+    }
+}
+
+async fn run_export(
+    mut rx: mpsc::Receiver<SpanData>,
+    endpoint: hyper::Uri,
+    resource: Vec<KeyValue>,
+) {
+    let client = crate::hyper_util::pooling_client::<Full<Bytes>>();
+    let mut batch = Vec::new();
+    while let Some(span) = rx.recv().await {
+        batch.push(span.into_proto());
+        // Wait a little for more spans, to send them together
+        let deadline = tokio::time::sleep(BATCH_DELAY);
+        tokio::pin!(deadline);
+        while batch.len() < MAX_BATCH_SIZE {
+            tokio::select! {
+                Some(span) = rx.recv() => batch.push(span.into_proto()),
+                _ = &mut deadline => break,
+            }
+        }
+        let req = ExportTraceServiceRequest {
+            resource_spans: vec![ResourceSpans {
+                resource: Some(Resource {
+                    attributes: resource.clone(),
+                }),
+                scope_spans: vec![ScopeSpans {
+                    scope: Some(InstrumentationScope {
+                        name: "ztunnel".to_string(),
+                        version: String::new(),
+                    }),
+                    spans: std::mem::take(&mut batch),
+                }],
+            }],
+        };
+        if let Err(e) = send(&client, &endpoint, req).await {
+            warn!("failed to export spans: {e}");
+        }
+    }
+}
+
+async fn send(
+    client: &Client<HttpConnector, Full<Bytes>>,
+    endpoint: &hyper::Uri,
+    req: ExportTraceServiceRequest,
+) -> anyhow::Result<()> {
+    let request = hyper::Request::post(endpoint.clone())
+        .header(CONTENT_TYPE, "application/x-protobuf")
+        .body(Full::new(Bytes::from(req.encode_to_vec())))?;
+    let response = tokio::time::timeout(EXPORT_TIMEOUT, client.request(request)).await??;
+    if !response.status().is_success() {
+        anyhow::bail!("collector returned {}", response.status());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn span_proto() {
+        let mut span = SpanData::new(
+            "relay",
+            SpanKind::Internal,
+            0x4bf92f3577b34da6a3ce929d0e0e4736,
+            0x00f067aa0ba902b7,
+            Some(0x1122334455667788),
+        );
+        span.error = Some("connection reset".to_string());
+        let span = span.into_proto();
+        assert_eq!(
+            span.trace_id,
+            [
+                0x4b, 0xf9, 0x2f, 0x35, 0x77, 0xb3, 0x4d, 0xa6, 0xa3, 0xce, 0x92, 0x9d, 0x0e, 0x0e,
+                0x47, 0x36
+            ]
+        );
+        assert_eq!(
+            span.span_id,
+            [0x00, 0xf0, 0x67, 0xaa, 0x0b, 0xa9, 0x02, 0xb7]
+        );
+        assert_eq!(
+            span.parent_span_id,
+            [0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88]
+        );
+        let status = span.status.unwrap();
+        assert_eq!(status.code, StatusCode::Error as i32);
+        assert_eq!(status.message, "connection reset");
+
+        // A connection span without a parent is a root span
+        let span = SpanData::new("outbound", SpanKind::Client, 1, 2, None).into_proto();
+        assert!(span.parent_span_id.is_empty());
+        assert_eq!(span.status.unwrap().code, StatusCode::Unset as i32);
+    }
+
+    #[test]
+    fn unsampled() {
+        // Without an exporter, nothing is sampled and the traceparent is left as is
+        let mut id = TraceParent::new();
+        id.flags = FLAG_SAMPLED;
+        let exporter = Exporter::default();
+        assert!(ConnectionTrace::inbound(&exporter, &id).0.is_none());
+        id.flags = 0;
+        assert!(ConnectionTrace::outbound(&exporter, &mut id).0.is_none());
+        assert_eq!(id.flags, 0);
+    }
+}
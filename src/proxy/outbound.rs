@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::io;
 use std::net::{IpAddr, SocketAddr};
 use std::str::FromStr;
 
@@ -23,17 +24,23 @@ use http_body_util::Empty;
 use hyper::client::conn::http2;
 use hyper::header::FORWARDED;
 
+use tokio::io::AsyncWriteExt;
 use tokio::net::{TcpListener, TcpStream};
 
 use tracing::{debug, error, info, info_span, trace_span, warn, Instrument};
 
+use crate::baggage::Baggage;
 use crate::config::ProxyMode;
 use crate::identity::Identity;
 
+use crate::proxy::bandwidth::StreamSide;
+use crate::proxy::limits::ConnectionLimits;
 use crate::proxy::metrics::Reporter;
+use crate::proxy::otel::ConnectionTrace;
 use crate::proxy::{metrics, pool, ConnectionOpen};
 use crate::proxy::{util, Error, ProxyInputs, TraceParent, BAGGAGE_HEADER, TRACEPARENT_HEADER};
 
+use crate::state::load_balancer::LoadBalanceHints;
 use crate::state::service::ServiceDescription;
 use crate::state::workload::gatewayaddress::Destination;
 use crate::state::workload::{address::Address, NetworkAddress, Protocol, Workload};
@@ -77,20 +84,27 @@ impl Outbound {
         // we can have situations where the workload is deleted, but a task is still "stuck"
         // waiting for a server response stream on a HTTP/2 connection or whatnot.
         //
-        // So use a drain to nuke tasks that might be stuck sending.
-        let (sub_drain_signal, sub_drain) = drain::channel();
-        let accept = async move {
+        // So on drain, give connections until the drain deadline to complete, then nuke any tasks
+        // that remain.
+        let Outbound {
+            pi,
+            drain,
+            listener,
+        } = self;
+        let connections = util::ConnectionDrain::default();
+        let accept = async {
             loop {
                 // Asynchronously wait for an inbound socket.
-                let socket = self.listener.accept().await;
+                let socket = listener.accept().await;
                 let start_outbound_instant = Instant::now();
-                let outbound_drain = sub_drain.clone();
-                let outer_conn_drain = sub_drain.clone();
                 match socket {
                     Ok((stream, _remote)) => {
+                        let (in_flight, outbound_drain) = connections.track();
+                        let outer_conn_drain = outbound_drain.clone();
                         let mut oc = OutboundConnection {
-                            pi: self.pi.clone(),
+                            pi: pi.clone(),
                             id: TraceParent::new(),
+                            authenticated_source: None,
                         };
                         let span = info_span!("outbound", id=%oc.id);
                         tokio::spawn(
@@ -99,10 +113,11 @@ impl Outbound {
                                 // Since this task is spawned, make sure we are guaranteed to terminate
                                 tokio::select! {
                                         _ = outbound_drain.signaled() => {
-                                            debug!("outbound drain signaled");
+                                            debug!("outbound drain deadline exceeded");
                                         }
-                                        _ = oc.proxy(stream, outer_conn_drain.clone()) => {}
+                                        _ = oc.proxy(stream, outer_conn_drain) => {}
                                 }
+                                drop(in_flight);
                                 debug!(dur=?start_outbound_instant.elapsed(), id=%oc.id, "outbound spawn DONE");
                             })
                             .instrument(span),
@@ -118,23 +133,36 @@ impl Outbound {
             }
         }.in_current_span();
 
-        // Stop accepting once we drain.
-        // Note: we are *not* waiting for all connections to be closed. In the future, we may consider
-        // this, but will need some timeout period, as we have no back-pressure mechanism on connections.
-        tokio::select! {
-            res = accept => { res }
-            _ = self.drain.signaled() => {
-                debug!("outbound drained, dropping any outbound connections");
-                sub_drain_signal.drain().await;
-                info!("outbound drained");
-            }
+        // Stop accepting once we drain, then wait for in-flight connections to complete. The
+        // listener is closed right away, so new connections are refused rather than left queued.
+        let release = tokio::select! {
+            _ = accept => return,
+            release = drain.signaled() => { release }
+        };
+        drop(listener);
+        let deadline = pi.cfg.termination_drain_duration;
+        debug!(
+            ?deadline,
+            "outbound draining, waiting for outbound connections to complete"
+        );
+        let cut_off = connections.drain(deadline).await;
+        if cut_off > 0 {
+            warn!(
+                connections = cut_off,
+                "outbound drain deadline exceeded, closed remaining connections"
+            );
         }
+        info!("outbound drained");
+        drop(release);
     }
 }
 
 pub(super) struct OutboundConnection {
     pub(super) pi: ProxyInputs,
     pub(super) id: TraceParent,
+    /// If set, the source workload the client authenticated as, rather than the one found by its
+    /// address.
+    pub(super) authenticated_source: Option<Workload>,
 }
 
 impl OutboundConnection {
@@ -144,6 +172,7 @@ impl OutboundConnection {
         let dst_addr = socket::orig_dst_addr_or_default(&source_stream);
         self.proxy_to(
             source_stream,
+            Bytes::new(),
             source_addr,
             dst_addr,
             false,
@@ -159,9 +188,13 @@ impl OutboundConnection {
     //
     // If using `proxy_to` in `tokio::spawn` tasks, it is recommended to use a drain, to guarantee termination
     // and prevent "zombie" outbound tasks.
+    //
+    // `early_data` is anything already read from `stream` that is meant for the upstream, such as
+    // data a client pipelined after an HTTP CONNECT request. It is sent before relaying the stream.
     pub async fn proxy_to_cancellable(
         &mut self,
         stream: TcpStream,
+        early_data: Bytes,
         remote_addr: SocketAddr,
         orig_dst_addr: SocketAddr,
         block_passthrough: bool,
@@ -174,12 +207,19 @@ impl OutboundConnection {
                         _ = drain.signaled() => {
                             info!("socks drain signaled");
                         }
-                        res = self.proxy_to(stream, remote_addr, orig_dst_addr, block_passthrough, Some(outer_conn_drain)) => res
+                        res = self.proxy_to(stream, early_data, remote_addr, orig_dst_addr, block_passthrough, Some(outer_conn_drain)) => res
                 }
             }
             None => {
-                self.proxy_to(stream, remote_addr, orig_dst_addr, block_passthrough, None)
-                    .await;
+                self.proxy_to(
+                    stream,
+                    early_data,
+                    remote_addr,
+                    orig_dst_addr,
+                    block_passthrough,
+                    None,
+                )
+                .await;
             }
         }
     }
@@ -187,22 +227,36 @@ impl OutboundConnection {
     async fn proxy_to(
         &mut self,
         mut source_stream: TcpStream,
+        early_data: Bytes,
         source_addr: SocketAddr,
         dest_addr: SocketAddr,
         block_passthrough: bool,
         outer_conn_drain: Option<Watch>,
     ) {
         let start = Instant::now();
+        let trace = ConnectionTrace::outbound(&self.pi.trace_exporter, &mut self.id);
         if self.pi.cfg.proxy_mode == ProxyMode::Shared
             && Some(dest_addr.ip()) == self.pi.cfg.local_ip
         {
-            metrics::log_early_deny(source_addr, dest_addr, Reporter::source, Error::SelfCall);
+            metrics::log_early_deny(
+                &self.pi.access_log,
+                source_addr,
+                dest_addr,
+                Reporter::source,
+                Error::SelfCall,
+            );
             return;
         }
-        let req = match self.build_request(source_addr.ip(), dest_addr).await {
+        let mut req = match self.build_request(source_addr.ip(), dest_addr, &[]).await {
             Ok(req) => req,
             Err(err) => {
-                metrics::log_early_deny(source_addr, dest_addr, Reporter::source, err);
+                metrics::log_early_deny(
+                    &self.pi.access_log,
+                    source_addr,
+                    dest_addr,
+                    Reporter::source,
+                    err,
+                );
                 return;
             }
         };
@@ -210,10 +264,18 @@ impl OutboundConnection {
             "request from {} to {} via {} type {:#?} dir {:#?}",
             req.source.name, dest_addr, req.gateway, req.request_type, req.direction
         );
+        trace.set_baggage(&Baggage {
+            cluster_id: Some(self.pi.cfg.cluster_id.clone()),
+            namespace: Some(req.source.namespace.clone()),
+            workload_name: Some(req.source.workload_name.clone()),
+            service_name: Some(req.source.canonical_name.clone()),
+            revision: Some(req.source.canonical_revision.clone()),
+        });
         if block_passthrough && req.destination_workload.is_none() {
             // This is mostly used by socks5. For typical outbound calls, we need to allow calls to arbitrary
             // domains. But for socks5
             metrics::log_early_deny(
+                &self.pi.access_log,
                 source_addr,
                 dest_addr,
                 Reporter::source,
@@ -221,43 +283,204 @@ impl OutboundConnection {
             );
             return;
         }
+        // Count the connection against the concurrent connection limits while it is open.
+        let mut circuit_guard =
+            match super::check_circuit_breakers(&self.pi, &Self::conn_metrics_from_request(&req)) {
+                Ok(guard) => Some(guard),
+                Err(err) => {
+                    metrics::log_early_deny(
+                        &self.pi.access_log,
+                        source_addr,
+                        dest_addr,
+                        Reporter::source,
+                        err,
+                    );
+                    return;
+                }
+            };
+
+        // Connect to the upstream. If this fails, we may retry with another endpoint of the same service.
+        let mut excluded = Vec::new();
+        let connected = loop {
+            let connect = self.connect(source_addr, outer_conn_drain.clone(), &req, &trace);
+            let res = match tokio::time::timeout(self.pi.cfg.connect_timeout, connect).await {
+                Ok(res) => res,
+                Err(e) => Err(Error::ConnectionFailed(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    e,
+                ))),
+            };
+            metrics::record_connection_attempt(
+                &self.pi.metrics,
+                &Self::conn_metrics_from_request(&req),
+                &res,
+            );
+            if let Some(uid) = Self::service_endpoint(&req) {
+                metrics::record_endpoint_outcome(
+                    &self.pi.metrics,
+                    &self.pi.state.outliers(),
+                    uid,
+                    res.is_ok(),
+                );
+            }
+            let err = match res {
+                Ok(conn) => break Ok(conn),
+                Err(err) => err,
+            };
+            match self
+                .failover_request(source_addr, dest_addr, &req, &mut excluded)
+                .await
+            {
+                Some(next) => {
+                    warn!(
+                        "failed to connect to {}: {}; retrying with {}",
+                        req.gateway, err, next.gateway
+                    );
+                    // The retry is counted against the limits of its own destination instead.
+                    circuit_guard = None;
+                    match super::check_circuit_breakers(
+                        &self.pi,
+                        &Self::conn_metrics_from_request(&next),
+                    ) {
+                        Ok(guard) => circuit_guard = Some(guard),
+                        Err(err) => break Err(err),
+                    }
+                    req = next;
+                }
+                None => break Err(err),
+            }
+        };
+        let connected = match connected {
+            Ok(mut conn) if !early_data.is_empty() => match conn.write_all(&early_data).await {
+                Ok(()) => Ok(conn),
+                Err(e) => Err(Error::Io(e)),
+            },
+            res => res,
+        };
+
         let connection_metrics = Self::conn_metrics_from_request(&req);
 
         let metrics = self.pi.metrics.clone();
-        let hbone_target = if req.request_type == RequestType::ToServerWaypoint {
-            Some(req.destination)
-        } else {
-            None
-        };
+        let (dst, hbone_target) = Self::reported_destination(&req);
         let result_tracker = metrics::ConnectionResult::new(
             source_addr,
-            req.gateway,
+            dst,
             hbone_target,
             start,
             &connection_metrics,
             metrics,
-        );
+            self.pi.access_log.clone(),
+        )
+        .with_trace(trace.clone());
+        // Count the connection against the endpoint for as long as it is open, for load balancing.
+        let _outbound_guard = match (&connected, Self::service_endpoint(&req)) {
+            (Ok(_), Some(uid)) => Some(self.pi.connection_manager.track_outbound(uid)),
+            _ => None,
+        };
 
-        let res = match req.protocol {
-            Protocol::HBONE => {
-                self.proxy_to_hbone(&mut source_stream, source_addr, outer_conn_drain, &req)
-                    .await
+        let shaper = self.pi.bandwidth.shaper(
+            Some(&req.source),
+            req.destination_service
+                .as_ref()
+                .map(|s| s.hostname.as_str()),
+            StreamSide::Source,
+        );
+        let limits = ConnectionLimits::new(&self.pi.cfg)
+            .with_bandwidth(shaper)
+            .with_live_metrics(result_tracker.live());
+        let res = match connected {
+            Ok(UpstreamConnection::Hbone(mut upgraded)) => {
+                let copy = super::copy_hbone(&mut upgraded, &mut source_stream, &limits)
+                    .instrument(trace_span!("hbone client"));
+                trace.traced("relay", copy).await
             }
-            Protocol::TCP => {
-                self.proxy_to_tcp(&mut source_stream, source_addr, outer_conn_drain, &req)
-                    .await
+            Ok(UpstreamConnection::Tcp(mut outbound)) => {
+                // Proxying data between downstrean and upstream
+                let copy = proxy::relay(&mut source_stream, &mut outbound, &limits);
+                trace.traced("relay", copy).await
             }
+            Err(err) => Err(err),
         };
-        result_tracker.record(res)
+        let res = res.map(|(sent, received)| (sent + early_data.len() as u64, received));
+        result_tracker.record(res);
+        drop(circuit_guard);
+    }
+
+    /// Returns the destination and HBONE target to report for the request. When tunneling through a
+    /// network gateway, this is the final destination rather than the gateway, which is only a hop
+    /// along the way.
+    fn reported_destination(req: &Request) -> (SocketAddr, Option<SocketAddr>) {
+        match &req.network_gateway {
+            Some(gw) if gw.single_tls => (req.destination, Some(req.destination)),
+            Some(_) => (req.destination, Some(req.gateway)),
+            None if req.request_type == RequestType::ToServerWaypoint => {
+                (req.gateway, Some(req.destination))
+            }
+            None => (req.gateway, None),
+        }
     }
 
-    async fn proxy_to_hbone(
+    /// Returns the workload UID of the service endpoint the request was load balanced to, if any.
+    /// Endpoints on the local node are sent Direct as well, as there is no separate local path, so
+    /// they fail over like any other endpoint.
+    fn service_endpoint(req: &Request) -> Option<&str> {
+        if req.request_type != RequestType::Direct || req.destination_service.is_none() {
+            return None;
+        }
+        req.destination_workload.as_ref().map(|wl| wl.uid.as_str())
+    }
+
+    /// Picks an alternate endpoint to retry a failed connection with, if the request was sent directly
+    /// to a service endpoint and we have retry budget left. Endpoints we already attempted are excluded.
+    async fn failover_request(
+        &self,
+        source_addr: SocketAddr,
+        dest_addr: SocketAddr,
+        failed: &Request,
+        excluded: &mut Vec<String>,
+    ) -> Option<Request> {
+        let failed_uid = Self::service_endpoint(failed)?;
+        if excluded.len() >= self.pi.cfg.connect_retry_budget {
+            return None;
+        }
+        excluded.push(failed_uid.to_string());
+        let next = self
+            .build_request(source_addr.ip(), dest_addr, excluded)
+            .await
+            .ok()?;
+        // Only retry to another endpoint of the service; otherwise we would fall back to passthrough.
+        if next.request_type != RequestType::Direct || next.destination_service.is_none() {
+            return None;
+        }
+        Some(next)
+    }
+
+    async fn connect(
         &mut self,
-        stream: &mut TcpStream,
         remote_addr: SocketAddr,
         outer_conn_drain: Option<Watch>,
         req: &Request,
-    ) -> Result<(u64, u64), Error> {
+        trace: &ConnectionTrace,
+    ) -> Result<UpstreamConnection, Error> {
+        match req.protocol {
+            Protocol::HBONE => self
+                .connect_hbone(remote_addr, outer_conn_drain, req, trace)
+                .await
+                .map(UpstreamConnection::Hbone),
+            Protocol::TCP => trace
+                .traced("connect", self.connect_tcp(remote_addr, req))
+                .await
+                .map(UpstreamConnection::Tcp),
+        }
+    }
+
+    async fn connect_hbone(
+        &mut self,
+        remote_addr: SocketAddr,
+        outer_conn_drain: Option<Watch>,
+        req: &Request,
+        trace: &ConnectionTrace,
+    ) -> Result<pool::Tunnel, Error> {
         debug!(
             "proxy to {} using HBONE via {} type {:#?}",
             req.destination, req.gateway, req.request_type
@@ -280,81 +503,191 @@ impl OutboundConnection {
         );
         let dst_identity = allowed_sans;
 
+        if let Some(gw) = req.network_gateway.as_ref().filter(|gw| gw.single_tls) {
+            let connect = self.connect_network_gateway(
+                remote_addr,
+                outer_conn_drain,
+                req,
+                gw,
+                req.destination,
+            );
+            return trace.traced("hbone handshake", connect).await;
+        }
+
         let pool_key = pool::Key {
             src_id: req.source.identity(),
             dst_id: dst_identity.clone(),
             src: remote_addr.ip(),
             dst: req.gateway,
+            via: req.network_gateway.as_ref().map(|gw| gw.address),
         };
 
         // Setup our connection future. This won't always run if we have an existing connection
         // in the pool.
         let connect = async {
-            let mut builder = http2::Builder::new(hyper_util::TokioExecutor);
-            let builder = builder
-                .initial_stream_window_size(self.pi.cfg.window_size)
-                .max_frame_size(self.pi.cfg.frame_size)
-                .initial_connection_window_size(self.pi.cfg.connection_window_size);
-
-            let local = self
-                .pi
-                .cfg
-                .enable_original_source
-                .unwrap_or_default()
-                .then_some(remote_addr.ip());
             let id = &req.source.identity();
             let cert = self.pi.cert_manager.fetch_certificate(id).await?;
             let connector = cert.outbound_connector(dst_identity)?;
-            let tcp_stream =
-                super::freebind_connect(local, req.gateway, self.pi.socket_factory.as_ref())
+            match &req.network_gateway {
+                None => {
+                    let tcp_stream = super::freebind_connect(
+                        self.local_addr(remote_addr),
+                        req.gateway,
+                        self.pi.socket_factory.as_ref(),
+                    )
                     .await?;
-            tcp_stream.set_nodelay(true)?; // TODO: this is backwards of expectations
-            let tls_stream = connector.connect(tcp_stream).await?;
-            let (request_sender, connection) = builder
-                .handshake(::hyper_util::rt::TokioIo::new(tls_stream))
-                .await
-                .map_err(Error::HttpHandshake)?;
-
-            // spawn a task to poll the connection and drive the HTTP state
-            // if we got a drain for that connection, respect it in a race
-            match outer_conn_drain {
-                Some(conn_drain) => {
-                    tokio::spawn(async move {
-                        tokio::select! {
-                                _ = conn_drain.signaled() => {
-                                    debug!("draining outer HBONE connection");
-                                }
-                                res = connection=> {
-                                    match res {
-                                        Err(e) => {
-                                            error!("Error in HBONE connection handshake: {:?}", e);
-                                        }
-                                        Ok(_) => {
-                                            debug!("done with HBONE connection handshake: {:?}", res);
-                                        }
-                                    }
-                                }
-                        }
-                    });
+                    tcp_stream.set_nodelay(true)?; // TODO: this is backwards of expectations
+                    let tls_stream = connector.connect(tcp_stream).await?;
+                    self.hbone_handshake(tls_stream, outer_conn_drain).await
                 }
-                None => {
-                    tokio::spawn(async move {
-                        if let Err(e) = connection.await {
-                            error!("Error in HBONE connection handshake: {:?}", e);
-                        }
-                    });
+                Some(gw) => {
+                    // Double HBONE: the mTLS connection to the destination runs inside an HBONE tunnel
+                    // to the network gateway, so the gateway never sees the inner traffic.
+                    let tunnel = self
+                        .connect_network_gateway(
+                            remote_addr,
+                            outer_conn_drain.clone(),
+                            req,
+                            gw,
+                            req.gateway,
+                        )
+                        .await?;
+                    let tls_stream = connector.connect_tunneled(tunnel, req.gateway.ip()).await?;
+                    self.hbone_handshake(tls_stream, outer_conn_drain).await
                 }
             }
+        };
+        // Establishing the connection is skipped if there is one in the pool.
+        let connection = trace
+            .traced("connect", self.pi.pool.connect(pool_key.clone(), connect))
+            .await?;
+        let handshake = self.send_connect(connection, req.destination, remote_addr, req);
+        trace.traced("hbone handshake", handshake).await
+    }
 
-            Ok(request_sender)
+    /// Opens an HBONE tunnel through the network gateway to `target`. For double HBONE this is the
+    /// HBONE address of the destination; for single TLS it is the destination itself.
+    async fn connect_network_gateway(
+        &self,
+        remote_addr: SocketAddr,
+        outer_conn_drain: Option<Watch>,
+        req: &Request,
+        gw: &NetworkGatewayHop,
+        target: SocketAddr,
+    ) -> Result<pool::Tunnel, Error> {
+        debug!(
+            "tunneling to {} via network gateway {} (single tls: {})",
+            target, gw.address, gw.single_tls
+        );
+        let pool_key = pool::Key {
+            src_id: req.source.identity(),
+            dst_id: gw.identities.clone(),
+            src: remote_addr.ip(),
+            dst: gw.address,
+            via: None,
+        };
+        let connect = async {
+            let id = &req.source.identity();
+            let cert = self.pi.cert_manager.fetch_certificate(id).await?;
+            let connector = cert.outbound_connector(gw.identities.clone())?;
+            let tcp_stream = super::freebind_connect(
+                self.local_addr(remote_addr),
+                gw.address,
+                self.pi.socket_factory.as_ref(),
+            )
+            .await?;
+            tcp_stream.set_nodelay(true)?;
+            let tls_stream = connector.connect(tcp_stream).await?;
+            self.hbone_handshake(tls_stream, outer_conn_drain).await
         };
-        let mut connection = self.pi.pool.connect(pool_key.clone(), connect).await?;
+        let connection = self.pi.pool.connect(pool_key, connect).await?;
+        self.send_connect(connection, target, remote_addr, req)
+            .await
+    }
+
+    // The local address to bind upstream connections to, if we are spoofing the source.
+    fn local_addr(&self, remote_addr: SocketAddr) -> Option<IpAddr> {
+        // Authenticated clients are not local workloads, so their address cannot be spoofed.
+        if self.authenticated_source.is_some() {
+            return None;
+        }
+        self.pi
+            .cfg
+            .enable_original_source
+            .unwrap_or_default()
+            .then_some(remote_addr.ip())
+    }
+
+    /// Performs the HTTP/2 handshake for an HBONE connection over the given stream.
+    async fn hbone_handshake<IO>(
+        &self,
+        stream: IO,
+        outer_conn_drain: Option<Watch>,
+    ) -> Result<http2::SendRequest<Empty<Bytes>>, Error>
+    where
+        IO: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+    {
+        let mut builder = http2::Builder::new(hyper_util::TokioExecutor);
+        let builder = builder
+            .initial_stream_window_size(self.pi.cfg.window_size)
+            .max_frame_size(self.pi.cfg.frame_size)
+            .initial_connection_window_size(self.pi.cfg.connection_window_size)
+            .timer(::hyper_util::rt::TokioTimer::new())
+            .keep_alive_interval(self.pi.cfg.pool_keepalive_interval)
+            .keep_alive_timeout(self.pi.cfg.pool_keepalive_timeout)
+            .keep_alive_while_idle(true);
+        let (request_sender, connection) = builder
+            .handshake(::hyper_util::rt::TokioIo::new(stream))
+            .await
+            .map_err(Error::HttpHandshake)?;
+
+        // spawn a task to poll the connection and drive the HTTP state
+        // if we got a drain for that connection, respect it in a race
+        match outer_conn_drain {
+            Some(conn_drain) => {
+                tokio::spawn(async move {
+                    tokio::select! {
+                            _ = conn_drain.signaled() => {
+                                debug!("draining outer HBONE connection");
+                            }
+                            res = connection=> {
+                                match res {
+                                    Err(e) => {
+                                        error!("Error in HBONE connection handshake: {:?}", e);
+                                    }
+                                    Ok(_) => {
+                                        debug!("done with HBONE connection handshake: {:?}", res);
+                                    }
+                                }
+                            }
+                    }
+                });
+            }
+            None => {
+                tokio::spawn(async move {
+                    if let Err(e) = connection.await {
+                        error!("Error in HBONE connection handshake: {:?}", e);
+                    }
+                });
+            }
+        }
+
+        Ok(request_sender)
+    }
 
+    /// Sends an HBONE CONNECT request for `authority` over the connection, returning the tunnel.
+    async fn send_connect(
+        &self,
+        mut connection: pool::Connection,
+        authority: SocketAddr,
+        remote_addr: SocketAddr,
+        req: &Request,
+    ) -> Result<pool::Tunnel, Error> {
         let mut f = http_types::proxies::Forwarded::new();
         f.add_for(remote_addr.to_string());
 
         let request = hyper::Request::builder()
-            .uri(&req.destination.to_string())
+            .uri(&authority.to_string())
             .method(hyper::Method::CONNECT)
             .version(hyper::Version::HTTP_2)
             .header(BAGGAGE_HEADER, baggage(req, self.pi.cfg.cluster_id.clone()))
@@ -374,33 +707,22 @@ impl OutboundConnection {
         if code != 200 {
             return Err(Error::HttpStatus(code));
         }
-        let mut upgraded = hyper::upgrade::on(response).await?;
-
-        super::copy_hbone(&mut upgraded, stream)
-            .instrument(trace_span!("hbone client"))
-            .await
+        let upgraded = hyper::upgrade::on(response).await?;
+        Ok(pool::Tunnel::new(upgraded, connection))
     }
-    async fn proxy_to_tcp(
+
+    async fn connect_tcp(
         &mut self,
-        stream: &mut TcpStream,
-        _remote_addr: SocketAddr,
-        _outer_conn_drain: Option<Watch>,
+        remote_addr: SocketAddr,
         req: &Request,
-    ) -> Result<(u64, u64), Error> {
+    ) -> Result<TcpStream, Error> {
         info!(
             "Proxying to {} using TCP via {} type {:?}",
             req.destination, req.gateway, req.request_type
         );
         // Create a TCP connection to upstream
-        let local = if self.pi.cfg.enable_original_source.unwrap_or_default() {
-            super::get_original_src_from_stream(stream)
-        } else {
-            None
-        };
-        let mut outbound =
-            super::freebind_connect(local, req.gateway, self.pi.socket_factory.as_ref()).await?;
-        // Proxying data between downstrean and upstream
-        proxy::relay(stream, &mut outbound).await
+        let local = self.local_addr(remote_addr);
+        Ok(super::freebind_connect(local, req.gateway, self.pi.socket_factory.as_ref()).await?)
     }
 
     fn conn_metrics_from_request(req: &Request) -> ConnectionOpen {
@@ -422,14 +744,18 @@ impl OutboundConnection {
         &self,
         downstream: IpAddr,
         target: SocketAddr,
+        excluded: &[String],
     ) -> Result<Request, Error> {
         let downstream_network_addr = NetworkAddress {
             network: self.pi.cfg.network.clone(),
             address: downstream,
         };
-        let source_workload = match self.pi.state.fetch_workload(&downstream_network_addr).await {
-            Some(wl) => wl,
-            None => return Err(Error::UnknownSource(downstream)),
+        let source_workload = match &self.authenticated_source {
+            Some(wl) => wl.clone(),
+            None => match self.pi.state.fetch_workload(&downstream_network_addr).await {
+                Some(wl) => wl,
+                None => return Err(Error::UnknownSource(downstream)),
+            },
         };
 
         // If this is to-service traffic check for a service waypoint
@@ -445,19 +771,19 @@ impl OutboundConnection {
         {
             // if we have a waypoint for this svc, use it; otherwise route traffic normally
             if let Some(wp) = s.waypoint.clone() {
-                let waypoint_vip = match wp.destination {
-                    Destination::Address(a) => a.address,
-                    Destination::Hostname(_) => {
-                        return Err(proxy::Error::UnknownWaypoint(
-                            "hostname lookup not supported yet".to_string(),
-                        ));
-                    }
-                };
-                let waypoint_vip = SocketAddr::new(waypoint_vip, wp.hbone_mtls_port);
+                let waypoint_addr = self
+                    .pi
+                    .state
+                    .fetch_gateway_address(&wp, &self.pi.cfg.network)
+                    .await
+                    .ok_or(proxy::Error::UnknownWaypoint(
+                        "unable to resolve waypoint address".to_string(),
+                    ))?;
+                let waypoint_vip = SocketAddr::new(waypoint_addr.address, wp.hbone_mtls_port);
                 let waypoint_us = self
                     .pi
                     .state
-                    .fetch_upstream(&self.pi.cfg.network, &source_workload, waypoint_vip)
+                    .fetch_upstream(&waypoint_addr.network, &source_workload, waypoint_vip)
                     .await
                     .ok_or(proxy::Error::UnknownWaypoint(
                         "unable to determine waypoint upstream".to_string(),
@@ -487,6 +813,7 @@ impl OutboundConnection {
                     gateway: waypoint_socket_address,
                     request_type: RequestType::ToServerWaypoint,
                     upstream_sans: waypoint_us.sans,
+                    network_gateway: None,
                 });
             }
             // this was service addressed but we did not find a waypoint
@@ -500,7 +827,16 @@ impl OutboundConnection {
         let us = match self
             .pi
             .state
-            .fetch_upstream(&source_workload.network, &source_workload, target)
+            .fetch_upstream_with_hints(
+                &source_workload.network,
+                &source_workload,
+                target,
+                &LoadBalanceHints {
+                    excluded,
+                    source_ip: Some(downstream),
+                    connections: Some(&self.pi.connection_manager),
+                },
+            )
             .await
         {
             Some(us) => us,
@@ -517,6 +853,7 @@ impl OutboundConnection {
                     direction: Direction::Outbound,
                     request_type: RequestType::Passthrough,
                     upstream_sans: vec![],
+                    network_gateway: None,
                 });
             }
         };
@@ -572,6 +909,7 @@ impl OutboundConnection {
                         direction: Direction::Inbound,
                         request_type: RequestType::ToServerWaypoint,
                         upstream_sans: us.sans,
+                        network_gateway: None,
                     });
                 }
                 // we expected the workload to have a waypoint, but could not find one
@@ -585,6 +923,16 @@ impl OutboundConnection {
             Protocol::TCP => SocketAddr::from((workload_ip, us.port)),
         };
 
+        // If the destination is on another network, we cannot reach it directly; tunnel through
+        // its network gateway instead.
+        let network_gateway = if us.workload.network != source_workload.network
+            && us.workload.protocol == Protocol::HBONE
+        {
+            self.network_gateway_hop(&us.workload).await?
+        } else {
+            None
+        };
+
         // For case no waypoint for both side and direct to remote node proxy
         Ok(Request {
             protocol: us.workload.protocol,
@@ -597,8 +945,58 @@ impl OutboundConnection {
             direction: Direction::Outbound,
             request_type: RequestType::Direct,
             upstream_sans: us.sans,
+            network_gateway,
         })
     }
+
+    /// Resolves the network gateway for a workload on another network, if it has one.
+    async fn network_gateway_hop(&self, wl: &Workload) -> Result<Option<NetworkGatewayHop>, Error> {
+        let Some(gw) = &wl.network_gateway else {
+            return Ok(None);
+        };
+        // Prefer double HBONE; fall back to single TLS if that is all the gateway supports.
+        let (port, single_tls) = match (gw.hbone_mtls_port, gw.hbone_single_tls_port) {
+            (0, Some(port)) => (port, true),
+            (port, _) => (port, false),
+        };
+        let gw_addr = self
+            .pi
+            .state
+            .fetch_gateway_address(gw, &wl.network)
+            .await
+            .ok_or_else(|| {
+                Error::UnknownNetworkGateway(format!(
+                    "unable to resolve network gateway address for {}",
+                    wl.name
+                ))
+            })?;
+        let identities = self.pi.state.fetch_gateway_identities(gw).await;
+        if identities.is_empty() {
+            return Err(Error::UnknownNetworkGateway(format!(
+                "unable to determine network gateway identity for {}",
+                wl.name
+            )));
+        }
+        Ok(Some(NetworkGatewayHop {
+            address: SocketAddr::new(gw_addr.address, port),
+            identities,
+            single_tls,
+        }))
+    }
+}
+
+enum UpstreamConnection {
+    Hbone(pool::Tunnel),
+    Tcp(TcpStream),
+}
+
+impl UpstreamConnection {
+    async fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        match self {
+            UpstreamConnection::Hbone(tunnel) => tunnel.write_all(buf).await,
+            UpstreamConnection::Tcp(stream) => stream.write_all(buf).await,
+        }
+    }
 }
 
 fn baggage(r: &Request, cluster: String) -> String {
@@ -628,6 +1026,21 @@ struct Request {
     request_type: RequestType,
 
     upstream_sans: Vec<String>,
+
+    // If set, the destination is on another network and the HBONE connection to `gateway` is tunneled
+    // through this network gateway.
+    network_gateway: Option<NetworkGatewayHop>,
+}
+
+#[derive(Debug)]
+struct NetworkGatewayHop {
+    // The HBONE address of the network gateway
+    address: SocketAddr,
+    // The identities the network gateway may present
+    identities: Vec<Identity>,
+    // If true, the gateway only supports single TLS HBONE: we CONNECT to the destination directly
+    // through the gateway, and the payload is only protected by the TLS connection to the gateway.
+    single_tls: bool,
 }
 
 #[derive(Debug)]
@@ -654,7 +1067,9 @@ mod tests {
 
     use super::*;
     use crate::config::Config;
+    use crate::proxy::bandwidth::BandwidthLimiter;
     use crate::proxy::connection_manager::ConnectionManager;
+    use crate::state::DemandProxyState;
     use crate::test_helpers::helpers::test_proxy_metrics;
     use crate::test_helpers::new_proxy_state;
     use crate::xds::istio::workload::address::Type as XdsAddressType;
@@ -665,16 +1080,38 @@ mod tests {
     use crate::xds::istio::workload::Workload as XdsWorkload;
     use crate::{identity, xds};
 
+    fn test_outbound(state: DemandProxyState) -> OutboundConnection {
+        let cfg = Config {
+            local_node: Some("local-node".to_string()),
+            ..crate::config::parse_config().unwrap()
+        };
+        let metrics = test_proxy_metrics();
+        OutboundConnection {
+            pi: ProxyInputs {
+                cert_manager: identity::mock::new_secret_manager(Duration::from_secs(10)),
+                state,
+                hbone_port: 15008,
+                bandwidth: BandwidthLimiter::new(&cfg, metrics.clone()),
+                pool: pool::Pool::new(&cfg),
+                cfg,
+                metrics,
+                socket_factory: std::sync::Arc::new(crate::proxy::DefaultSocketFactory),
+                proxy_workload_info: None,
+                connection_manager: ConnectionManager::default(),
+                access_log: Default::default(),
+                trace_exporter: Default::default(),
+            },
+            id: TraceParent::new(),
+            authenticated_source: None,
+        }
+    }
+
     async fn run_build_request(
         from: &str,
         to: &str,
         xds: XdsAddressType,
         expect: Option<ExpectedRequest<'_>>,
     ) {
-        let cfg = Config {
-            local_node: Some("local-node".to_string()),
-            ..crate::config::parse_config().unwrap()
-        };
         let source = XdsWorkload {
             uid: "cluster1//v1/Pod/ns/source-workload".to_string(),
             name: "source-workload".to_string(),
@@ -688,6 +1125,7 @@ mod tests {
             name: "waypoint-workload".to_string(),
             namespace: "ns".to_string(),
             addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 10])],
+            hostname: "waypoint.ns.svc.cluster.local".to_string(),
             node: "local-node".to_string(),
             service_account: "waypoint-sa".to_string(),
             ..Default::default()
@@ -696,23 +1134,10 @@ mod tests {
             XdsAddressType::Workload(wl) => new_proxy_state(&[source, waypoint, wl], &[], &[]),
             XdsAddressType::Service(svc) => new_proxy_state(&[source, waypoint], &[svc], &[]),
         };
-        let outbound = OutboundConnection {
-            pi: ProxyInputs {
-                cert_manager: identity::mock::new_secret_manager(Duration::from_secs(10)),
-                state,
-                hbone_port: 15008,
-                cfg,
-                metrics: test_proxy_metrics(),
-                pool: pool::Pool::new(),
-                socket_factory: std::sync::Arc::new(crate::proxy::DefaultSocketFactory),
-                proxy_workload_info: None,
-                connection_manager: ConnectionManager::default(),
-            },
-            id: TraceParent::new(),
-        };
+        let outbound = test_outbound(state);
 
         let req = outbound
-            .build_request(from.parse().unwrap(), to.parse().unwrap())
+            .build_request(from.parse().unwrap(), to.parse().unwrap(), &[])
             .await
             .ok();
         if let Some(r) = req {
@@ -959,6 +1384,112 @@ mod tests {
         .await;
     }
 
+    #[tokio::test]
+    async fn build_request_destination_hostname_waypoint() {
+        run_build_request(
+            "127.0.0.1",
+            "127.0.0.2:80",
+            XdsAddressType::Workload(XdsWorkload {
+                uid: "cluster1//v1/Pod/default/my-pod".to_string(),
+                addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 2])],
+                waypoint: Some(xds::istio::workload::GatewayAddress {
+                    destination: Some(
+                        xds::istio::workload::gateway_address::Destination::Hostname(
+                            xds::istio::workload::NamespacedHostname {
+                                namespace: "ns".to_string(),
+                                hostname: "waypoint.ns.svc.cluster.local".to_string(),
+                            },
+                        ),
+                    ),
+                    hbone_mtls_port: 15008,
+                    hbone_single_tls_port: 15003,
+                }),
+                ..Default::default()
+            }),
+            // Should use the waypoint
+            Some(ExpectedRequest {
+                protocol: Protocol::HBONE,
+                destination: "127.0.0.2:80",
+                gateway: "127.0.0.10:15008",
+                request_type: RequestType::ToServerWaypoint,
+            }),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn build_request_destination_svc_hostname_waypoint() {
+        run_build_request(
+            "127.0.0.1",
+            "127.0.0.3:80",
+            XdsAddressType::Service(XdsService {
+                addresses: vec![XdsNetworkAddress {
+                    network: "".to_string(),
+                    address: vec![127, 0, 0, 3],
+                }],
+                ports: vec![Port {
+                    service_port: 80,
+                    target_port: 8080,
+                }],
+                waypoint: Some(xds::istio::workload::GatewayAddress {
+                    destination: Some(
+                        xds::istio::workload::gateway_address::Destination::Hostname(
+                            xds::istio::workload::NamespacedHostname {
+                                namespace: "ns".to_string(),
+                                hostname: "waypoint.ns.svc.cluster.local".to_string(),
+                            },
+                        ),
+                    ),
+                    hbone_mtls_port: 15008,
+                    hbone_single_tls_port: 15003,
+                }),
+                ..Default::default()
+            }),
+            // Should use the waypoint
+            Some(ExpectedRequest {
+                protocol: Protocol::HBONE,
+                destination: "127.0.0.3:80",
+                gateway: "127.0.0.10:15008",
+                request_type: RequestType::ToServerWaypoint,
+            }),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn build_request_destination_unknown_hostname_waypoint() {
+        run_build_request(
+            "127.0.0.1",
+            "127.0.0.3:80",
+            XdsAddressType::Service(XdsService {
+                addresses: vec![XdsNetworkAddress {
+                    network: "".to_string(),
+                    address: vec![127, 0, 0, 3],
+                }],
+                ports: vec![Port {
+                    service_port: 80,
+                    target_port: 8080,
+                }],
+                waypoint: Some(xds::istio::workload::GatewayAddress {
+                    destination: Some(
+                        xds::istio::workload::gateway_address::Destination::Hostname(
+                            xds::istio::workload::NamespacedHostname {
+                                namespace: "ns".to_string(),
+                                hostname: "unknown.ns.svc.cluster.local".to_string(),
+                            },
+                        ),
+                    ),
+                    hbone_mtls_port: 15008,
+                    hbone_single_tls_port: 15003,
+                }),
+                ..Default::default()
+            }),
+            // The waypoint cannot be resolved, so the request fails
+            None,
+        )
+        .await;
+    }
+
     #[derive(PartialEq, Debug)]
     struct ExpectedRequest<'a> {
         protocol: Protocol,
@@ -966,4 +1497,197 @@ mod tests {
         gateway: &'a str,
         request_type: RequestType,
     }
+
+    #[tokio::test]
+    async fn failover_request_local_node() {
+        let source = XdsWorkload {
+            uid: "cluster1//v1/Pod/ns/source-workload".to_string(),
+            name: "source-workload".to_string(),
+            namespace: "ns".to_string(),
+            addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 1])],
+            node: "local-node".to_string(),
+            ..Default::default()
+        };
+        let endpoint = |name: &str, ip: u8| XdsWorkload {
+            uid: format!("cluster1//v1/Pod/ns/{name}"),
+            name: name.to_string(),
+            namespace: "ns".to_string(),
+            addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, ip])],
+            tunnel_protocol: XdsProtocol::Hbone as i32,
+            node: "local-node".to_string(),
+            services: std::collections::HashMap::from([(
+                "ns/svc.ns.svc.cluster.local".to_string(),
+                xds::istio::workload::PortList {
+                    ports: vec![Port {
+                        service_port: 80,
+                        target_port: 8080,
+                    }],
+                },
+            )]),
+            ..Default::default()
+        };
+        let svc = XdsService {
+            name: "svc".to_string(),
+            namespace: "ns".to_string(),
+            hostname: "svc.ns.svc.cluster.local".to_string(),
+            addresses: vec![XdsNetworkAddress {
+                network: "".to_string(),
+                address: vec![127, 0, 0, 10],
+            }],
+            ports: vec![Port {
+                service_port: 80,
+                target_port: 8080,
+            }],
+            ..Default::default()
+        };
+        let state = new_proxy_state(
+            &[source, endpoint("ep1", 2), endpoint("ep2", 3)],
+            &[svc],
+            &[],
+        );
+        let outbound = test_outbound(state);
+        let source_addr = "127.0.0.1:12345".parse().unwrap();
+        let dest_addr = "127.0.0.10:80".parse().unwrap();
+
+        let req = outbound
+            .build_request(source_addr.ip(), dest_addr, &[])
+            .await
+            .unwrap();
+        assert_eq!(req.request_type, RequestType::Direct);
+        let first = req.destination_workload.as_ref().unwrap().uid.clone();
+
+        // Endpoints on the local node fail over to the other endpoint of the service
+        let mut excluded = Vec::new();
+        let next = outbound
+            .failover_request(source_addr, dest_addr, &req, &mut excluded)
+            .await
+            .expect("should fail over");
+        let second = next.destination_workload.as_ref().unwrap().uid.clone();
+        assert_ne!(first, second);
+        assert_eq!(excluded, vec![first]);
+
+        // Once every endpoint has been attempted, there is nothing left to fail over to
+        assert!(outbound
+            .failover_request(source_addr, dest_addr, &next, &mut excluded)
+            .await
+            .is_none());
+    }
+
+    async fn build_remote_network_request(
+        hbone_mtls_port: u32,
+        hbone_single_tls_port: u32,
+    ) -> Request {
+        let source = XdsWorkload {
+            uid: "cluster1//v1/Pod/ns/source-workload".to_string(),
+            name: "source-workload".to_string(),
+            namespace: "ns".to_string(),
+            addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 1])],
+            node: "local-node".to_string(),
+            ..Default::default()
+        };
+        let gateway = XdsWorkload {
+            uid: "cluster2//v1/Pod/istio-system/eastwest-gateway".to_string(),
+            name: "eastwest-gateway".to_string(),
+            namespace: "istio-system".to_string(),
+            network: "remote".to_string(),
+            addresses: vec![Bytes::copy_from_slice(&[192, 168, 0, 1])],
+            service_account: "eastwest-gateway".to_string(),
+            ..Default::default()
+        };
+        let remote = XdsWorkload {
+            uid: "cluster2//v1/Pod/ns/remote-workload".to_string(),
+            name: "remote-workload".to_string(),
+            namespace: "ns".to_string(),
+            network: "remote".to_string(),
+            addresses: vec![Bytes::copy_from_slice(&[10, 0, 0, 2])],
+            tunnel_protocol: XdsProtocol::Hbone as i32,
+            services: std::collections::HashMap::from([(
+                "ns/remote.ns.svc.cluster.local".to_string(),
+                xds::istio::workload::PortList {
+                    ports: vec![Port {
+                        service_port: 80,
+                        target_port: 8080,
+                    }],
+                },
+            )]),
+            network_gateway: Some(xds::istio::workload::GatewayAddress {
+                destination: Some(xds::istio::workload::gateway_address::Destination::Address(
+                    XdsNetworkAddress {
+                        network: "remote".to_string(),
+                        address: vec![192, 168, 0, 1],
+                    },
+                )),
+                hbone_mtls_port,
+                hbone_single_tls_port,
+            }),
+            ..Default::default()
+        };
+        let svc = XdsService {
+            name: "remote".to_string(),
+            namespace: "ns".to_string(),
+            hostname: "remote.ns.svc.cluster.local".to_string(),
+            addresses: vec![XdsNetworkAddress {
+                network: "".to_string(),
+                address: vec![127, 0, 0, 3],
+            }],
+            ports: vec![Port {
+                service_port: 80,
+                target_port: 8080,
+            }],
+            ..Default::default()
+        };
+        let state = new_proxy_state(&[source, gateway, remote], &[svc], &[]);
+        test_outbound(state)
+            .build_request(
+                "127.0.0.1".parse().unwrap(),
+                "127.0.0.3:80".parse().unwrap(),
+                &[],
+            )
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn build_request_remote_network_gateway() {
+        let req = build_remote_network_request(15008, 15003).await;
+        assert_eq!(req.protocol, Protocol::HBONE);
+        assert_eq!(req.request_type, RequestType::Direct);
+        assert_eq!(req.destination, "10.0.0.2:8080".parse().unwrap());
+        assert_eq!(req.gateway, "10.0.0.2:15008".parse().unwrap());
+        // The gateway is not reported as the destination
+        assert_eq!(
+            OutboundConnection::reported_destination(&req),
+            (
+                "10.0.0.2:8080".parse().unwrap(),
+                Some("10.0.0.2:15008".parse().unwrap())
+            )
+        );
+        let gw = req.network_gateway.expect("should use the network gateway");
+        assert_eq!(gw.address, "192.168.0.1:15008".parse().unwrap());
+        assert!(!gw.single_tls);
+        assert_eq!(
+            gw.identities,
+            vec![Identity::Spiffe {
+                trust_domain: "cluster.local".to_string(),
+                namespace: "istio-system".to_string(),
+                service_account: "eastwest-gateway".to_string(),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn build_request_remote_network_gateway_single_tls() {
+        let req = build_remote_network_request(0, 15003).await;
+        assert_eq!(req.destination, "10.0.0.2:8080".parse().unwrap());
+        assert_eq!(
+            OutboundConnection::reported_destination(&req),
+            (
+                "10.0.0.2:8080".parse().unwrap(),
+                Some("10.0.0.2:8080".parse().unwrap())
+            )
+        );
+        let gw = req.network_gateway.expect("should use the network gateway");
+        assert_eq!(gw.address, "192.168.0.1:15003".parse().unwrap());
+        assert!(gw.single_tls);
+    }
 }
@@ -13,41 +13,108 @@
 // limitations under the License.
 
 use bytes::Bytes;
-use futures::pin_mut;
-use futures_util::future;
-use futures_util::future::Either;
-use http_body_util::Empty;
+use futures_util::future::{FutureExt, Shared};
+use http_body_util::{Empty, Full};
 use hyper::body::Incoming;
 use hyper::client::conn::http2;
 use hyper::http::{Request, Response};
-use hyper_util::client::legacy::pool;
-use hyper_util::client::legacy::pool::{Pool as HyperPool, Poolable, Pooled, Reservation};
-use hyper_util::rt::TokioTimer;
+use hyper_util::rt::TokioIo;
+use std::collections::HashMap;
 use std::future::Future;
+use std::io;
 use std::net::IpAddr;
 use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll};
 use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::oneshot;
+use tokio::time::Instant;
 use tracing::debug;
 
+use crate::config::Config;
 use crate::identity::Identity;
 use crate::proxy::Error;
 
+/// Pool holds HBONE connections for reuse. Each connection multiplexes up to
+/// `pool_max_streams_per_conn` streams; once all connections for a key are saturated, a new one is
+/// opened.
 #[derive(Clone)]
 pub struct Pool {
-    pool: HyperPool<Client, Key>,
+    inner: Arc<PoolInner>,
+}
+
+struct PoolInner {
+    max_streams: usize,
+    idle_timeout: Duration,
+    connections: Mutex<HashMap<Key, KeyState>>,
+}
+
+#[derive(Default)]
+struct KeyState {
+    clients: Vec<Arc<Client>>,
+    // Set while a new connection is established, so concurrent requests for the same key wait for it
+    // rather than each opening their own.
+    connecting: Option<Shared<oneshot::Receiver<ConnectOutcome>>>,
+}
+
+// The outcome of establishing a connection, as seen by the requests waiting for it.
+type ConnectOutcome = Result<Arc<Client>, String>;
+
+impl KeyState {
+    /// Reserves a stream on an existing connection, if one has capacity. Closed connections are
+    /// dropped along the way.
+    fn checkout(&mut self, max_streams: usize) -> Option<Connection> {
+        self.clients.retain(|c| c.is_open());
+        let client = self.clients.iter().find(|c| c.try_reserve(max_streams))?;
+        Some(Connection::new(client.clone()))
+    }
+}
+
+/// PendingConnection is held by the request establishing a new connection for a key. If that
+/// request is cancelled, dropping this lets one of the waiting requests take over.
+struct PendingConnection<'a> {
+    inner: &'a PoolInner,
+    key: &'a Key,
+    tx: Option<oneshot::Sender<ConnectOutcome>>,
+}
+
+impl PendingConnection<'_> {
+    /// Adds the connection to the pool if it was established, and wakes up the waiting requests.
+    fn complete(mut self, outcome: ConnectOutcome) {
+        let mut connections = self.inner.connections.lock().unwrap();
+        let state = connections.entry(self.key.clone()).or_default();
+        if let Ok(client) = &outcome {
+            state.clients.push(client.clone());
+        }
+        state.connecting = None;
+        drop(connections);
+        if let Some(tx) = self.tx.take() {
+            let _ = tx.send(outcome);
+        }
+    }
+}
+
+impl Drop for PendingConnection<'_> {
+    fn drop(&mut self) {
+        if self.tx.is_some() {
+            if let Some(state) = self.inner.connections.lock().unwrap().get_mut(self.key) {
+                state.connecting = None;
+            }
+        }
+    }
 }
 
 impl Pool {
-    pub fn new() -> Pool {
+    pub fn new(cfg: &Config) -> Pool {
         Self {
-            pool: HyperPool::new(
-                hyper_util::client::legacy::pool::Config {
-                    idle_timeout: Some(Duration::from_secs(90)),
-                    max_idle_per_host: std::usize::MAX,
-                },
-                TokioExec,
-                Some(TokioTimer::new()),
-            ),
+            inner: Arc::new(PoolInner {
+                max_streams: cfg.pool_max_streams_per_conn.max(1) as usize,
+                idle_timeout: cfg.pool_idle_timeout,
+                connections: Default::default(),
+            }),
         }
     }
 }
@@ -65,26 +132,50 @@ where
     }
 }
 
-#[derive(Debug, Clone)]
-struct Client(http2::SendRequest<Empty<Bytes>>);
+#[derive(Debug)]
+struct Client {
+    sender: http2::SendRequest<Empty<Bytes>>,
+    created: Instant,
+    // Number of streams currently checked out on this connection
+    streams: AtomicUsize,
+    // Time the last stream was released, in milliseconds since created
+    last_used: AtomicU64,
+}
 
-impl Poolable for Client {
+impl Client {
     fn is_open(&self) -> bool {
-        self.0.is_ready()
+        !self.sender.is_closed()
     }
 
-    fn reserve(self) -> Reservation<Self> {
-        let b = self.clone();
-        let a = self;
-        Reservation::Shared(a, b)
+    /// Reserves a stream on the connection, if it has capacity left.
+    fn try_reserve(&self, max_streams: usize) -> bool {
+        self.streams
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < max_streams).then_some(n + 1)
+            })
+            .is_ok()
     }
 
-    fn can_share(&self) -> bool {
-        true // http2 always shares
+    fn release(&self) {
+        self.last_used
+            .store(self.created.elapsed().as_millis() as u64, Ordering::Relaxed);
+        self.streams.fetch_sub(1, Ordering::AcqRel);
+    }
+
+    /// Returns when the connection becomes idle for long enough to be closed, or None if it has
+    /// active streams.
+    fn idle_deadline(&self, idle_timeout: Duration) -> Option<Instant> {
+        if self.streams.load(Ordering::Acquire) > 0 {
+            return None;
+        }
+        let last_used =
+            self.created + Duration::from_millis(self.last_used.load(Ordering::Relaxed));
+        Some(last_used + idle_timeout)
     }
 }
 
-#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+#[derive(PartialEq, Eq, Hash, Clone, Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Key {
     pub src_id: Identity,
     pub dst_id: Vec<Identity>,
@@ -94,17 +185,80 @@ pub struct Key {
     // Because we spoof the source IP, we need to key on this as well. Note: for in-pod its already per-pod
     // pools anyways.
     pub src: IpAddr,
+    // If set, the connection is tunneled through this network gateway. The same destination reached
+    // through different gateways must not share a connection.
+    pub via: Option<SocketAddr>,
 }
 
+/// Connection is a stream reserved on a pooled connection. The stream counts against the
+/// connection's limit until this is dropped.
 #[derive(Debug)]
-pub struct Connection(Pooled<Client, Key>);
+pub struct Connection {
+    client: Arc<Client>,
+    sender: http2::SendRequest<Empty<Bytes>>,
+}
 
 impl Connection {
+    fn new(client: Arc<Client>) -> Self {
+        let sender = client.sender.clone();
+        Connection { client, sender }
+    }
+
     pub fn send_request(
         &mut self,
         req: Request<Empty<Bytes>>,
     ) -> impl Future<Output = hyper::Result<Response<Incoming>>> {
-        self.0 .0.send_request(req)
+        self.sender.send_request(req)
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.client.release();
+    }
+}
+
+/// Tunnel is an established HBONE stream. It holds on to its [Connection], so the stream is
+/// released back to the pool only once the tunnel is closed.
+pub struct Tunnel {
+    io: TokioIo<hyper::upgrade::Upgraded>,
+    _connection: Connection,
+}
+
+impl Tunnel {
+    pub fn new(upgraded: hyper::upgrade::Upgraded, connection: Connection) -> Self {
+        Tunnel {
+            io: TokioIo::new(upgraded),
+            _connection: connection,
+        }
+    }
+}
+
+impl AsyncRead for Tunnel {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Tunnel {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
     }
 }
 
@@ -113,57 +267,182 @@ impl Pool {
     where
         F: Future<Output = Result<http2::SendRequest<Empty<Bytes>>, Error>>,
     {
-        let reuse_connection = self.pool.checkout(key.clone());
-
-        let connect_pool = async {
-            let ver = pool::Ver::Http2;
-            let Some(connecting) = self.pool.connecting(&key, ver) else {
-                // There is already an existing connection establishment in flight.
-                // Return an error so
-                return Err(Error::PoolAlreadyConnecting);
-            };
-            let pc = Client(connect.await?);
-            let pooled = self.pool.pooled(connecting, pc);
-            Ok::<_, Error>(pooled)
-        };
-        pin_mut!(connect_pool);
-        let request_sender: Pooled<Client, _> =
-            match future::select(reuse_connection, connect_pool).await {
-                // Checkout won.
-                Either::Left((Ok(conn), _)) => {
+        // Use an existing connection, wait for one that is being established, or establish one.
+        // The pool lock is never held across an await.
+        let tx = loop {
+            let pending = {
+                let mut connections = self.inner.connections.lock().unwrap();
+                let state = connections.entry(key.clone()).or_default();
+                if let Some(conn) = state.checkout(self.inner.max_streams) {
                     debug!(?key, "fetched existing connection");
-                    conn
+                    return Ok(conn);
                 }
-                // Checkout won, but had an error.
-                Either::Left((Err(err), connecting)) => match err {
-                    // Checked out a closed connection. Just keep connecting then
-                    pool::Error::CheckedOutClosedValue => connecting.await?,
-                    // Some other error, bubble it up
-                    _ => return Err(Error::Pool(err)),
-                },
-                // Connect won, checkout can just be dropped.
-                Either::Right((Ok(request_sender), _checkout)) => {
-                    debug!(?key, "established new connection");
-                    request_sender
-                }
-                // Connect won, checkout can just be dropped.
-                Either::Right((Err(err), checkout)) => {
-                    debug!(
-                        ?key,
-                        "connect won, but wait for existing pooled connection to establish"
-                    );
-                    match err {
-                        // Connect won but we already had an in-flight connection, so use that.
-                        Error::PoolAlreadyConnecting => checkout.await?,
-                        // Some other connection error
-                        err => return Err(err),
+                match &state.connecting {
+                    Some(pending) => pending.clone(),
+                    None => {
+                        let (tx, rx) = oneshot::channel();
+                        state.connecting = Some(rx.shared());
+                        break tx;
                     }
                 }
             };
+            match pending.await {
+                Ok(Err(e)) => {
+                    return Err(Error::ConnectionFailed(io::Error::new(
+                        io::ErrorKind::Other,
+                        e,
+                    )))
+                }
+                // Either the connection was established, and we retry checking it out, or the
+                // request establishing it was cancelled, and we may take over.
+                Ok(Ok(_)) | Err(_) => continue,
+            }
+        };
+        let pending = PendingConnection {
+            inner: &self.inner,
+            key: &key,
+            tx: Some(tx),
+        };
+        let sender = match connect.await {
+            Ok(sender) => sender,
+            Err(e) => {
+                pending.complete(Err(e.to_string()));
+                return Err(e);
+            }
+        };
+        let client = Arc::new(Client {
+            sender,
+            created: Instant::now(),
+            streams: AtomicUsize::new(1),
+            last_used: AtomicU64::new(0),
+        });
+        debug!(?key, "established new connection");
+        pending.complete(Ok(client.clone()));
+        self.spawn_idle_reaper(key, Arc::downgrade(&client));
+        Ok(Connection::new(client))
+    }
 
-        Ok(Connection(request_sender))
+    /// Removes the connection from the pool once it has been idle for `pool_idle_timeout`. Once
+    /// removed and all streams are done, the connection is closed.
+    fn spawn_idle_reaper(&self, key: Key, client: Weak<Client>) {
+        let pool = Arc::downgrade(&self.inner);
+        let idle_timeout = self.inner.idle_timeout;
+        tokio::spawn(async move {
+            loop {
+                // Don't hold on to the connection while sleeping, so it can be closed if removed
+                // from the pool.
+                let Some(deadline) = client.upgrade().map(|c| c.idle_deadline(idle_timeout)) else {
+                    return;
+                };
+                match deadline {
+                    Some(deadline) if deadline <= Instant::now() => break,
+                    Some(deadline) => tokio::time::sleep_until(deadline).await,
+                    None => tokio::time::sleep(idle_timeout).await,
+                }
+            }
+            let Some(pool) = pool.upgrade() else {
+                return;
+            };
+            let mut connections = pool.connections.lock().unwrap();
+            if let Some(state) = connections.get_mut(&key) {
+                state.clients.retain(|c| Arc::as_ptr(c) != client.as_ptr());
+                if state.clients.is_empty() && state.connecting.is_none() {
+                    connections.remove(&key);
+                }
+            }
+            debug!(?key, "closed idle connection");
+        });
     }
+
+    /// Returns the current pooled connections, for debugging.
+    pub fn dump(&self) -> Vec<PoolDump> {
+        let connections = self.inner.connections.lock().unwrap();
+        connections
+            .iter()
+            .filter(|(_, state)| !state.clients.is_empty())
+            .map(|(key, state)| PoolDump {
+                key: key.clone(),
+                connections: state
+                    .clients
+                    .iter()
+                    .map(|c| ConnectionDump {
+                        streams: c.streams.load(Ordering::Acquire),
+                        age_seconds: c.created.elapsed().as_secs(),
+                        open: c.is_open(),
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PoolDump {
+    key: Key,
+    connections: Vec<ConnectionDump>,
 }
+
+#[derive(serde::Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionDump {
+    streams: usize,
+    age_seconds: u64,
+    open: bool,
+}
+
+/// PoolRegistry tracks the connection pools of all running proxies, and serves them on the admin
+/// server.
+#[derive(Default)]
+pub struct PoolRegistry {
+    pools: Mutex<Vec<Weak<PoolInner>>>,
+}
+
+impl PoolRegistry {
+    pub fn register(&self, pool: &Pool) {
+        let mut pools = self.pools.lock().unwrap();
+        pools.retain(|p| p.strong_count() > 0);
+        pools.push(Arc::downgrade(&pool.inner));
+    }
+
+    fn dump(&self) -> Vec<PoolDump> {
+        let pools = self.pools.lock().unwrap();
+        pools
+            .iter()
+            .filter_map(Weak::upgrade)
+            .flat_map(|inner| Pool { inner }.dump())
+            .collect()
+    }
+}
+
+impl crate::admin::AdminHandler for PoolRegistry {
+    fn path(&self) -> &'static str {
+        "/debug/pool"
+    }
+
+    fn description(&self) -> &'static str {
+        "Pooled HBONE connections"
+    }
+
+    fn handle(
+        &self,
+        _req: Request<Incoming>,
+    ) -> Pin<Box<dyn futures_util::Future<Output = Response<Full<Bytes>>> + Sync + Send>> {
+        let response = match serde_json::to_vec(&self.dump()) {
+            Ok(body) => Response::builder()
+                .status(hyper::StatusCode::OK)
+                .header(hyper::header::CONTENT_TYPE, "application/json")
+                .body(body.into())
+                .unwrap(),
+            Err(e) => Response::builder()
+                .status(hyper::StatusCode::INTERNAL_SERVER_ERROR)
+                .body(format!("failed to dump pool: {e}").into())
+                .unwrap(),
+        };
+        Box::pin(std::future::ready(response))
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::convert::Infallible;
@@ -177,8 +456,7 @@ mod test {
 
     use super::*;
 
-    #[tokio::test]
-    async fn test_pool() {
+    async fn spawn_server() -> SocketAddr {
         // We'll bind to 127.0.0.1:3000
         let addr = SocketAddr::from(([127, 0, 0, 1], 0));
         async fn hello_world(req: Request<Incoming>) -> Result<Response<Empty<Bytes>>, Infallible> {
@@ -210,43 +488,135 @@ mod test {
                 });
             }
         });
-        let pool = Pool::new();
-        let key = Key {
+        addr
+    }
+
+    fn key(addr: SocketAddr) -> Key {
+        Key {
             src_id: Identity::default(),
             dst_id: vec![Identity::default()],
             src: IpAddr::from([127, 0, 0, 2]),
             dst: addr,
-        };
-        let connect = || async {
-            let builder = http2::Builder::new(TokioExec);
-
-            let tcp_stream = TcpStream::connect(addr).await?;
-            let (request_sender, connection) = builder
-                .handshake(hyper_util::rt::TokioIo::new(tcp_stream))
-                .await?;
-            // spawn a task to poll the connection and drive the HTTP state
-            tokio::spawn(async move {
-                if let Err(e) = connection.await {
-                    error!("Error in connection handshake: {:?}", e);
-                }
-            });
-            Ok(request_sender)
-        };
-        let req = || {
-            hyper::Request::builder()
-                .uri(format!("http://{addr}"))
-                .method(hyper::Method::GET)
-                .version(hyper::Version::HTTP_2)
-                .body(Empty::<Bytes>::new())
-                .unwrap()
-        };
-        let mut c1 = pool.connect(key.clone(), connect()).await.unwrap();
+            via: None,
+        }
+    }
+
+    async fn connect(addr: SocketAddr) -> Result<http2::SendRequest<Empty<Bytes>>, Error> {
+        let builder = http2::Builder::new(TokioExec);
+
+        let tcp_stream = TcpStream::connect(addr).await?;
+        let (request_sender, connection) = builder
+            .handshake(hyper_util::rt::TokioIo::new(tcp_stream))
+            .await?;
+        // spawn a task to poll the connection and drive the HTTP state
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                error!("Error in connection handshake: {:?}", e);
+            }
+        });
+        Ok(request_sender)
+    }
+
+    fn req(addr: SocketAddr) -> Request<Empty<Bytes>> {
+        hyper::Request::builder()
+            .uri(format!("http://{addr}"))
+            .method(hyper::Method::GET)
+            .version(hyper::Version::HTTP_2)
+            .body(Empty::<Bytes>::new())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_pool() {
+        let addr = spawn_server().await;
+        let pool = Pool::new(&crate::test_helpers::test_config());
+        let key = key(addr);
+        let mut c1 = pool.connect(key.clone(), connect(addr)).await.unwrap();
         let mut c2 = pool
             .connect(key, async { unreachable!("should use pooled connection") })
             .await
             .unwrap();
-        assert_eq!(c1.send_request(req()).await.unwrap().status(), 200);
-        assert_eq!(c1.send_request(req()).await.unwrap().status(), 200);
-        assert_eq!(c2.send_request(req()).await.unwrap().status(), 200);
+        assert_eq!(c1.send_request(req(addr)).await.unwrap().status(), 200);
+        assert_eq!(c1.send_request(req(addr)).await.unwrap().status(), 200);
+        assert_eq!(c2.send_request(req(addr)).await.unwrap().status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_pool_max_streams() {
+        let addr = spawn_server().await;
+        let pool = Pool::new(&Config {
+            pool_max_streams_per_conn: 1,
+            ..crate::test_helpers::test_config()
+        });
+        let key = key(addr);
+        let c1 = pool.connect(key.clone(), connect(addr)).await.unwrap();
+        // The first connection is saturated, so a second one is opened
+        let mut c2 = pool.connect(key.clone(), connect(addr)).await.unwrap();
+        assert_eq!(c2.send_request(req(addr)).await.unwrap().status(), 200);
+        let dump = pool.dump();
+        assert_eq!(dump.len(), 1);
+        assert_eq!(dump[0].connections.len(), 2);
+        assert!(dump[0].connections.iter().all(|c| c.streams == 1));
+
+        // Once a stream is released, its connection can be reused
+        drop(c1);
+        let mut c3 = pool
+            .connect(key, async { unreachable!("should use pooled connection") })
+            .await
+            .unwrap();
+        assert_eq!(c3.send_request(req(addr)).await.unwrap().status(), 200);
+        assert_eq!(pool.dump()[0].connections.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_pool_concurrent_connect() {
+        let addr = spawn_server().await;
+        let pool = Pool::new(&crate::test_helpers::test_config());
+        let key = key(addr);
+
+        // Requests arriving while a connection is established wait for it
+        let (release, wait) = oneshot::channel::<()>();
+        let (c1, c2, _) = tokio::join!(
+            pool.connect(key.clone(), async {
+                wait.await.unwrap();
+                connect(addr).await
+            }),
+            pool.connect(key.clone(), async {
+                unreachable!("should wait for the pending connection")
+            }),
+            async {
+                tokio::task::yield_now().await;
+                release.send(()).unwrap();
+            },
+        );
+        let (mut c1, mut c2) = (c1.unwrap(), c2.unwrap());
+        assert_eq!(c1.send_request(req(addr)).await.unwrap().status(), 200);
+        assert_eq!(c2.send_request(req(addr)).await.unwrap().status(), 200);
+        let dump = pool.dump();
+        assert_eq!(dump[0].connections.len(), 1);
+        assert_eq!(dump[0].connections[0].streams, 2);
+
+        // ... and share its failure, rather than each trying again in turn
+        let other = self::key("127.0.0.1:1".parse().unwrap());
+        let (release, wait) = oneshot::channel::<()>();
+        let (r1, r2, _) = tokio::join!(
+            pool.connect(other.clone(), async {
+                wait.await.unwrap();
+                Err(Error::UnsupportedFeature("test".to_string()))
+            }),
+            pool.connect(other.clone(), async {
+                unreachable!("should wait for the pending connection")
+            }),
+            async {
+                tokio::task::yield_now().await;
+                release.send(()).unwrap();
+            },
+        );
+        assert!(r1.is_err());
+        assert!(r2.unwrap_err().to_string().contains("test"));
+
+        // Once the failed attempt completes, a new request tries again
+        let mut c3 = pool.connect(other, connect(addr)).await.unwrap();
+        assert_eq!(c3.send_request(req(addr)).await.unwrap().status(), 200);
     }
 }
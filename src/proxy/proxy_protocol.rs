@@ -0,0 +1,257 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::identity::Identity;
+use crate::proxy::Error;
+
+// Custom TLVs added to the PROXY protocol v2 headers sent to application tunnels. Values are UTF-8
+// strings, and are only present if known.
+/// The SPIFFE identity of the source.
+pub const TLV_SOURCE_IDENTITY: u8 = 0xD0;
+/// The hostname of the destination service, if the connection was addressed to a service.
+pub const TLV_DESTINATION_SERVICE: u8 = 0xD1;
+/// The name of the source workload, as reported by the client in baggage.
+pub const TLV_SOURCE_WORKLOAD: u8 = 0xD2;
+/// The namespace of the source workload, as reported by the client in baggage.
+pub const TLV_SOURCE_NAMESPACE: u8 = 0xD3;
+/// The trace ID of the connection, as 32 lowercase hex characters.
+pub const TLV_TRACE_ID: u8 = 0xD4;
+
+/// ProxyTlvs are the connection attributes ztunnel sends to application tunnels, as custom TLVs.
+/// Applications can recover them from a header with `ProxyTlvs::decode`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProxyTlvs {
+    pub source_identity: Option<Identity>,
+    pub destination_service: Option<String>,
+    pub source_workload: Option<String>,
+    pub source_namespace: Option<String>,
+    pub trace_id: Option<String>,
+}
+
+impl ProxyTlvs {
+    /// Adds the known attributes to a header being built.
+    pub fn write(&self, mut builder: ppp::v2::Builder) -> std::io::Result<ppp::v2::Builder> {
+        if let Some(id) = &self.source_identity {
+            builder = builder.write_tlv(TLV_SOURCE_IDENTITY, id.to_string().as_bytes())?;
+        }
+        for (kind, value) in [
+            (TLV_DESTINATION_SERVICE, &self.destination_service),
+            (TLV_SOURCE_WORKLOAD, &self.source_workload),
+            (TLV_SOURCE_NAMESPACE, &self.source_namespace),
+            (TLV_TRACE_ID, &self.trace_id),
+        ] {
+            if let Some(value) = value {
+                builder = builder.write_tlv(kind, value.as_bytes())?;
+            }
+        }
+        Ok(builder)
+    }
+
+    /// Decodes the attributes from a complete PROXY protocol v2 header. Unknown TLVs are ignored.
+    pub fn decode(header: &[u8]) -> Result<ProxyTlvs, Error> {
+        let header = ppp::v2::Header::try_from(header)
+            .map_err(|e| Error::ProxyProtocol(format!("invalid v2 header: {e}")))?;
+        Self::from_header(&header)
+    }
+
+    /// Decodes the attributes from a parsed PROXY protocol v2 header. Unknown TLVs are ignored.
+    pub fn from_header(header: &ppp::v2::Header) -> Result<ProxyTlvs, Error> {
+        let mut tlvs = ProxyTlvs::default();
+        for tlv in header.tlvs() {
+            let tlv = tlv.map_err(|e| Error::ProxyProtocol(format!("invalid TLV: {e}")))?;
+            let field = match tlv.kind {
+                TLV_SOURCE_IDENTITY => None,
+                TLV_DESTINATION_SERVICE => Some(&mut tlvs.destination_service),
+                TLV_SOURCE_WORKLOAD => Some(&mut tlvs.source_workload),
+                TLV_SOURCE_NAMESPACE => Some(&mut tlvs.source_namespace),
+                TLV_TRACE_ID => Some(&mut tlvs.trace_id),
+                _ => continue,
+            };
+            let value = std::str::from_utf8(&tlv.value).map_err(|_| {
+                Error::ProxyProtocol(format!("TLV {:#04x} is not valid UTF-8", tlv.kind))
+            })?;
+            match field {
+                Some(field) => *field = Some(value.to_string()),
+                None => {
+                    tlvs.source_identity = Some(Identity::from_str(value).map_err(|_| {
+                        Error::ProxyProtocol(format!("invalid source identity {value}"))
+                    })?)
+                }
+            }
+        }
+        Ok(tlvs)
+    }
+}
+
+// How long a peer has to send the PROXY protocol header once connected.
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+// The longest possible v1 header, including the trailing CRLF.
+const V1_MAX_LENGTH: usize = 107;
+const V1_PREFIX: &[u8] = b"PROXY";
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+// Signature, version and command, protocol, and length
+const V2_FIXED_LENGTH: usize = 16;
+
+/// Returns whether `peer` is trusted to send a PROXY protocol header.
+pub fn is_trusted(trusted: &[ipnet::IpNet], peer: IpAddr) -> bool {
+    trusted.iter().any(|net| net.contains(&peer))
+}
+
+/// Reads a PROXY protocol v1 or v2 header from the start of `stream`, returning the source address
+/// it carries. If the header does not carry one, such as for health checks by the sender, None is
+/// returned. Nothing beyond the header is read from the stream.
+pub async fn read_source<S: AsyncRead + Unpin>(
+    stream: &mut S,
+) -> Result<Option<SocketAddr>, Error> {
+    tokio::time::timeout(HEADER_TIMEOUT, read_header(stream))
+        .await
+        .map_err(|_| Error::ProxyProtocol("timed out reading header".to_string()))?
+}
+
+async fn read_header<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Option<SocketAddr>, Error> {
+    // The shortest header is a v1 header for an unknown protocol, so don't read more than that to
+    // tell the versions apart.
+    let mut buf = vec![0u8; V1_PREFIX.len()];
+    stream.read_exact(&mut buf).await?;
+    if buf == V1_PREFIX {
+        while !buf.ends_with(b"\r\n") {
+            if buf.len() >= V1_MAX_LENGTH {
+                return Err(Error::ProxyProtocol("v1 header too long".to_string()));
+            }
+            buf.push(stream.read_u8().await?);
+        }
+        let header = std::str::from_utf8(&buf)
+            .map_err(|_| Error::ProxyProtocol("v1 header is not valid text".to_string()))?;
+        return parse_v1(header);
+    }
+    if buf != V2_SIGNATURE[..V1_PREFIX.len()] {
+        return Err(Error::ProxyProtocol("missing header".to_string()));
+    }
+    buf.resize(V2_FIXED_LENGTH, 0);
+    stream.read_exact(&mut buf[V1_PREFIX.len()..]).await?;
+    let length = u16::from_be_bytes([buf[14], buf[15]]) as usize;
+    buf.resize(V2_FIXED_LENGTH + length, 0);
+    stream.read_exact(&mut buf[V2_FIXED_LENGTH..]).await?;
+    parse_v2(&buf)
+}
+
+fn parse_v1(header: &str) -> Result<Option<SocketAddr>, Error> {
+    use ppp::v1::{Addresses, Header};
+    let header = Header::try_from(header)
+        .map_err(|e| Error::ProxyProtocol(format!("invalid v1 header: {e}")))?;
+    Ok(match header.addresses {
+        Addresses::Tcp4(a) => Some(SocketAddr::from((a.source_address, a.source_port))),
+        Addresses::Tcp6(a) => Some(SocketAddr::from((a.source_address, a.source_port))),
+        Addresses::Unknown => None,
+    })
+}
+
+fn parse_v2(header: &[u8]) -> Result<Option<SocketAddr>, Error> {
+    use ppp::v2::{Addresses, Command, Header};
+    let header = Header::try_from(header)
+        .map_err(|e| Error::ProxyProtocol(format!("invalid v2 header: {e}")))?;
+    if header.command == Command::Local {
+        return Ok(None);
+    }
+    Ok(match header.addresses {
+        Addresses::IPv4(a) => Some(SocketAddr::from((a.source_address, a.source_port))),
+        Addresses::IPv6(a) => Some(SocketAddr::from((a.source_address, a.source_port))),
+        Addresses::Unix(_) | Addresses::Unspecified => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ppp::v2::{Builder, Command, Protocol, Version};
+    use tokio::io::AsyncWriteExt;
+
+    async fn read(header: &[u8]) -> (Result<Option<SocketAddr>, Error>, Vec<u8>) {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client.write_all(header).await.unwrap();
+        client.write_all(b"payload").await.unwrap();
+        drop(client);
+        let res = read_source(&mut server).await;
+        let mut rest = Vec::new();
+        server.read_to_end(&mut rest).await.unwrap();
+        (res, rest)
+    }
+
+    #[tokio::test]
+    async fn proxy_protocol_header() {
+        let (res, rest) = read(b"PROXY TCP4 10.0.0.1 10.0.0.2 35000 80\r\n").await;
+        assert_eq!(res.unwrap(), Some("10.0.0.1:35000".parse().unwrap()));
+        assert_eq!(rest, b"payload");
+
+        let (res, rest) = read(b"PROXY UNKNOWN\r\n").await;
+        assert_eq!(res.unwrap(), None);
+        assert_eq!(rest, b"payload");
+
+        let src: SocketAddr = "[fd00::1]:35000".parse().unwrap();
+        let dst: SocketAddr = "[fd00::2]:80".parse().unwrap();
+        let v2 =
+            Builder::with_addresses(Version::Two | Command::Proxy, Protocol::Stream, (src, dst))
+                .build()
+                .unwrap();
+        let (res, rest) = read(&v2).await;
+        assert_eq!(res.unwrap(), Some(src));
+        assert_eq!(rest, b"payload");
+
+        let (res, _) = read(b"GET / HTTP/1.1\r\n").await;
+        assert!(matches!(res, Err(Error::ProxyProtocol(_))), "{res:?}");
+    }
+
+    #[test]
+    fn proxy_tlvs() {
+        let tlvs = ProxyTlvs {
+            source_identity: Some(
+                Identity::from_str("spiffe://cluster.local/ns/ns1/sa/sa1").unwrap(),
+            ),
+            destination_service: Some("svc.ns2.svc.cluster.local".to_string()),
+            source_workload: Some("client".to_string()),
+            source_namespace: Some("ns1".to_string()),
+            trace_id: Some("4bf92f3577b34da6a3ce929d0e0e4736".to_string()),
+        };
+        let src: SocketAddr = "10.0.0.1:35000".parse().unwrap();
+        let dst: SocketAddr = "10.0.0.2:80".parse().unwrap();
+        let builder =
+            Builder::with_addresses(Version::Two | Command::Proxy, Protocol::Stream, (src, dst));
+        let header = tlvs.write(builder).unwrap().build().unwrap();
+        assert_eq!(ProxyTlvs::decode(&header).unwrap(), tlvs);
+
+        // Unset attributes are omitted
+        let builder =
+            Builder::with_addresses(Version::Two | Command::Proxy, Protocol::Stream, (src, dst));
+        let header = ProxyTlvs::default()
+            .write(builder)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(ProxyTlvs::decode(&header).unwrap(), ProxyTlvs::default());
+    }
+
+    #[test]
+    fn trusted_peers() {
+        let trusted = vec!["10.0.0.0/8".parse().unwrap()];
+        assert!(is_trusted(&trusted, "10.1.2.3".parse().unwrap()));
+        assert!(!is_trusted(&trusted, "192.168.0.1".parse().unwrap()));
+    }
+}
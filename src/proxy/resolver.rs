@@ -0,0 +1,60 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tracing::debug;
+
+use crate::dns;
+use crate::dns::server::MeshResolver;
+use crate::proxy::{Error, ProxyInputs};
+
+/// Builds a resolver for the domain name targets of proxy requests, such as SOCKS5 and HTTP
+/// CONNECT. Names are resolved against the mesh first, as the DNS proxy would.
+pub(super) fn mesh_resolver(pi: &ProxyInputs) -> Result<Arc<MeshResolver>, Error> {
+    let forwarder = dns::forwarder_for_mode(pi.cfg.proxy_mode)?;
+    Ok(Arc::new(MeshResolver::new(
+        pi.cfg.cluster_domain.clone(),
+        pi.cfg.network.clone(),
+        pi.state.clone(),
+        forwarder,
+    )))
+}
+
+/// Resolves a domain name target. Mesh services and workloads are looked up first, from the
+/// perspective of the client, falling back to the system resolver for everything else.
+pub(super) async fn resolve(
+    resolver: &MeshResolver,
+    client: SocketAddr,
+    domain: &str,
+    port: u16,
+) -> Result<SocketAddr, anyhow::Error> {
+    if let Some(ips) = resolver.resolve(client, domain) {
+        let ip = ips
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("no addresses for mesh host {domain}"))?;
+        debug!("resolved {domain} to {ip} from mesh state");
+        return Ok(SocketAddr::new(*ip, port));
+    }
+    let addr = tokio::net::lookup_host((domain, port))
+        .await?
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("no addresses for host {domain}"))?;
+    debug!(
+        "resolved {domain} to {} with the system resolver",
+        addr.ip()
+    );
+    Ok(addr)
+}
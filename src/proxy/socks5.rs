@@ -14,22 +14,34 @@
 
 use anyhow::Result;
 use byteorder::{BigEndian, ByteOrder};
+use bytes::Bytes;
 use drain::Watch;
-use std::net::{IpAddr, SocketAddr};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use tokio::io::AsyncReadExt;
 use tokio::io::AsyncWriteExt;
-use tokio::net::{TcpListener, TcpStream};
-use tracing::{error, info};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::{debug, error, info, trace, warn};
 
+use crate::dns::server::MeshResolver;
+use crate::identity::Identity;
+use crate::proxy::metrics::{Reporter, Socks5AuthFailure, Socks5AuthFailureLabels};
 use crate::proxy::outbound::OutboundConnection;
-use crate::proxy::{util, Error, ProxyInputs, TraceParent};
+use crate::proxy::resolver::{mesh_resolver, resolve};
+use crate::proxy::util::InFlight;
+use crate::proxy::{metrics, udp, util, Error, ProxyInputs, TraceParent};
 use crate::socket;
+use crate::state::workload::{NetworkAddress, Workload};
 
 pub(super) struct Socks5 {
     pi: ProxyInputs,
     listener: TcpListener,
     drain: Watch,
+    resolver: Arc<MeshResolver>,
 }
 
 impl Socks5 {
@@ -45,10 +57,13 @@ impl Socks5 {
             "listener established",
         );
 
+        let resolver = mesh_resolver(&pi)?;
+
         Ok(Socks5 {
             pi,
             listener,
             drain,
+            resolver,
         })
     }
 
@@ -57,23 +72,48 @@ impl Socks5 {
     }
 
     pub async fn run(self) {
-        let inner_drain = self.drain.clone();
-        let accept = async move {
+        let Socks5 {
+            pi,
+            listener,
+            drain,
+            resolver,
+        } = self;
+        let connections = util::ConnectionDrain::default();
+        let handshakes = Arc::new(Semaphore::new(MAX_CONCURRENT_HANDSHAKES));
+        let accept = async {
             loop {
+                // Stop accepting while too many clients are mid-handshake, leaving new ones in
+                // the listen backlog.
+                let permit = handshakes
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("semaphore is never closed");
                 // Asynchronously wait for an inbound socket.
-                let socket = self.listener.accept().await;
-                let inpod = self.pi.cfg.inpod_enabled;
-                let stream_drain = inner_drain.clone();
+                let socket = listener.accept().await;
                 match socket {
                     Ok((stream, remote)) => {
                         info!("accepted outbound connection from {}", remote);
                         let oc = OutboundConnection {
-                            pi: self.pi.clone(),
+                            pi: pi.clone(),
                             id: TraceParent::new(),
+                            authenticated_source: None,
                         };
+                        let (in_flight, stream_drain) = connections.track();
+                        let resolver = resolver.clone();
                         tokio::spawn(async move {
-                            if let Err(err) = handle(oc, stream, stream_drain, inpod).await {
-                                log::error!("handshake error: {}", err);
+                            // The client may stall the handshake, so give up on it once the drain
+                            // deadline passes rather than holding up the drain.
+                            let force = stream_drain.clone();
+                            tokio::select! {
+                                res = handle(oc, stream, stream_drain, in_flight, resolver, permit) => {
+                                    if let Err(err) = res {
+                                        log::error!("handshake error: {}", err);
+                                    }
+                                }
+                                _ = force.signaled() => {
+                                    debug!("socks5 handshake cancelled by drain");
+                                }
                             }
                         });
                     }
@@ -87,26 +127,91 @@ impl Socks5 {
             }
         };
 
-        tokio::select! {
-            res = accept => { res }
-            _ = self.drain.signaled() => {
-                // out_drain_signal.drain().await;
-                info!("socks5 drained");
-            }
+        // Stop accepting once we drain, then wait for in-flight connections to complete.
+        let release = tokio::select! {
+            _ = accept => return,
+            release = drain.signaled() => { release }
+        };
+        drop(listener);
+        let cut_off = connections.drain(pi.cfg.termination_drain_duration).await;
+        if cut_off > 0 {
+            warn!(
+                connections = cut_off,
+                "socks5 drain deadline exceeded, closed remaining connections"
+            );
         }
+        info!("socks5 drained");
+        drop(release);
     }
 }
 
 // handle will process a SOCKS5 connection. This supports a minimal subset of the protocol,
 // sufficient to integrate with common clients:
-// - only unauthenticated requests
-// - only CONNECT, with IPv4 or IPv6
+// - unauthenticated requests, or username/password authentication if credentials are configured
+// - CONNECT and UDP ASSOCIATE, with IPv4, IPv6 or a domain name
 async fn handle(
     mut oc: OutboundConnection,
     mut stream: TcpStream,
     out_drain: Watch,
-    is_inpod: bool,
+    in_flight: InFlight,
+    resolver: Arc<MeshResolver>,
+    handshake_permit: OwnedSemaphorePermit,
 ) -> Result<(), anyhow::Error> {
+    let remote_addr = socket::to_canonical(stream.peer_addr().expect("must receive peer addr"));
+
+    let (command, target, port) = tokio::time::timeout(
+        HANDSHAKE_TIMEOUT,
+        negotiate(&mut oc, &mut stream, remote_addr),
+    )
+    .await
+    .map_err(|_| anyhow::anyhow!("handshake timed out"))??;
+    drop(handshake_permit);
+
+    if command == CMD_UDP_ASSOCIATE {
+        // The requested address is only a hint of where the client will send from; we only accept
+        // datagrams from the client's address anyway.
+        return udp_associate(oc, stream, remote_addr, out_drain, in_flight, resolver).await;
+    }
+
+    let host = match target {
+        Target::Ip(ip) => SocketAddr::new(ip, port),
+        Target::Domain(domain) => match resolve(&resolver, remote_addr, &domain, port).await {
+            Ok(host) => host,
+            Err(e) => {
+                reply(&mut stream, REPLY_HOST_UNREACHABLE, UNSPECIFIED_ADDR).await?;
+                return Err(e);
+            }
+        },
+    };
+
+    // Send dummy values - the client generally ignores it.
+    reply(&mut stream, REPLY_SUCCEEDED, UNSPECIFIED_ADDR).await?;
+
+    info!("accepted connection from {remote_addr} to {host}");
+    // The connection stays in flight until this task completes. When we drain, it gets until the
+    // drain deadline to complete normally before `out_drain` is signaled to cancel it.
+    tokio::spawn(async move {
+        oc.proxy_to_cancellable(
+            stream,
+            Bytes::new(),
+            remote_addr,
+            host,
+            true,
+            Some(out_drain),
+        )
+        .await;
+        drop(in_flight);
+    });
+    Ok(())
+}
+
+/// Negotiates the auth method, authenticates the client if required and reads its request,
+/// returning the command, target and port.
+async fn negotiate(
+    oc: &mut OutboundConnection,
+    stream: &mut TcpStream,
+    remote_addr: SocketAddr,
+) -> Result<(u8, Target, u16), anyhow::Error> {
     // Version(5), Number of auth methods
     let mut version = [0u8; 2];
     stream.read_exact(&mut version).await?;
@@ -125,15 +230,38 @@ async fn handle(
     let mut methods = vec![0u8; nmethods as usize];
     stream.read_exact(&mut methods).await?;
 
-    // Client must include 'unauthenticated' (0).
-    if !methods.into_iter().any(|x| x == 0) {
+    // Once credentials are configured, clients must authenticate; otherwise they must allow
+    // 'unauthenticated' (0).
+    let authenticate = !oc.pi.cfg.socks5_credentials.is_empty();
+    let method = if authenticate {
+        AUTH_USERNAME_PASSWORD
+    } else {
+        AUTH_NONE
+    };
+    if !methods.contains(&method) {
+        stream
+            .write_all(&[0x05, AUTH_NO_ACCEPTABLE_METHODS])
+            .await?;
+        if authenticate {
+            warn!(client=%remote_addr, "socks5 client does not support username/password authentication");
+            oc.pi
+                .metrics
+                .socks5_auth_failures
+                .get_or_create(&Socks5AuthFailureLabels {
+                    reason: Socks5AuthFailure::unsupported_method,
+                })
+                .inc();
+        }
         return Err(anyhow::anyhow!("unsupported auth method"));
     }
+    stream.write_all(&[0x05, method]).await?;
 
-    // Select 'unauthenticated' (0).
-    stream.write_all(&[0x05, 0x00]).await?;
+    if authenticate {
+        let source = authenticate_user(&oc.pi, stream, remote_addr).await?;
+        oc.authenticated_source = Some(source);
+    }
 
-    // Version(5), Command - only support CONNECT (1)
+    // Version(5), Command - only support CONNECT (1) and UDP ASSOCIATE (3)
     let mut version_command = [0u8; 2];
     stream.read_exact(&mut version_command).await?;
     let version = version_command[0];
@@ -142,7 +270,9 @@ async fn handle(
         return Err(anyhow::anyhow!("unsupported version"));
     }
 
-    if version_command[1] != 1 {
+    let command = version_command[1];
+    if command != CMD_CONNECT && command != CMD_UDP_ASSOCIATE {
+        reply(stream, REPLY_COMMAND_NOT_SUPPORTED, UNSPECIFIED_ADDR).await?;
         return Err(anyhow::anyhow!("unsupported command"));
     }
 
@@ -153,29 +283,26 @@ async fn handle(
     let mut atyp = [0u8];
     stream.read_exact(&mut atyp).await?;
 
-    let ip;
-
-    match atyp[0] {
+    let target = match atyp[0] {
         0x01 => {
             let mut hostb = [0u8; 4];
             stream.read_exact(&mut hostb).await?;
-            ip = IpAddr::V4(hostb.into());
+            Target::Ip(IpAddr::V4(hostb.into()))
         }
         0x04 => {
             let mut hostb = [0u8; 16];
             stream.read_exact(&mut hostb).await?;
-            ip = IpAddr::V6(hostb.into());
+            Target::Ip(IpAddr::V6(hostb.into()))
         }
         0x03 => {
             let mut domain_length = [0u8];
             stream.read_exact(&mut domain_length).await?;
             let mut domain = vec![0u8; domain_length[0] as usize];
             stream.read_exact(&mut domain).await?;
-            // TODO: DNS lookup, if we want to integrate with HTTP-based apps without
-            // a DNS server.
-            return Err(anyhow::anyhow!("unsupported host"));
+            Target::Domain(String::from_utf8(domain)?)
         }
         _ => {
+            reply(stream, REPLY_ADDRESS_TYPE_NOT_SUPPORTED, UNSPECIFIED_ADDR).await?;
             return Err(anyhow::anyhow!("unsupported host"));
         }
     };
@@ -183,31 +310,464 @@ async fn handle(
     let mut port = [0u8; 2];
     stream.read_exact(&mut port).await?;
     let port = BigEndian::read_u16(&port);
+    Ok((command, target, port))
+}
 
-    let host = SocketAddr::new(ip, port);
+const AUTH_NONE: u8 = 0x00;
+const AUTH_USERNAME_PASSWORD: u8 = 0x02;
+const AUTH_NO_ACCEPTABLE_METHODS: u8 = 0xff;
 
-    let remote_addr = socket::to_canonical(stream.peer_addr().expect("must receive peer addr"));
+const CMD_CONNECT: u8 = 0x01;
+const CMD_UDP_ASSOCIATE: u8 = 0x03;
 
-    // Send dummy values - the client generally ignores it.
-    let buf = [
-        0x05u8, // versuib
-        0x00, 0x00, // success, rsv
-        0x01, 0x00, 0x00, 0x00, 0x00, // IPv4
-        0x00, 0x00, // port
-    ];
-    stream.write_all(&buf).await?;
+const REPLY_SUCCEEDED: u8 = 0x00;
+const REPLY_GENERAL_FAILURE: u8 = 0x01;
+const REPLY_NOT_ALLOWED: u8 = 0x02;
+const REPLY_HOST_UNREACHABLE: u8 = 0x04;
+const REPLY_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+const REPLY_ADDRESS_TYPE_NOT_SUPPORTED: u8 = 0x08;
 
-    info!("accepted connection from {remote_addr} to {host}");
-    // For inpod, we want this `spawn` to guaranteed-terminate when we drain - the workload is gone.
-    // For non-inpod (shared instance for all workloads), let the spawned task run until the proxy process
-    // itself is killed, or the connection terminates normally.
+const UNSPECIFIED_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+
+// Clients must complete the handshake, including authentication, within this time.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Connections still in the handshake beyond this many are left in the listen backlog.
+const MAX_CONCURRENT_HANDSHAKES: usize = 1024;
+
+// Datagrams to more distinct destinations than this, within one association, are dropped.
+const MAX_UDP_DESTINATIONS: usize = 1024;
+
+/// Writes a reply to a request, with the bound address.
+async fn reply(stream: &mut TcpStream, code: u8, bound: SocketAddr) -> std::io::Result<()> {
+    let mut buf = vec![0x05, code, 0x00];
+    encode_addr(&mut buf, bound);
+    stream.write_all(&buf).await
+}
+
+/// Performs username/password authentication (RFC 1929), returning the workload the client is
+/// treated as. The workload only carries the credential's identity; it is not found in the mesh.
+async fn authenticate_user(
+    pi: &ProxyInputs,
+    stream: &mut TcpStream,
+    remote_addr: SocketAddr,
+) -> Result<Workload, anyhow::Error> {
+    // Version(1), username length
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header).await?;
+    if header[0] != 0x01 {
+        return Err(anyhow::anyhow!("unsupported auth version"));
+    }
+    let mut user = vec![0u8; header[1] as usize];
+    stream.read_exact(&mut user).await?;
+    let mut password_len = [0u8];
+    stream.read_exact(&mut password_len).await?;
+    let mut password = vec![0u8; password_len[0] as usize];
+    stream.read_exact(&mut password).await?;
+
+    let user = String::from_utf8_lossy(&user);
+    let credential = pi
+        .cfg
+        .socks5_credentials
+        .get(user.as_ref())
+        .filter(|c| constant_time_eq(c.password.as_bytes(), &password));
+    let Some(credential) = credential else {
+        stream.write_all(&[0x01, 0x01]).await?;
+        warn!(%user, client=%remote_addr, "socks5 authentication failed");
+        pi.metrics
+            .socks5_auth_failures
+            .get_or_create(&Socks5AuthFailureLabels {
+                reason: Socks5AuthFailure::invalid_credentials,
+            })
+            .inc();
+        return Err(anyhow::anyhow!("authentication failed"));
+    };
+    stream.write_all(&[0x01, 0x00]).await?;
+    info!(%user, client=%remote_addr, identity=%credential.identity, "socks5 client authenticated");
+    Ok(authenticated_workload(
+        &pi.cfg.network,
+        &credential.identity,
+    ))
+}
+
+// The uid is derived from the identity, so that each credential gets its own circuit breaker and
+// bandwidth limits rather than sharing them with every other SOCKS5 client.
+pub(super) fn authenticated_workload(network: &str, identity: &Identity) -> Workload {
+    let uid = format!("socks5/{identity}");
+    let Identity::Spiffe {
+        trust_domain,
+        namespace,
+        service_account,
+    } = identity.clone();
+    Workload {
+        workload_ips: vec![],
+        waypoint: None,
+        network_gateway: None,
+        gateway_address: None,
+        protocol: Default::default(),
+        uid,
+        name: service_account.clone(),
+        namespace,
+        trust_domain,
+        service_account,
+        network: network.to_string(),
+        workload_name: String::new(),
+        workload_type: String::new(),
+        canonical_name: String::new(),
+        canonical_revision: String::new(),
+        hostname: String::new(),
+        node: String::new(),
+        native_tunnel: false,
+        application_tunnel: None,
+        authorization_policies: vec![],
+        status: Default::default(),
+        cluster_id: String::new(),
+        locality: Default::default(),
+        bandwidth_limit: Default::default(),
+    }
+}
+
+// Compares secrets without returning early, so the comparison time does not leak where they differ.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Relays datagrams for a UDP ASSOCIATE request, until the client closes the control connection.
+/// Each datagram is sent to the destination named in its header, which is mapped to an upstream the
+/// same way as captured UDP traffic. Datagrams are sent as plaintext, so an authenticated client's
+/// identity is only used for policy and metrics, and is never presented to the destination. For
+/// the same reason, destinations which require HBONE are refused.
+async fn udp_associate(
+    oc: OutboundConnection,
+    mut stream: TcpStream,
+    remote_addr: SocketAddr,
+    out_drain: Watch,
+    in_flight: InFlight,
+    resolver: Arc<MeshResolver>,
+) -> Result<(), anyhow::Error> {
+    let start = Instant::now();
+    let pi = oc.pi;
+    let source = match oc.authenticated_source {
+        Some(wl) => Some(wl),
+        None => {
+            let network_addr = NetworkAddress {
+                network: pi.cfg.network.clone(),
+                address: remote_addr.ip(),
+            };
+            pi.state.fetch_workload(&network_addr).await
+        }
+    };
+    let Some(source) = source else {
+        let local_addr = stream.local_addr()?;
+        metrics::log_early_deny(
+            &pi.access_log,
+            remote_addr,
+            local_addr,
+            Reporter::source,
+            Error::UnknownSource(remote_addr.ip()),
+        );
+        reply(&mut stream, REPLY_NOT_ALLOWED, UNSPECIFIED_ADDR).await?;
+        return Err(anyhow::anyhow!("unknown source {}", remote_addr.ip()));
+    };
+
+    let sockets = stream.local_addr().and_then(|local| {
+        let relay = pi
+            .socket_factory
+            .udp_bind(SocketAddr::new(socket::to_canonical(local).ip(), 0))?;
+        let upstream = pi
+            .socket_factory
+            .udp_bind(SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)))
+            .or_else(|_| {
+                pi.socket_factory
+                    .udp_bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)))
+            })?;
+        Ok((relay, upstream))
+    });
+    let (relay, upstream) = match sockets {
+        Ok(sockets) => sockets,
+        Err(e) => {
+            reply(&mut stream, REPLY_GENERAL_FAILURE, UNSPECIFIED_ADDR).await?;
+            return Err(e.into());
+        }
+    };
+    let relay_addr = relay.local_addr()?;
+    reply(&mut stream, REPLY_SUCCEEDED, relay_addr).await?;
+    info!(client=%remote_addr, identity=%source.identity(), relay=%relay_addr, "accepted udp association");
+
+    let connection_metrics = metrics::ConnectionOpen {
+        reporter: Reporter::source,
+        source: Some(source.clone()),
+        derived_source: None,
+        destination: None,
+        destination_service: None,
+        connection_security_policy: metrics::SecurityPolicy::unknown,
+    };
+    let result_tracker = metrics::ConnectionResult::new(
+        remote_addr,
+        relay_addr,
+        None,
+        start,
+        &connection_metrics,
+        pi.metrics.clone(),
+        pi.access_log.clone(),
+    );
+
+    // The association stays in flight until the client closes the control connection. When we
+    // drain, it gets until the drain deadline before `out_drain` is signaled to close it.
     tokio::spawn(async move {
-        let drain = match is_inpod {
-            true => Some(out_drain),
-            false => None,
-        };
-        oc.proxy_to_cancellable(stream, remote_addr, host, true, drain)
-            .await;
+        let res = relay_udp(
+            &pi,
+            &source,
+            &resolver,
+            remote_addr,
+            &mut stream,
+            &relay,
+            &upstream,
+            out_drain,
+        )
+        .await;
+        result_tracker.record(res);
+        drop(in_flight);
     });
     Ok(())
 }
+
+/// Relays datagrams between the client and upstreams, until the control connection is closed or
+/// `drain` is signaled. Returns the bytes sent (to upstreams, to the client).
+#[allow(clippy::too_many_arguments)]
+async fn relay_udp(
+    pi: &ProxyInputs,
+    source: &Workload,
+    resolver: &MeshResolver,
+    client: SocketAddr,
+    control: &mut TcpStream,
+    relay: &UdpSocket,
+    upstream: &UdpSocket,
+    drain: Watch,
+) -> Result<(u64, u64), Error> {
+    // A dual-stack upstream socket can only send to IPv4 destinations as IPv4-mapped addresses.
+    let dual_stack = upstream.local_addr()?.is_ipv6();
+    // The address the client sends datagrams from, once known.
+    let mut client_udp: Option<SocketAddr> = None;
+    // Requested destination to upstream, and back, so replies can be attributed to the destination
+    // the client sent to.
+    let mut destinations: HashMap<SocketAddr, SocketAddr> = HashMap::new();
+    let mut origins: HashMap<SocketAddr, SocketAddr> = HashMap::new();
+
+    let (mut to_upstream, mut to_downstream): (u64, u64) = (0, 0);
+    let mut down_buf = vec![0u8; udp::MAX_DATAGRAM_SIZE];
+    let mut up_buf = vec![0u8; udp::MAX_DATAGRAM_SIZE];
+    let mut control_buf = [0u8; 64];
+    let drained = drain.signaled();
+    tokio::pin!(drained);
+    loop {
+        tokio::select! {
+            res = relay.recv_from(&mut down_buf) => {
+                let (n, from) = res?;
+                let from = socket::to_canonical(from);
+                if from.ip() != client.ip() {
+                    trace!(%client, %from, "dropping datagram from unexpected address");
+                    continue;
+                }
+                client_udp = Some(from);
+                let Some((target, port, header_len)) = parse_udp_header(&down_buf[..n]) else {
+                    trace!(%client, "dropping malformed or fragmented datagram");
+                    continue;
+                };
+                let dest = match target {
+                    Target::Ip(ip) => SocketAddr::new(ip, port),
+                    Target::Domain(domain) => match resolve(resolver, client, &domain, port).await {
+                        Ok(dest) => dest,
+                        Err(e) => {
+                            debug!(%client, "dropping datagram to {domain}: {e}");
+                            continue;
+                        }
+                    },
+                };
+                let upstream_addr = match destinations.get(&dest) {
+                    Some(upstream_addr) => *upstream_addr,
+                    None if destinations.len() >= MAX_UDP_DESTINATIONS => {
+                        debug!(%client, %dest, "dropping datagram, too many destinations");
+                        continue;
+                    }
+                    None => match udp::select_upstream(pi, source, dest).await {
+                        Ok((upstream_addr, _, _)) => {
+                            debug!(%client, %dest, upstream=%upstream_addr, "new udp destination");
+                            destinations.insert(dest, upstream_addr);
+                            origins.insert(upstream_addr, dest);
+                            upstream_addr
+                        }
+                        Err(e) => {
+                            debug!(%client, %dest, "dropping datagram: {e}");
+                            continue;
+                        }
+                    },
+                };
+                let send_addr = match upstream_addr.ip() {
+                    IpAddr::V4(ip) if dual_stack => {
+                        SocketAddr::new(IpAddr::V6(ip.to_ipv6_mapped()), upstream_addr.port())
+                    }
+                    _ => upstream_addr,
+                };
+                match upstream.send_to(&down_buf[header_len..n], send_addr).await {
+                    Ok(sent) => to_upstream += sent as u64,
+                    Err(e) => debug!(%client, upstream=%upstream_addr, "failed to send datagram: {e}"),
+                }
+            }
+            res = upstream.recv_from(&mut up_buf) => {
+                let (n, from) = match res {
+                    Ok(res) => res,
+                    Err(e) => {
+                        debug!(%client, "failed to receive from upstream: {e}");
+                        continue;
+                    }
+                };
+                let from = socket::to_canonical(from);
+                let (Some(client_udp), Some(origin)) = (client_udp, origins.get(&from)) else {
+                    trace!(%client, %from, "dropping datagram from unknown upstream");
+                    continue;
+                };
+                let mut datagram = vec![0x00, 0x00, 0x00];
+                encode_addr(&mut datagram, *origin);
+                datagram.extend_from_slice(&up_buf[..n]);
+                relay.send_to(&datagram, client_udp).await?;
+                to_downstream += n as u64;
+            }
+            res = control.read(&mut control_buf) => {
+                // The association ends when the control connection does; anything sent on it is
+                // ignored.
+                if matches!(res, Ok(0) | Err(_)) {
+                    break;
+                }
+            }
+            _ = &mut drained => {
+                info!(%client, "socks5 udp association drained");
+                break;
+            }
+        }
+    }
+    Ok((to_upstream, to_downstream))
+}
+
+/// Appends a SOCKS5 address and port, as used in replies and UDP headers.
+fn encode_addr(buf: &mut Vec<u8>, addr: SocketAddr) {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            buf.push(0x01);
+            buf.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            buf.push(0x04);
+            buf.extend_from_slice(&ip.octets());
+        }
+    }
+    buf.extend_from_slice(&addr.port().to_be_bytes());
+}
+
+/// Parses the header of a UDP request: RSV(2), FRAG(1), ATYP(1), DST.ADDR, DST.PORT(2). Returns the
+/// target, port, and length of the header. Fragmented datagrams are not supported.
+fn parse_udp_header(buf: &[u8]) -> Option<(Target, u16, usize)> {
+    let (&frag, rest) = buf.get(2..)?.split_first()?;
+    if frag != 0 {
+        return None;
+    }
+    let (&atyp, rest) = rest.split_first()?;
+    let (target, addr_len) = match atyp {
+        0x01 => {
+            let b: [u8; 4] = rest.get(..4)?.try_into().ok()?;
+            (Target::Ip(IpAddr::V4(b.into())), 4)
+        }
+        0x04 => {
+            let b: [u8; 16] = rest.get(..16)?.try_into().ok()?;
+            (Target::Ip(IpAddr::V6(b.into())), 16)
+        }
+        0x03 => {
+            let len = *rest.first()? as usize;
+            let domain = std::str::from_utf8(rest.get(1..1 + len)?).ok()?;
+            (Target::Domain(domain.to_string()), 1 + len)
+        }
+        _ => return None,
+    };
+    let port = rest.get(addr_len..addr_len + 2)?;
+    Some((target, BigEndian::read_u16(port), 4 + addr_len + 2))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Target {
+    Ip(IpAddr),
+    Domain(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn udp_header() {
+        let addr: SocketAddr = "10.0.0.1:53".parse().unwrap();
+        let mut datagram = vec![0x00, 0x00, 0x00];
+        encode_addr(&mut datagram, addr);
+        datagram.extend_from_slice(b"payload");
+        let (target, port, header_len) = parse_udp_header(&datagram).unwrap();
+        assert_eq!(target, Target::Ip(addr.ip()));
+        assert_eq!(port, 53);
+        assert_eq!(&datagram[header_len..], b"payload");
+
+        let mut datagram = vec![0x00, 0x00, 0x00, 0x03, 11];
+        datagram.extend_from_slice(b"example.com\x01\xbbpayload");
+        let (target, port, header_len) = parse_udp_header(&datagram).unwrap();
+        assert_eq!(target, Target::Domain("example.com".to_string()));
+        assert_eq!(port, 443);
+        assert_eq!(&datagram[header_len..], b"payload");
+
+        // Fragmented and truncated datagrams are dropped
+        assert_eq!(
+            parse_udp_header(&[0x00, 0x00, 0x01, 0x01, 10, 0, 0, 1, 0, 53]),
+            None
+        );
+        assert_eq!(parse_udp_header(&[0x00, 0x00, 0x00, 0x01, 10, 0]), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn authenticated_workloads_are_limited_independently() {
+        use crate::proxy::bandwidth::{BandwidthLimiter, StreamSide};
+        use crate::proxy::connection_manager::{ConnectionKey, ConnectionManager};
+        use crate::test_helpers::helpers::test_proxy_metrics;
+
+        let identity = |sa: &str| Identity::Spiffe {
+            trust_domain: "cluster.local".to_string(),
+            namespace: "default".to_string(),
+            service_account: sa.to_string(),
+        };
+        let alice = authenticated_workload("", &identity("alice"));
+        let bob = authenticated_workload("", &identity("bob"));
+        assert_ne!(alice.uid, bob.uid);
+
+        // Each credential has its own connection limit
+        let connections = ConnectionManager::default();
+        let track = |wl: &Workload| {
+            connections.try_track_limited(vec![(
+                ConnectionKey::SourceWorkload(Reporter::source, wl.uid.clone()),
+                Some(1),
+            )])
+        };
+        let _alice_conn = track(&alice).unwrap();
+        assert!(track(&alice).is_err());
+        assert!(track(&bob).is_ok());
+
+        // ...and its own bandwidth bucket
+        let cfg = crate::config::Config {
+            workload_bandwidth_limit: "1000:".parse().unwrap(),
+            ..crate::config::parse_config().unwrap()
+        };
+        let limiter = BandwidthLimiter::new(&cfg, test_proxy_metrics());
+        let alice_shaper = limiter
+            .shaper(Some(&alice), None, StreamSide::Source)
+            .unwrap();
+        let bob_shaper = limiter
+            .shaper(Some(&bob), None, StreamSide::Source)
+            .unwrap();
+        assert_eq!(alice_shaper.on_read(1500), Duration::from_millis(500));
+        assert_eq!(bob_shaper.on_read(1000), Duration::ZERO);
+    }
+}
@@ -0,0 +1,753 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use bytes::Bytes;
+use drain::Watch;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tracing::{debug, error, info, trace, warn, Instrument};
+
+use crate::config::ProxyMode;
+use crate::proxy::metrics::Reporter;
+use crate::proxy::Error;
+use crate::proxy::{metrics, util, ProxyInputs};
+use crate::rbac;
+use crate::state::service::ServiceDescription;
+use crate::state::workload::address::Address;
+use crate::state::workload::gatewayaddress::Destination;
+use crate::state::workload::{NetworkAddress, Protocol, Workload};
+use crate::{proxy, socket};
+
+// Large enough for any datagram we may receive.
+pub(super) const MAX_DATAGRAM_SIZE: usize = 65_535;
+// Datagrams queued for a flow that has not yet been set up (or is slow to send upstream).
+// Beyond this, datagrams are dropped, as they would be by the network.
+const FLOW_QUEUE_SIZE: usize = 128;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Direction {
+    Outbound,
+    InboundPassthrough,
+}
+
+impl Direction {
+    fn component(&self) -> &'static str {
+        match self {
+            Direction::Outbound => "outbound udp",
+            Direction::InboundPassthrough => "inbound plaintext udp",
+        }
+    }
+}
+
+/// Flows are identified by the client address and the original destination it sent to.
+type FlowKey = (SocketAddr, SocketAddr);
+
+/// Sessions tracks the active UDP flows for a listener. Each flow has a dedicated task, which is fed
+/// datagrams through a channel, and removes itself once it has been idle for `udp_idle_timeout`.
+#[derive(Clone, Default)]
+struct Sessions(Arc<Mutex<HashMap<FlowKey, mpsc::Sender<Bytes>>>>);
+
+impl Sessions {
+    /// Sends a datagram to the flow identified by `key`. If there is no such flow, a new one is
+    /// created and its receiver is returned, so the caller can start the flow.
+    fn dispatch(&self, key: FlowKey, datagram: Bytes) -> Option<mpsc::Receiver<Bytes>> {
+        let mut sessions = self.0.lock().unwrap();
+        if let Some(tx) = sessions.get(&key) {
+            if let Err(e) = tx.try_send(datagram) {
+                trace!(src=%key.0, dst=%key.1, "dropping datagram: {e}");
+            }
+            return None;
+        }
+        let (tx, rx) = mpsc::channel(FLOW_QUEUE_SIZE);
+        tx.try_send(datagram)
+            .expect("new channel must have capacity");
+        sessions.insert(key, tx);
+        Some(rx)
+    }
+
+    fn remove(&self, key: &FlowKey) {
+        self.0.lock().unwrap().remove(key);
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+}
+
+pub(super) struct UdpProxy {
+    socket: Arc<UdpSocket>,
+    pi: ProxyInputs,
+    drain: Watch,
+    direction: Direction,
+    sessions: Sessions,
+}
+
+impl UdpProxy {
+    pub(super) async fn new(
+        mut pi: ProxyInputs,
+        drain: Watch,
+        direction: Direction,
+    ) -> Result<UdpProxy, Error> {
+        let addr = match direction {
+            Direction::Outbound => pi.cfg.outbound_addr,
+            Direction::InboundPassthrough => pi.cfg.inbound_plaintext_addr,
+        };
+        let socket = pi
+            .socket_factory
+            .udp_bind(addr)
+            .map_err(|e| Error::Bind(addr, e))?;
+        // Unlike TCP, we have no way to recover the original destination without TPROXY.
+        socket::set_transparent_udp(&socket)?;
+        // The listener is always transparent, so the original source can be used unless it was
+        // explicitly disabled.
+        pi.cfg.enable_original_source = Some(pi.cfg.enable_original_source != Some(false));
+
+        info!(
+            address=%socket.local_addr().expect("local_addr available"),
+            component=direction.component(),
+            "listener established",
+        );
+        Ok(UdpProxy {
+            socket: Arc::new(socket),
+            pi,
+            drain,
+            direction,
+            sessions: Sessions::default(),
+        })
+    }
+
+    pub(super) async fn run(self) {
+        let direction = self.direction;
+        let accept = async move {
+            let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+            loop {
+                let (n, src, dst) = match socket::recv_from_orig_dst(&self.socket, &mut buf).await
+                {
+                    Ok(res) => res,
+                    Err(e) => {
+                        if util::is_runtime_shutdown(&e) {
+                            return;
+                        }
+                        error!(component = direction.component(), "failed to receive: {e}");
+                        continue;
+                    }
+                };
+                let Some(dst) = dst else {
+                    debug!(%src, component=direction.component(), "dropping datagram without original destination");
+                    continue;
+                };
+                let key = (src, dst);
+                let Some(rx) = self
+                    .sessions
+                    .dispatch(key, Bytes::copy_from_slice(&buf[..n]))
+                else {
+                    continue;
+                };
+                let pi = self.pi.clone();
+                let sessions = self.sessions.clone();
+                tokio::spawn(
+                    async move {
+                        match direction {
+                            Direction::Outbound => Self::proxy_outbound(pi, src, dst, rx).await,
+                            Direction::InboundPassthrough => {
+                                Self::proxy_inbound_plaintext(pi, src, dst, rx).await
+                            }
+                        }
+                        sessions.remove(&key);
+                    }
+                    .in_current_span(),
+                );
+            }
+        }
+        .in_current_span();
+        // Stop accepting once we drain. Like TCP, existing flows are not waited on; they will expire
+        // once idle.
+        tokio::select! {
+            res = accept => { res }
+            _ = self.drain.signaled() => {
+                info!(component=direction.component(), "drained");
+            }
+        }
+    }
+
+    async fn proxy_outbound(
+        pi: ProxyInputs,
+        source_addr: SocketAddr,
+        dest_addr: SocketAddr,
+        rx: mpsc::Receiver<Bytes>,
+    ) {
+        let start = Instant::now();
+        let source_network_addr = NetworkAddress {
+            network: pi.cfg.network.clone(),
+            address: source_addr.ip(),
+        };
+        let Some(source_workload) = pi.state.fetch_workload(&source_network_addr).await else {
+            metrics::log_early_deny(
+                &pi.access_log,
+                source_addr,
+                dest_addr,
+                Reporter::source,
+                Error::UnknownSource(source_addr.ip()),
+            );
+            return;
+        };
+
+        let (upstream_addr, destination, destination_service) =
+            match select_upstream(&pi, &source_workload, dest_addr).await {
+                Ok(upstream) => upstream,
+                Err(e) => {
+                    metrics::log_early_deny(
+                        &pi.access_log,
+                        source_addr,
+                        dest_addr,
+                        Reporter::source,
+                        e,
+                    );
+                    return;
+                }
+            };
+
+        let connection_metrics = metrics::ConnectionOpen {
+            reporter: Reporter::source,
+            source: Some(source_workload),
+            derived_source: None,
+            destination,
+            destination_service,
+            connection_security_policy: metrics::SecurityPolicy::unknown,
+        };
+        let result_tracker = metrics::ConnectionResult::new(
+            source_addr,
+            upstream_addr,
+            None,
+            start,
+            &connection_metrics,
+            pi.metrics.clone(),
+            pi.access_log.clone(),
+        );
+        let res = Self::relay(
+            &pi,
+            source_addr,
+            dest_addr,
+            upstream_addr,
+            None,
+            rx,
+            Direction::Outbound,
+        )
+        .await;
+        result_tracker.record(res);
+    }
+
+    async fn proxy_inbound_plaintext(
+        pi: ProxyInputs,
+        source_addr: SocketAddr,
+        dest_addr: SocketAddr,
+        rx: mpsc::Receiver<Bytes>,
+    ) {
+        let start = Instant::now();
+        // Check if it is a recursive call when proxy mode is Node.
+        if pi.cfg.proxy_mode == ProxyMode::Shared && Some(dest_addr.ip()) == pi.cfg.local_ip {
+            metrics::log_early_deny(
+                &pi.access_log,
+                source_addr,
+                dest_addr,
+                Reporter::destination,
+                Error::SelfCall,
+            );
+            return;
+        }
+        let network_addr = NetworkAddress {
+            network: pi.cfg.network.clone(), // inbound request must be on our network
+            address: dest_addr.ip(),
+        };
+        let Some((upstream, upstream_service)) =
+            pi.state.fetch_workload_services(&network_addr).await
+        else {
+            metrics::log_early_deny(
+                &pi.access_log,
+                source_addr,
+                dest_addr,
+                Reporter::destination,
+                Error::UnknownDestination(dest_addr.ip()),
+            );
+            return;
+        };
+
+        let rbac_ctx = crate::state::ProxyRbacContext {
+            conn: rbac::Connection {
+                src_identity: None,
+                src: source_addr,
+                // Plaintext traffic cannot have crossed a network gateway, so it must be on our network.
+                dst_network: pi.cfg.network.clone(),
+                dst: dest_addr,
+            },
+            dest_workload_info: pi.proxy_workload_info.clone(),
+        };
+
+        let source_workload = {
+            let network_addr_srcip = NetworkAddress {
+                network: pi.cfg.network.clone(),
+                address: source_addr.ip(),
+            };
+            pi.state.fetch_workload(&network_addr_srcip).await
+        };
+        let derived_source = metrics::DerivedWorkload {
+            identity: rbac_ctx.conn.src_identity.clone(),
+            ..Default::default()
+        };
+        let ds = proxy::guess_inbound_service(&rbac_ctx.conn, upstream_service, &upstream);
+        let connection_metrics = metrics::ConnectionOpen {
+            reporter: Reporter::destination,
+            source: source_workload,
+            derived_source: Some(derived_source),
+            destination: Some(upstream),
+            connection_security_policy: metrics::SecurityPolicy::unknown,
+            destination_service: ds,
+        };
+        let mut result_tracker = metrics::ConnectionResult::new(
+            source_addr,
+            dest_addr,
+            None,
+            start,
+            &connection_metrics,
+            pi.metrics.clone(),
+            pi.access_log.clone(),
+        );
+
+        let connection_manager = pi.connection_manager.clone();
+        //register before assert_rbac to ensure the flow is tracked during it's entire valid span
+        connection_manager.register(&rbac_ctx);
+        let decision = pi.state.assert_rbac(&rbac_ctx).await;
+        result_tracker = result_tracker.with_rbac_decision(&decision);
+        if !decision.allowed {
+            connection_manager.release(&rbac_ctx);
+            result_tracker.record(Err(Error::AuthorizationPolicyRejection));
+            return;
+        }
+        let close = match connection_manager.track(&rbac_ctx) {
+            Some(c) => c,
+            None => {
+                // this seems unlikely but could occur if policy changes while track awaits lock
+                result_tracker.record(Err(Error::AuthorizationPolicyRejection));
+                return;
+            }
+        };
+
+        let orig_src = if pi.cfg.enable_original_source.unwrap_or_default() {
+            Some(source_addr.ip())
+        } else {
+            None
+        };
+        let send = Self::relay(
+            &pi,
+            source_addr,
+            dest_addr,
+            dest_addr,
+            orig_src,
+            rx,
+            Direction::InboundPassthrough,
+        );
+        let res = tokio::select! {
+            res = send => {
+                connection_manager.release(&rbac_ctx);
+                // Match the TCP passthrough ordering of (sent, received)
+                res.map(|(to_upstream, to_downstream)| (to_downstream, to_upstream))
+            }
+            decision = close.signaled() => {
+                result_tracker.set_late_rejection(decision);
+                Err(Error::AuthorizationPolicyLateRejection)
+            }
+        };
+        result_tracker.record(res);
+    }
+
+    /// Relays datagrams between the client and the upstream, until the flow has been idle for
+    /// `udp_idle_timeout`. If `orig_src` is set, datagrams are sent upstream from that address, as
+    /// with TCP. Returns the bytes sent (to the upstream, to the client).
+    async fn relay(
+        pi: &ProxyInputs,
+        source_addr: SocketAddr,
+        dest_addr: SocketAddr,
+        upstream_addr: SocketAddr,
+        orig_src: Option<IpAddr>,
+        mut rx: mpsc::Receiver<Bytes>,
+        direction: Direction,
+    ) -> Result<(u64, u64), Error> {
+        let upstream = Self::bind_upstream(pi, upstream_addr, orig_src)
+            .await
+            .map_err(Error::ConnectionFailed)?;
+        // Replies must appear to come from the address the client originally sent to, so we bind it,
+        // connected to the client. If an application in the same network namespace holds the port
+        // without SO_REUSEADDR, this fails and the flow is rejected, rather than interfering with it.
+        let downstream = pi
+            .socket_factory
+            .udp_bind_transparent(dest_addr, source_addr)
+            .map_err(Error::ConnectionFailed)?;
+        trace!(%source_addr, %dest_addr, %upstream_addr, component=direction.component(), "flow established");
+
+        let idle_timeout = pi.cfg.udp_idle_timeout;
+        let (mut to_upstream, mut to_downstream): (u64, u64) = (0, 0);
+        let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+        loop {
+            tokio::select! {
+                datagram = rx.recv() => {
+                    let Some(datagram) = datagram else { break };
+                    to_upstream += upstream.send(&datagram).await? as u64;
+                }
+                res = upstream.recv(&mut buf) => {
+                    let n = res?;
+                    to_downstream += downstream.send(&buf[..n]).await? as u64;
+                }
+                _ = tokio::time::sleep(idle_timeout) => {
+                    trace!(%source_addr, %dest_addr, component=direction.component(), "flow idle");
+                    break;
+                }
+            }
+        }
+        Ok((to_upstream, to_downstream))
+    }
+
+    /// Binds a socket connected to `upstream_addr`. Like freebind_connect, this uses `orig_src` as
+    /// the source address if set, unless it is the upstream itself.
+    async fn bind_upstream(
+        pi: &ProxyInputs,
+        upstream_addr: SocketAddr,
+        orig_src: Option<IpAddr>,
+    ) -> std::io::Result<UdpSocket> {
+        if let Some(src) = orig_src.filter(|src| *src != upstream_addr.ip()) {
+            match pi
+                .socket_factory
+                .udp_bind_transparent(SocketAddr::new(src, 0), upstream_addr)
+            {
+                Ok(socket) => return Ok(socket),
+                Err(err) => warn!("failed to bind original source {src}: {err}"),
+            }
+        }
+        let bind_addr = if upstream_addr.is_ipv4() {
+            SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))
+        } else {
+            SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))
+        };
+        let upstream = pi.socket_factory.udp_bind(bind_addr)?;
+        upstream.connect(upstream_addr).await?;
+        Ok(upstream)
+    }
+}
+
+/// Picks the upstream for a datagram from `source` to `dest_addr`. UDP is sent as plaintext, so
+/// destinations which expect HBONE, or have a waypoint, are refused rather than bypassing mTLS and
+/// the waypoint's policy. Otherwise service VIPs are translated to a selected endpoint, and other
+/// destinations are passed through. Returns the upstream address, and the destination workload and
+/// service if known.
+pub(super) async fn select_upstream(
+    pi: &ProxyInputs,
+    source: &Workload,
+    dest_addr: SocketAddr,
+) -> Result<(SocketAddr, Option<Workload>, Option<ServiceDescription>), Error> {
+    let refuse = |reason: &str| {
+        pi.metrics.udp_flows_refused.inc();
+        Err(Error::UnsupportedFeature(format!(
+            "UDP to {dest_addr}, which {reason}"
+        )))
+    };
+    if let Some(Address::Service(svc)) = pi
+        .state
+        .fetch_destination(&Destination::Address(NetworkAddress {
+            network: source.network.clone(),
+            address: dest_addr.ip(),
+        }))
+        .await
+    {
+        if svc.waypoint.is_some() {
+            return refuse("has a waypoint");
+        }
+    }
+    let Some(us) = pi
+        .state
+        .fetch_upstream(&source.network, source, dest_addr)
+        .await
+    else {
+        return Ok((dest_addr, None, None));
+    };
+    if us.workload.waypoint.is_some() {
+        return refuse("has a waypoint");
+    }
+    if us.workload.protocol == Protocol::HBONE {
+        return refuse("requires HBONE");
+    }
+    let workload_ip = pi
+        .state
+        .pick_workload_destination(&us.workload, source, pi.metrics.clone())
+        .await?;
+    Ok((
+        SocketAddr::new(workload_ip, us.port),
+        Some(us.workload),
+        us.destination_service,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::net::TcpListener;
+    use tokio::net::TcpSocket;
+
+    use super::*;
+    use crate::identity;
+    use crate::proxy::bandwidth::BandwidthLimiter;
+    use crate::proxy::connection_manager::ConnectionManager;
+    use crate::proxy::{pool, DefaultSocketFactory, SocketFactory};
+    use crate::test_helpers;
+    use crate::test_helpers::helpers::test_proxy_metrics;
+    use crate::test_helpers::new_proxy_state;
+    use crate::xds::istio::workload::TunnelProtocol as XdsProtocol;
+    use crate::xds::istio::workload::Workload as XdsWorkload;
+
+    // Binds an ephemeral port in place of the original destination, as tests cannot bind
+    // transparently.
+    struct TestSocketFactory;
+
+    impl SocketFactory for TestSocketFactory {
+        fn new_tcp_v4(&self) -> std::io::Result<TcpSocket> {
+            DefaultSocketFactory.new_tcp_v4()
+        }
+
+        fn new_tcp_v6(&self) -> std::io::Result<TcpSocket> {
+            DefaultSocketFactory.new_tcp_v6()
+        }
+
+        fn tcp_bind(&self, addr: SocketAddr) -> std::io::Result<TcpListener> {
+            DefaultSocketFactory.tcp_bind(addr)
+        }
+
+        fn udp_bind(&self, addr: SocketAddr) -> std::io::Result<UdpSocket> {
+            DefaultSocketFactory.udp_bind(addr)
+        }
+
+        fn udp_bind_transparent(
+            &self,
+            _addr: SocketAddr,
+            peer: SocketAddr,
+        ) -> std::io::Result<UdpSocket> {
+            let socket = std::net::UdpSocket::bind("127.0.0.1:0")?;
+            socket.connect(peer)?;
+            socket.set_nonblocking(true)?;
+            UdpSocket::from_std(socket)
+        }
+    }
+
+    fn test_inputs() -> ProxyInputs {
+        let cfg = crate::config::Config {
+            udp_idle_timeout: Duration::from_millis(100),
+            ..test_helpers::test_config()
+        };
+        let metrics = test_proxy_metrics();
+        // Both the client and the server are on localhost, so a single workload covers both.
+        let state = new_proxy_state(
+            &[
+                XdsWorkload {
+                    uid: "cluster1//v1/Pod/default/local".to_string(),
+                    name: "local".to_string(),
+                    namespace: "default".to_string(),
+                    addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 1])],
+                    ..Default::default()
+                },
+                XdsWorkload {
+                    uid: "cluster1//v1/Pod/default/mesh".to_string(),
+                    name: "mesh".to_string(),
+                    namespace: "default".to_string(),
+                    addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 2])],
+                    tunnel_protocol: XdsProtocol::Hbone as i32,
+                    ..Default::default()
+                },
+            ],
+            &[],
+            &[],
+        );
+        ProxyInputs {
+            cert_manager: identity::mock::new_secret_manager(Duration::from_secs(10)),
+            state,
+            hbone_port: 15008,
+            bandwidth: BandwidthLimiter::new(&cfg, metrics.clone()),
+            pool: pool::Pool::new(&cfg),
+            cfg,
+            metrics,
+            socket_factory: Arc::new(TestSocketFactory),
+            proxy_workload_info: None,
+            connection_manager: ConnectionManager::default(),
+            access_log: Default::default(),
+            trace_exporter: Default::default(),
+        }
+    }
+
+    // Starts a server echoing back every datagram, returning its address.
+    async fn echo_server() -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+            while let Ok((n, src)) = socket.recv_from(&mut buf).await {
+                let _ = socket.send_to(&buf[..n], src).await;
+            }
+        });
+        addr
+    }
+
+    // Runs a flow from a client to an echo server with `proxy`, checking datagrams are relayed both
+    // ways and the flow expires once idle.
+    async fn run_flow<F>(
+        proxy: impl FnOnce(ProxyInputs, SocketAddr, SocketAddr, mpsc::Receiver<Bytes>) -> F,
+    ) where
+        F: std::future::Future<Output = ()> + Send + 'static,
+    {
+        let server = echo_server().await;
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let (tx, rx) = mpsc::channel(FLOW_QUEUE_SIZE);
+        let flow = tokio::spawn(proxy(
+            test_inputs(),
+            client.local_addr().unwrap(),
+            server,
+            rx,
+        ));
+
+        let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+        for msg in [&b"hello"[..], &b"world"[..]] {
+            tx.send(Bytes::copy_from_slice(msg)).await.unwrap();
+            let (n, _) = tokio::time::timeout(Duration::from_secs(5), client.recv_from(&mut buf))
+                .await
+                .expect("reply")
+                .unwrap();
+            assert_eq!(&buf[..n], msg);
+        }
+
+        tokio::time::timeout(Duration::from_secs(5), flow)
+            .await
+            .expect("flow should expire once idle")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn relay_outbound() {
+        run_flow(UdpProxy::proxy_outbound).await;
+    }
+
+    #[tokio::test]
+    async fn relay_inbound_plaintext() {
+        run_flow(UdpProxy::proxy_inbound_plaintext).await;
+    }
+
+    #[tokio::test]
+    async fn refuse_hbone_destination() {
+        let pi = test_inputs();
+        let source = pi
+            .state
+            .fetch_workload(&NetworkAddress {
+                network: pi.cfg.network.clone(),
+                address: "127.0.0.1".parse().unwrap(),
+            })
+            .await
+            .unwrap();
+
+        // Sending plaintext would bypass mTLS, so the flow is refused
+        let res = select_upstream(&pi, &source, "127.0.0.2:53".parse().unwrap()).await;
+        assert!(matches!(res, Err(Error::UnsupportedFeature(_))));
+        assert_eq!(pi.metrics.udp_flows_refused.get(), 1);
+
+        // Workloads without HBONE are still reached directly
+        let (upstream, destination, _) =
+            select_upstream(&pi, &source, "127.0.0.1:53".parse().unwrap())
+                .await
+                .unwrap();
+        assert_eq!(upstream, "127.0.0.1:53".parse::<SocketAddr>().unwrap());
+        assert_eq!(destination.unwrap().name, "local");
+    }
+
+    #[tokio::test]
+    async fn refuse_hbone_destination_for_socks5_client() {
+        let pi = test_inputs();
+        let identity = identity::Identity::Spiffe {
+            trust_domain: "cluster.local".to_string(),
+            namespace: "default".to_string(),
+            service_account: "client".to_string(),
+        };
+        let source = proxy::socks5::authenticated_workload(&pi.cfg.network, &identity);
+
+        // The identity cannot be presented over plaintext UDP, so mesh destinations are refused
+        let res = select_upstream(&pi, &source, "127.0.0.2:53".parse().unwrap()).await;
+        assert!(matches!(res, Err(Error::UnsupportedFeature(_))));
+
+        // Other destinations are sent plaintext datagrams, without the identity
+        let (upstream, _, _) = select_upstream(&pi, &source, "127.0.0.1:53".parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(upstream, "127.0.0.1:53".parse::<SocketAddr>().unwrap());
+    }
+
+    #[tokio::test]
+    async fn sessions_dispatch() {
+        let sessions = Sessions::default();
+        let a = (
+            "127.0.0.1:1000".parse().unwrap(),
+            "10.0.0.1:53".parse().unwrap(),
+        );
+        let b = (
+            "127.0.0.1:1001".parse().unwrap(),
+            "10.0.0.1:53".parse().unwrap(),
+        );
+
+        // The first datagram creates the flow, and is queued for it.
+        let mut rx = sessions
+            .dispatch(a, Bytes::from_static(b"one"))
+            .expect("new flow");
+        // Later datagrams are sent to the existing flow.
+        assert!(sessions.dispatch(a, Bytes::from_static(b"two")).is_none());
+        assert_eq!(rx.recv().await.unwrap(), Bytes::from_static(b"one"));
+        assert_eq!(rx.recv().await.unwrap(), Bytes::from_static(b"two"));
+
+        // A different client is a different flow.
+        let _rx_b = sessions
+            .dispatch(b, Bytes::from_static(b"three"))
+            .expect("new flow");
+        assert_eq!(sessions.len(), 2);
+
+        // Once removed, the next datagram starts a new flow.
+        sessions.remove(&a);
+        assert_eq!(sessions.len(), 1);
+        assert!(sessions.dispatch(a, Bytes::from_static(b"four")).is_some());
+    }
+
+    #[tokio::test]
+    async fn sessions_drop_when_full() {
+        let sessions = Sessions::default();
+        let key = (
+            "127.0.0.1:1000".parse().unwrap(),
+            "10.0.0.1:53".parse().unwrap(),
+        );
+        let mut rx = sessions.dispatch(key, Bytes::new()).expect("new flow");
+        for _ in 0..FLOW_QUEUE_SIZE * 2 {
+            assert!(sessions.dispatch(key, Bytes::new()).is_none());
+        }
+        let mut received = 0;
+        while rx.try_recv().is_ok() {
+            received += 1;
+        }
+        assert_eq!(received, FLOW_QUEUE_SIZE);
+    }
+}
@@ -13,6 +13,12 @@
 // limitations under the License.
 
 use std::io::{Error, ErrorKind};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use drain::Watch;
+use tokio::sync::Notify;
 
 pub fn is_runtime_shutdown(e: &Error) -> bool {
     if e.kind() == ErrorKind::Other
@@ -22,3 +28,110 @@ pub fn is_runtime_shutdown(e: &Error) -> bool {
     }
     false
 }
+
+/// ConnectionDrain tracks the connections a listener is serving, so that on shutdown they can be
+/// given time to complete before being forcibly closed.
+pub struct ConnectionDrain {
+    tracker: Arc<Tracker>,
+    force_signal: drain::Signal,
+    force: Watch,
+}
+
+#[derive(Default)]
+struct Tracker {
+    active: AtomicUsize,
+    idle: Notify,
+}
+
+/// InFlight marks a connection as in progress until it is dropped.
+pub struct InFlight(Arc<Tracker>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if self.0.active.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+impl Default for ConnectionDrain {
+    fn default() -> Self {
+        let (force_signal, force) = drain::channel();
+        ConnectionDrain {
+            tracker: Default::default(),
+            force_signal,
+            force,
+        }
+    }
+}
+
+impl ConnectionDrain {
+    /// Registers a new connection. The returned watch is signaled if the connection has to be
+    /// closed because the drain deadline passed.
+    pub fn track(&self) -> (InFlight, Watch) {
+        self.tracker.active.fetch_add(1, Ordering::AcqRel);
+        (InFlight(self.tracker.clone()), self.force.clone())
+    }
+
+    /// Waits up to `deadline` for all tracked connections to complete, then force closes any that
+    /// remain. Returns the number of connections that were cut off.
+    pub async fn drain(self, deadline: Duration) -> usize {
+        let ConnectionDrain {
+            tracker,
+            force_signal,
+            force,
+        } = self;
+        drop(force);
+        let completed = async {
+            loop {
+                let idle = tracker.idle.notified();
+                if tracker.active.load(Ordering::Acquire) == 0 {
+                    return;
+                }
+                idle.await;
+            }
+        };
+        let remaining = match tokio::time::timeout(deadline, completed).await {
+            Ok(()) => 0,
+            Err(_) => tracker.active.load(Ordering::Acquire),
+        };
+        force_signal.drain().await;
+        remaining
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn connection_drain() {
+        let drain = ConnectionDrain::default();
+        let (short, _) = drain.track();
+        let (long, force) = drain.track();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            drop(short);
+        });
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = force.signaled() => {}
+                _ = tokio::time::sleep(Duration::from_secs(60)) => {}
+            }
+            drop(long);
+        });
+        let start = tokio::time::Instant::now();
+        assert_eq!(drain.drain(Duration::from_secs(5)).await, 1);
+        assert_eq!(start.elapsed(), Duration::from_secs(5));
+
+        let drain = ConnectionDrain::default();
+        let (conn, _) = drain.track();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            drop(conn);
+        });
+        let start = tokio::time::Instant::now();
+        assert_eq!(drain.drain(Duration::from_secs(5)).await, 0);
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+    }
+}
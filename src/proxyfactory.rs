@@ -21,7 +21,10 @@ use tracing::error;
 
 use crate::dns;
 
+use crate::proxy::access_log::AccessLog;
 use crate::proxy::connection_manager::ConnectionManager;
+use crate::proxy::otel::Exporter;
+use crate::proxy::pool::PoolRegistry;
 use crate::proxy::{Error, Metrics};
 
 use crate::proxy::Proxy;
@@ -35,9 +38,13 @@ pub struct ProxyFactory {
     proxy_metrics: Option<Arc<Metrics>>,
     dns_metrics: Option<Arc<dns::Metrics>>,
     drain: Watch,
+    pools: Arc<PoolRegistry>,
+    access_log: AccessLog,
+    trace_exporter: Exporter,
 }
 
 impl ProxyFactory {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         config: config::Config,
         state: DemandProxyState,
@@ -45,6 +52,8 @@ impl ProxyFactory {
         proxy_metrics: Option<Metrics>,
         dns_metrics: Option<dns::Metrics>,
         drain: Watch,
+        access_log: AccessLog,
+        trace_exporter: Exporter,
     ) -> std::io::Result<Self> {
         let proxy_metrics = match proxy_metrics {
             Some(metrics) => Some(Arc::new(metrics)),
@@ -72,9 +81,17 @@ impl ProxyFactory {
             proxy_metrics,
             dns_metrics,
             drain,
+            pools: Default::default(),
+            access_log,
+            trace_exporter,
         })
     }
 
+    /// The connection pools of all proxies created by this factory, for the admin server.
+    pub fn pools(&self) -> Arc<PoolRegistry> {
+        self.pools.clone()
+    }
+
     pub async fn new_proxies(&self) -> Result<ProxyResult, Error> {
         self.new_proxies_from_factory(None, None, Arc::new(crate::proxy::DefaultSocketFactory))
             .await
@@ -100,6 +117,9 @@ impl ProxyFactory {
                 self.proxy_metrics.clone().unwrap(),
                 socket_factory.clone(),
                 proxy_workload_info,
+                &self.pools,
+                self.access_log.clone(),
+                self.trace_exporter.clone(),
             );
             result.connection_manager = Some(cm);
             result.proxy = Some(Proxy::from_inputs(pi, drain.clone()).await?);
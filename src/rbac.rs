@@ -14,6 +14,7 @@
 
 use ipnet::IpNet;
 
+use std::collections::BTreeMap;
 use std::fmt;
 use std::fmt::{Display, Formatter};
 use std::net::SocketAddr;
@@ -29,6 +30,8 @@ use crate::identity::Identity;
 use crate::state::workload::{byte_to_ip, WorkloadError};
 use crate::xds;
 
+pub mod ext_authz;
+
 #[derive(Debug, Hash, Eq, PartialEq, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct Authorization {
@@ -37,6 +40,9 @@ pub struct Authorization {
     pub scope: RbacScope,
     pub action: RbacAction,
     pub rules: Vec<Vec<Vec<RbacMatch>>>,
+    /// If set, the policy is evaluated but not enforced.
+    #[serde(default)]
+    pub dry_run: bool,
 }
 
 #[derive(Debug, Clone, Eq, Hash, Ord, PartialEq, PartialOrd, serde::Serialize)]
@@ -75,27 +81,21 @@ impl Authorization {
         format!("{}/{}", self.namespace, self.name)
     }
 
-    #[instrument(level = "trace", skip_all, fields(policy=self.to_key()))]
     pub fn matches(&self, conn: &Connection) -> bool {
-        let id = conn
-            .src_identity
-            .as_ref()
-            .map(|i| i.to_string())
-            .unwrap_or_default();
-        let ns = conn
-            .src_identity
-            .as_ref()
-            .map(|i| match i {
-                Identity::Spiffe { namespace, .. } => namespace.to_owned(), // may be more clear if we use to_owned() to denote change from borrowed to owned
-            })
-            .unwrap_or_default();
+        self.matched_rule(conn).is_some()
+    }
+
+    /// matched_rule returns the index of the first rule that matches the connection, if any.
+    #[instrument(level = "trace", skip_all, fields(policy=self.to_key()))]
+    pub fn matched_rule(&self, conn: &Connection) -> Option<usize> {
+        let (id, ns) = Self::source(conn);
         if self.rules.is_empty() {
             trace!(matches = false, "empty rules");
-            return false;
+            return None;
         }
         // An Authorization Policy can have multiple rules
         // If ANY rule matches it's a match...
-        for rule in self.rules.iter() {
+        for (index, rule) in self.rules.iter().enumerate() {
             // Rule typically has 1-3 clauses (from,to,when)
             // If ALL clauses match, it is a match...
             let mut rule_match = true;
@@ -109,37 +109,9 @@ impl Authorization {
                         continue;
                     }
                     // We need ALL of these to match. Within each type, ANY must match
-                    let mut m = true;
-                    m &= Self::matches_internal(
-                        "destination_ip",
-                        &mg.destination_ips,
-                        &mg.not_destination_ips,
-                        |i| i.contains(&conn.dst.ip()),
-                    );
-                    m &= Self::matches_internal(
-                        "source_ips",
-                        &mg.source_ips,
-                        &mg.not_source_ips,
-                        |i| i.contains(&conn.src.ip()),
-                    );
-                    m &= Self::matches_internal(
-                        "destination_ports",
-                        &mg.destination_ports,
-                        &mg.not_destination_ports,
-                        |p| *p == conn.dst.port(),
-                    );
-                    m &= Self::matches_internal(
-                        "principals",
-                        &mg.principals,
-                        &mg.not_principals,
-                        |p| p.matches_principal(&id),
-                    );
-                    m &= Self::matches_internal(
-                        "namespaces",
-                        &mg.namespaces,
-                        &mg.not_namespaces,
-                        |p| p.matches(&ns),
-                    );
+                    let m = Self::match_fields(mg, conn, &id, &ns)
+                        .iter()
+                        .all(|(_, m)| m.unwrap_or(true));
 
                     if m {
                         clause_match = true;
@@ -161,10 +133,120 @@ impl Authorization {
             }
             trace!(matches = rule_match, "rule");
             if rule_match {
-                return true;
+                return Some(index);
             }
         }
-        false
+        None
+    }
+
+    /// explain evaluates every rule of the policy against the connection, reporting whether each
+    /// rule, clause and match fired. Unlike matched_rule, nothing is short circuited.
+    pub fn explain(&self, conn: &Connection) -> PolicyExplanation {
+        let (id, ns) = Self::source(conn);
+        let rules: Vec<RuleExplanation> = self
+            .rules
+            .iter()
+            .map(|rule| {
+                let clauses: Vec<ClauseExplanation> = rule
+                    .iter()
+                    .map(|clause| {
+                        let matches: Vec<MatchExplanation> = clause
+                            .iter()
+                            .map(|mg| {
+                                if mg.is_empty() {
+                                    return MatchExplanation::default();
+                                }
+                                let fields = Self::match_fields(mg, conn, &id, &ns);
+                                MatchExplanation {
+                                    matched: fields.iter().all(|(_, m)| m.unwrap_or(true)),
+                                    fields: fields
+                                        .into_iter()
+                                        .filter_map(|(desc, m)| m.map(|m| (desc, m)))
+                                        .collect(),
+                                }
+                            })
+                            .collect();
+                        ClauseExplanation {
+                            matched: clause.is_empty() || matches.iter().any(|m| m.matched),
+                            matches,
+                        }
+                    })
+                    .collect();
+                RuleExplanation {
+                    matched: clauses.iter().all(|c| c.matched),
+                    clauses,
+                }
+            })
+            .collect();
+        PolicyExplanation {
+            policy: self.to_key(),
+            action: self.action,
+            dry_run: self.dry_run,
+            matched_rule: rules.iter().position(|r| r.matched),
+            rules,
+        }
+    }
+
+    // source returns the identity and namespace of the source of the connection, as matched by
+    // principals and namespaces.
+    fn source(conn: &Connection) -> (String, String) {
+        let id = conn
+            .src_identity
+            .as_ref()
+            .map(|i| i.to_string())
+            .unwrap_or_default();
+        let ns = conn
+            .src_identity
+            .as_ref()
+            .map(|i| match i {
+                Identity::Spiffe { namespace, .. } => namespace.to_owned(), // may be more clear if we use to_owned() to denote change from borrowed to owned
+            })
+            .unwrap_or_default();
+        (id, ns)
+    }
+
+    // match_fields evaluates each type of match in `mg` against the connection. Types that `mg` does
+    // not declare are None.
+    fn match_fields(
+        mg: &RbacMatch,
+        conn: &Connection,
+        id: &str,
+        ns: &str,
+    ) -> [(&'static str, Option<bool>); 5] {
+        [
+            Self::match_field(
+                "destination_ips",
+                &mg.destination_ips,
+                &mg.not_destination_ips,
+                |i| i.contains(&conn.dst.ip()),
+            ),
+            Self::match_field("source_ips", &mg.source_ips, &mg.not_source_ips, |i| {
+                i.contains(&conn.src.ip())
+            }),
+            Self::match_field(
+                "destination_ports",
+                &mg.destination_ports,
+                &mg.not_destination_ports,
+                |p| *p == conn.dst.port(),
+            ),
+            Self::match_field("principals", &mg.principals, &mg.not_principals, |p| {
+                p.matches_principal(id)
+            }),
+            Self::match_field("namespaces", &mg.namespaces, &mg.not_namespaces, |p| {
+                p.matches(ns)
+            }),
+        ]
+    }
+
+    fn match_field<T: fmt::Debug>(
+        desc: &'static str,
+        positive: &Vec<T>,
+        negative: &Vec<T>,
+        predicate: impl FnMut(&T) -> bool,
+    ) -> (&'static str, Option<bool>) {
+        let matches = Self::matches_internal(desc, positive, negative, predicate);
+        let declared = !positive.is_empty() || !negative.is_empty();
+        (desc, declared.then_some(matches))
     }
 
     #[instrument(name= "match", level = "trace", skip_all, fields(%desc))]
@@ -194,6 +276,42 @@ impl Authorization {
     }
 }
 
+/// PolicyExplanation describes how a policy applies to a connection, for debugging.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PolicyExplanation {
+    pub policy: String,
+    pub action: RbacAction,
+    pub dry_run: bool,
+    /// The index of the first rule that matched, which is the one that decides the policy.
+    pub matched_rule: Option<usize>,
+    pub rules: Vec<RuleExplanation>,
+}
+
+/// RuleExplanation describes a rule of a policy. It matches if all of its clauses do.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct RuleExplanation {
+    pub matched: bool,
+    pub clauses: Vec<ClauseExplanation>,
+}
+
+/// ClauseExplanation describes a clause of a rule. It matches if any of its matches do, or if it is
+/// empty.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct ClauseExplanation {
+    pub matched: bool,
+    pub matches: Vec<MatchExplanation>,
+}
+
+/// MatchExplanation describes a match of a clause. It matches if all of the declared fields do; an
+/// empty match never matches.
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct MatchExplanation {
+    pub matched: bool,
+    /// Whether each declared field matched, such as `source_ips`.
+    pub fields: BTreeMap<&'static str, bool>,
+}
+
 #[derive(Debug, Hash, Eq, PartialEq, Clone, Default, serde::Serialize, serde::Deserialize)]
 pub struct RbacMatch {
     #[serde(skip_serializing_if = "Vec::is_empty", default)]
@@ -283,6 +401,8 @@ impl From<xds::istio::security::Scope> for RbacScope {
 pub enum RbacAction {
     Allow,
     Deny,
+    Audit,
+    Custom,
 }
 
 impl From<xds::istio::security::Action> for RbacAction {
@@ -290,6 +410,70 @@ impl From<xds::istio::security::Action> for RbacAction {
         match value {
             xds::istio::security::Action::Allow => RbacAction::Allow,
             xds::istio::security::Action::Deny => RbacAction::Deny,
+            xds::istio::security::Action::Audit => RbacAction::Audit,
+            xds::istio::security::Action::Custom => RbacAction::Custom,
+        }
+    }
+}
+
+/// RbacDecision is the outcome of evaluating the authorization policies for a connection.
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RbacDecision {
+    pub allowed: bool,
+    /// The action of the policy that decided the outcome. Unset if no policy decided it, such as when
+    /// no ALLOW policies apply to the destination.
+    pub action: Option<RbacAction>,
+    /// The key of the policy that decided the outcome.
+    pub policy: Option<String>,
+    /// The index of the rule in `policy` that matched the connection.
+    pub rule: Option<usize>,
+    /// Whether the connection was denied because ALLOW policies apply, but none of them matched.
+    pub default_deny: bool,
+    /// The keys of the AUDIT policies that matched the connection. These do not affect whether it is
+    /// allowed, but the connection is flagged in access logs and metrics.
+    pub audit_policies: Vec<String>,
+    /// The decision that would have been made if the dry-run policies were enforced. Only set if
+    /// any dry-run ALLOW or DENY policies apply to the destination.
+    pub shadow: Option<ShadowDecision>,
+}
+
+/// ShadowDecision is the outcome of evaluating the authorization policies with dry-run policies
+/// enforced.
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShadowDecision {
+    pub allowed: bool,
+    /// The key of the policy that decided the outcome, if it was not the default.
+    pub policy: Option<String>,
+}
+
+impl RbacDecision {
+    pub fn deny() -> Self {
+        RbacDecision::default()
+    }
+
+    /// matched returns the decision made by rule `rule` of `policy` matching the connection.
+    pub fn matched(allowed: bool, policy: &Authorization, rule: usize) -> Self {
+        RbacDecision {
+            allowed,
+            action: Some(policy.action),
+            policy: Some(policy.to_key()),
+            rule: Some(rule),
+            ..Default::default()
+        }
+    }
+}
+
+impl Display for RbacDecision {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", if self.allowed { "allow" } else { "deny" })?;
+        match (&self.action, &self.policy, &self.rule) {
+            (Some(action), Some(policy), Some(rule)) => {
+                write!(f, " by {action:?} policy {policy} rule {rule}")
+            }
+            _ if self.default_deny => write!(f, " by default, as no ALLOW policy matched"),
+            _ => Ok(()),
         }
     }
 }
@@ -320,6 +504,7 @@ impl TryFrom<&XdsRbac> for Authorization {
             scope: RbacScope::from(xds::istio::security::Scope::try_from(resource.scope)?),
             action: RbacAction::from(xds::istio::security::Action::try_from(resource.action)?),
             rules,
+            dry_run: resource.dry_run,
         })
     }
 }
@@ -428,6 +613,7 @@ mod tests {
             scope: RbacScope::Global,
             action: RbacAction::Allow,
             rules,
+            dry_run: false,
         }
     }
 
@@ -480,6 +666,76 @@ mod tests {
         assert!(!allow_policy("empty".to_string(), vec![]).matches(&plaintext_conn()));
     }
 
+    #[test]
+    fn rbac_matched_rule() {
+        let rule = |port| {
+            vec![vec![RbacMatch {
+                destination_ports: vec![port],
+                ..Default::default()
+            }]]
+        };
+        let pol = allow_policy("ports".to_string(), vec![rule(9090), rule(8080)]);
+        assert_eq!(pol.matched_rule(&plaintext_conn()), Some(1));
+        assert_eq!(pol.matched_rule(&tls_conn_alt()), Some(0));
+
+        let decision = RbacDecision::matched(true, &pol, 1);
+        assert_eq!(
+            decision.to_string(),
+            "allow by Allow policy namespace/ports rule 1"
+        );
+        let decision = RbacDecision {
+            default_deny: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            decision.to_string(),
+            "deny by default, as no ALLOW policy matched"
+        );
+    }
+
+    #[test]
+    fn rbac_explain() {
+        let pol = allow_policy(
+            "explain".to_string(),
+            vec![
+                vec![vec![RbacMatch {
+                    destination_ports: vec![9090],
+                    ..Default::default()
+                }]],
+                vec![
+                    vec![
+                        RbacMatch::default(),
+                        RbacMatch {
+                            namespaces: vec![StringMatch::Exact("namespace".to_string())],
+                            not_source_ips: vec!["127.0.0.1/32".parse().unwrap()],
+                            ..Default::default()
+                        },
+                    ],
+                    vec![],
+                ],
+            ],
+        );
+        let explanation = pol.explain(&tls_conn());
+        assert_eq!(explanation.matched_rule, None);
+        assert_eq!(explanation.matched_rule, pol.matched_rule(&tls_conn()));
+        assert!(!explanation.rules[0].matched);
+        let clause = &explanation.rules[1].clauses[0];
+        assert!(!clause.matched);
+        assert_eq!(clause.matches[0], MatchExplanation::default());
+        assert_eq!(
+            clause.matches[1].fields,
+            BTreeMap::from([("namespaces", true), ("source_ips", false)])
+        );
+        // Empty clauses match
+        assert!(explanation.rules[1].clauses[1].matched);
+
+        let explanation = pol.explain(&tls_conn_alt());
+        assert_eq!(explanation.matched_rule, Some(0));
+        assert_eq!(explanation.matched_rule, pol.matched_rule(&tls_conn_alt()));
+        // Rules after the first match are still explained
+        assert!(!explanation.rules[1].matched);
+    }
+
     #[test]
     fn rbac_nesting() {
         let pol = allow_policy(
@@ -0,0 +1,270 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tracing::{debug, warn};
+
+use crate::config::{Config, RootCert};
+use crate::identity::Identity;
+use crate::rbac::Connection;
+use crate::tls::{self, TlsGrpcChannel};
+
+use proto::authorization_client::AuthorizationClient;
+use proto::{attribute_context, Address, AttributeContext, CheckRequest, SocketAddress};
+
+// We don't control the codegen, so disable any code warnings in the
+// proto modules.
+#[allow(warnings)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+pub(crate) mod proto {
+    tonic::include_proto!("envoy.service.auth.v3");
+}
+
+// The most decisions kept in the cache.
+const CACHE_CAPACITY: usize = 1024;
+
+/// ExtAuthz checks connections matching CUSTOM authorization policies with an external
+/// authorization service, speaking the Envoy ext_authz gRPC protocol.
+#[derive(Clone, Debug)]
+pub struct ExtAuthz {
+    client: AuthorizationClient<TlsGrpcChannel>,
+    timeout: Duration,
+    fail_open: bool,
+    cache: Option<Arc<Mutex<DecisionCache>>>,
+}
+
+impl ExtAuthz {
+    /// new returns a client for the configured external authorization service, if any. https://
+    /// services are verified against the system root certificates.
+    pub async fn new(cfg: &Config) -> Result<Option<ExtAuthz>, tls::Error> {
+        let Some(address) = cfg.ext_authz_address.clone() else {
+            return Ok(None);
+        };
+        let channel = if address.starts_with("https://") {
+            tls::grpc_tls_connector(address, RootCert::Default).await?
+        } else {
+            tls::grpc_plaintext_connector(address)?
+        };
+        Ok(Some(ExtAuthz {
+            client: AuthorizationClient::new(channel),
+            timeout: cfg.ext_authz_timeout,
+            fail_open: cfg.ext_authz_fail_open,
+            cache: (!cfg.ext_authz_cache_ttl.is_zero())
+                .then(|| Arc::new(Mutex::new(DecisionCache::new(cfg.ext_authz_cache_ttl)))),
+        }))
+    }
+
+    /// check returns whether the external authorization service allows the connection. If the
+    /// service fails or does not decide in time, the configured fail-open setting decides instead.
+    pub async fn check(&self, conn: &Connection) -> bool {
+        if let Some(allowed) = self
+            .cache
+            .as_ref()
+            .and_then(|c| c.lock().unwrap().get(conn))
+        {
+            debug!(%conn, allowed, "external authorization cache hit");
+            return allowed;
+        }
+        let req = CheckRequest {
+            attributes: Some(AttributeContext {
+                source: Some(peer(
+                    conn.src,
+                    conn.src_identity.as_ref().map(|i| i.to_string()),
+                )),
+                destination: Some(peer(conn.dst, None)),
+            }),
+        };
+        let res = tokio::time::timeout(self.timeout, self.client.clone().check(req)).await;
+        let status = match res {
+            Ok(Ok(resp)) => resp.into_inner().status.unwrap_or_default(),
+            Ok(Err(err)) => {
+                warn!(%conn, fail_open=self.fail_open, "external authorization failed: {err}");
+                return self.fail_open;
+            }
+            Err(_) => {
+                warn!(%conn, fail_open=self.fail_open, "external authorization timed out");
+                return self.fail_open;
+            }
+        };
+        // Any status other than OK denies the connection
+        let allowed = status.code == tonic::Code::Ok as i32;
+        debug!(%conn, allowed, message=status.message, "external authorization decision");
+        if let Some(cache) = &self.cache {
+            cache.lock().unwrap().insert(conn, allowed);
+        }
+        allowed
+    }
+}
+
+fn peer(addr: SocketAddr, principal: Option<String>) -> attribute_context::Peer {
+    attribute_context::Peer {
+        address: Some(Address {
+            socket_address: Some(SocketAddress {
+                address: addr.ip().to_string(),
+                port_value: addr.port() as u32,
+            }),
+        }),
+        service: String::new(),
+        principal: principal.unwrap_or_default(),
+    }
+}
+
+/// DecisionCache keeps recent decisions of the external authorization service, so repeated
+/// connections do not each wait for it.
+#[derive(Debug)]
+struct DecisionCache {
+    ttl: Duration,
+    decisions: HashMap<DecisionKey, (bool, Instant)>,
+}
+
+/// DecisionKey identifies connections that share a cached decision. The source port is left out,
+/// as clients pick a new one for every connection, which would make every lookup a miss.
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+struct DecisionKey {
+    src_ip: IpAddr,
+    src_identity: Option<Identity>,
+    dst_network: String,
+    dst: SocketAddr,
+}
+
+impl From<&Connection> for DecisionKey {
+    fn from(conn: &Connection) -> Self {
+        DecisionKey {
+            src_ip: conn.src.ip(),
+            src_identity: conn.src_identity.clone(),
+            dst_network: conn.dst_network.clone(),
+            dst: conn.dst,
+        }
+    }
+}
+
+impl DecisionCache {
+    fn new(ttl: Duration) -> Self {
+        DecisionCache {
+            ttl,
+            decisions: HashMap::new(),
+        }
+    }
+
+    fn get(&self, conn: &Connection) -> Option<bool> {
+        self.decisions
+            .get(&DecisionKey::from(conn))
+            .filter(|(_, at)| at.elapsed() < self.ttl)
+            .map(|(allowed, _)| *allowed)
+    }
+
+    fn insert(&mut self, conn: &Connection, allowed: bool) {
+        if self.decisions.len() >= CACHE_CAPACITY {
+            let ttl = self.ttl;
+            self.decisions.retain(|_, (_, at)| at.elapsed() < ttl);
+        }
+        if self.decisions.len() >= CACHE_CAPACITY {
+            // Everything is still fresh, so make room by dropping an arbitrary decision.
+            if let Some(key) = self.decisions.keys().next().cloned() {
+                self.decisions.remove(&key);
+            }
+        }
+        self.decisions
+            .insert(DecisionKey::from(conn), (allowed, Instant::now()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers;
+    use crate::test_helpers::ext_authz::ExtAuthzServer;
+    use std::sync::atomic::Ordering;
+
+    fn conn(port: u16) -> Connection {
+        Connection {
+            src_identity: None,
+            src: "127.0.0.1:1234".parse().unwrap(),
+            dst_network: "".to_string(),
+            dst: SocketAddr::from(([127, 0, 0, 2], port)),
+        }
+    }
+
+    async fn client(server: &ExtAuthzServer, f: impl FnOnce(&mut Config)) -> ExtAuthz {
+        let addr = server.clone().spawn().await;
+        let mut cfg = test_helpers::test_config();
+        cfg.ext_authz_address = Some(format!("http://{addr}"));
+        f(&mut cfg);
+        ExtAuthz::new(&cfg).await.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn check() {
+        let server = ExtAuthzServer {
+            allowed_ports: vec![8080],
+            ..Default::default()
+        };
+        let authz = client(&server, |_| {}).await;
+        assert!(authz.check(&conn(8080)).await);
+        assert!(!authz.check(&conn(9090)).await);
+        assert_eq!(server.checks.load(Ordering::SeqCst), 2);
+
+        // Decisions are cached
+        assert!(authz.check(&conn(8080)).await);
+        assert!(!authz.check(&conn(9090)).await);
+        assert_eq!(server.checks.load(Ordering::SeqCst), 2);
+
+        // Connections differing only by source port share a decision
+        let other_port = Connection {
+            src: "127.0.0.1:5678".parse().unwrap(),
+            ..conn(8080)
+        };
+        assert!(authz.check(&other_port).await);
+        assert_eq!(server.checks.load(Ordering::SeqCst), 2);
+
+        let authz = client(&server, |cfg| cfg.ext_authz_cache_ttl = Duration::ZERO).await;
+        assert!(authz.check(&conn(8080)).await);
+        assert_eq!(server.checks.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn check_timeout() {
+        let server = ExtAuthzServer {
+            allowed_ports: vec![8080],
+            delay: Duration::from_secs(1),
+            ..Default::default()
+        };
+        let authz = client(&server, |cfg| {
+            cfg.ext_authz_timeout = Duration::from_millis(10);
+        })
+        .await;
+        assert!(!authz.check(&conn(8080)).await);
+
+        let authz = client(&server, |cfg| {
+            cfg.ext_authz_timeout = Duration::from_millis(10);
+            cfg.ext_authz_fail_open = true;
+        })
+        .await;
+        assert!(authz.check(&conn(9090)).await);
+    }
+
+    #[test]
+    fn cache_capacity() {
+        let mut cache = DecisionCache::new(Duration::from_secs(60));
+        for port in 0..=CACHE_CAPACITY as u16 {
+            cache.insert(&conn(port), true);
+        }
+        assert_eq!(cache.decisions.len(), CACHE_CAPACITY);
+        assert_eq!(cache.get(&conn(CACHE_CAPACITY as u16)), Some(true));
+    }
+}
@@ -18,12 +18,14 @@ use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use tokio::io;
 use tokio::net::TcpListener;
 use tokio::net::TcpSocket;
+use tokio::net::UdpSocket;
 
 #[cfg(target_os = "linux")]
 use {
     realm_io,
     socket2::{Domain, SockRef},
     std::io::ErrorKind,
+    std::os::unix::io::AsRawFd,
     tracing::warn,
 };
 
@@ -32,6 +34,56 @@ pub fn set_transparent(l: &TcpListener) -> io::Result<()> {
     SockRef::from(l).set_ip_transparent(true)
 }
 
+/// Marks a UDP listener as transparent, and asks the kernel to report the original destination of
+/// each datagram. This is required to serve UDP traffic redirected with TPROXY, as UDP has no
+/// equivalent of SO_ORIGINAL_DST.
+#[cfg(target_os = "linux")]
+pub fn set_transparent_udp(s: &UdpSocket) -> io::Result<()> {
+    let socket = SockRef::from(s);
+    socket.set_ip_transparent(true)?;
+    linux::set_recv_orig_dst(&socket, false)?;
+    if socket.domain()? == Domain::IPV6 {
+        // Dual-stack sockets may receive both IPv4 and IPv6 datagrams, so enable both.
+        linux::set_recv_orig_dst(&socket, true)?;
+    }
+    Ok(())
+}
+
+/// Binds a transparent UDP socket to a (possibly non-local) address, connected to `peer`. This is
+/// used to send replies to a client that appear to come from the address it originally sent to.
+///
+/// Many flows can share the same original destination, so the address is bound with SO_REUSEADDR.
+/// As the socket is connected, the kernel only delivers datagrams from `peer` to it; a connected
+/// socket is always a better match than an unconnected one, so it never takes datagrams meant for
+/// another socket on the address, such as an application listening there.
+#[cfg(target_os = "linux")]
+pub fn udp_bind_transparent(addr: SocketAddr, peer: SocketAddr) -> io::Result<std::net::UdpSocket> {
+    let socket = socket2::Socket::new(Domain::for_address(addr), socket2::Type::DGRAM, None)?;
+    socket.set_reuse_address(true)?;
+    match addr {
+        SocketAddr::V4(_) => socket.set_ip_transparent(true)?,
+        SocketAddr::V6(_) => linux::set_ipv6_transparent(&SockRef::from(&socket))?,
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.connect(&peer.into())?;
+    Ok(socket.into())
+}
+
+/// Receives a single datagram, returning its length, its source, and its original destination (if known).
+#[cfg(target_os = "linux")]
+pub async fn recv_from_orig_dst(
+    s: &UdpSocket,
+    buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr, Option<SocketAddr>)> {
+    let (n, src, dst) = s
+        .async_io(tokio::io::Interest::READABLE, || {
+            linux::recv_orig_dst(s.as_raw_fd(), buf)
+        })
+        .await?;
+    Ok((n, to_canonical(src), dst.map(to_canonical)))
+}
+
 #[cfg(target_os = "linux")]
 pub fn set_freebind_and_transparent(socket: &TcpSocket) -> io::Result<()> {
     let socket = SockRef::from(socket);
@@ -118,6 +170,31 @@ pub fn set_transparent(_: &TcpListener) -> io::Result<()> {
     ))
 }
 
+#[cfg(not(target_os = "linux"))]
+pub fn set_transparent_udp(_: &UdpSocket) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "IP_TRANSPARENT not supported on this operating system",
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn udp_bind_transparent(_: SocketAddr, _: SocketAddr) -> io::Result<std::net::UdpSocket> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "IP_TRANSPARENT not supported on this operating system",
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn recv_from_orig_dst(
+    s: &UdpSocket,
+    buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr, Option<SocketAddr>)> {
+    let (n, src) = s.recv_from(buf).await?;
+    Ok((n, to_canonical(src), None))
+}
+
 #[cfg(target_os = "linux")]
 pub fn set_mark<S: std::os::unix::io::AsFd>(socket: &S, mark: u32) -> io::Result<()> {
     let socket = SockRef::from(socket);
@@ -135,10 +212,15 @@ pub fn set_mark(_socket: &TcpSocket, _mark: u32) -> io::Result<()> {
 #[cfg(target_os = "linux")]
 #[allow(unsafe_code)]
 mod linux {
-    use std::os::unix::io::AsRawFd;
+    use std::net::SocketAddr;
+    use std::os::fd::{FromRawFd, OwnedFd};
+    use std::os::unix::io::{AsRawFd, RawFd};
+    use std::time::Duration;
 
     use socket2::{SockAddr, SockRef};
     use tokio::io;
+    use tokio::io::Interest;
+    use tokio::net::tcp::{ReadHalf, WriteHalf};
 
     pub fn set_ipv6_transparent(sock: &SockRef) -> io::Result<()> {
         unsafe {
@@ -157,6 +239,82 @@ mod linux {
         Ok(())
     }
 
+    pub fn set_recv_orig_dst(sock: &SockRef, ipv6: bool) -> io::Result<()> {
+        let (level, name) = if ipv6 {
+            (libc::SOL_IPV6, libc::IPV6_RECVORIGDSTADDR)
+        } else {
+            (libc::SOL_IP, libc::IP_RECVORIGDSTADDR)
+        };
+        unsafe {
+            let optval: libc::c_int = 1;
+            let ret = libc::setsockopt(
+                sock.as_raw_fd(),
+                level,
+                name,
+                &optval as *const _ as *const libc::c_void,
+                std::mem::size_of_val(&optval) as libc::socklen_t,
+            );
+            if ret != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    // cmsg_len is a size_t with glibc, but a socklen_t with musl.
+    #[allow(clippy::unnecessary_cast)]
+    pub fn recv_orig_dst(
+        fd: RawFd,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr, Option<SocketAddr>)> {
+        unsafe {
+            let mut src: libc::sockaddr_storage = std::mem::zeroed();
+            let mut iov = libc::iovec {
+                iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+                iov_len: buf.len(),
+            };
+            // Large enough for a single IP(V6)_ORIGDSTADDR message, and aligned for cmsghdr.
+            let mut control = [0u64; 16];
+            let mut msg: libc::msghdr = std::mem::zeroed();
+            msg.msg_name = &mut src as *mut _ as *mut libc::c_void;
+            msg.msg_namelen = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            msg.msg_controllen = std::mem::size_of_val(&control) as _;
+
+            let n = libc::recvmsg(fd, &mut msg, 0);
+            if n < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let src = SockAddr::new(src, msg.msg_namelen)
+                .as_socket()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "unexpected address family"))?;
+
+            let mut orig_dst = None;
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                let hdr = &*cmsg;
+                if (hdr.cmsg_level == libc::SOL_IP && hdr.cmsg_type == libc::IP_ORIGDSTADDR)
+                    || (hdr.cmsg_level == libc::SOL_IPV6 && hdr.cmsg_type == libc::IPV6_ORIGDSTADDR)
+                {
+                    let len = hdr.cmsg_len as usize - libc::CMSG_LEN(0) as usize;
+                    let len = len.min(std::mem::size_of::<libc::sockaddr_storage>());
+                    let mut dst: libc::sockaddr_storage = std::mem::zeroed();
+                    std::ptr::copy_nonoverlapping(
+                        libc::CMSG_DATA(cmsg),
+                        &mut dst as *mut _ as *mut u8,
+                        len,
+                    );
+                    orig_dst = SockAddr::new(dst, len as libc::socklen_t).as_socket();
+                    break;
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+            Ok((n as usize, src, orig_dst))
+        }
+    }
+
     pub fn original_dst(sock: &SockRef) -> io::Result<SockAddr> {
         sock.original_dst()
     }
@@ -164,6 +322,101 @@ mod linux {
     pub fn original_dst_ipv6(sock: &SockRef) -> io::Result<SockAddr> {
         sock.original_dst_ipv6()
     }
+
+    // The most data moved through the pipe at once. This matches the default pipe capacity.
+    const SPLICE_CHUNK: usize = 64 * 1024;
+
+    struct Pipe {
+        read: OwnedFd,
+        write: OwnedFd,
+    }
+
+    impl Pipe {
+        fn new() -> io::Result<Pipe> {
+            let mut fds: [libc::c_int; 2] = [0; 2];
+            if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // Safety: pipe2 succeeded, so both descriptors are open and owned only by us.
+            unsafe {
+                Ok(Pipe {
+                    read: OwnedFd::from_raw_fd(fds[0]),
+                    write: OwnedFd::from_raw_fd(fds[1]),
+                })
+            }
+        }
+    }
+
+    fn splice(from: RawFd, to: RawFd, len: usize) -> io::Result<usize> {
+        let n = unsafe {
+            libc::splice(
+                from,
+                std::ptr::null_mut(),
+                to,
+                std::ptr::null_mut(),
+                len,
+                libc::SPLICE_F_MOVE | libc::SPLICE_F_NONBLOCK,
+            )
+        };
+        if n < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(n as usize)
+        }
+    }
+
+    /// splice_paced copies data from one socket to another through a pipe, without copying it to
+    /// userspace. After each chunk, it pauses for as long as `pace` asks.
+    /// If the source cannot be spliced, the rest is copied through userspace instead.
+    pub async fn splice_paced(
+        mut from: ReadHalf<'_>,
+        mut to: WriteHalf<'_>,
+        pace: impl Fn(u64) -> Duration,
+    ) -> io::Result<u64> {
+        let pipe = Pipe::new()?;
+        let mut total = 0;
+        loop {
+            let res = from
+                .as_ref()
+                .async_io(Interest::READABLE, || {
+                    splice(
+                        from.as_ref().as_raw_fd(),
+                        pipe.write.as_raw_fd(),
+                        SPLICE_CHUNK,
+                    )
+                })
+                .await;
+            let n = match res {
+                Ok(n) => n,
+                // The pipe is empty at this point, so nothing is lost by switching over.
+                Err(e) if e.raw_os_error() == Some(libc::EINVAL) => {
+                    let copied = super::copy_paced(&mut from, &mut to, pace).await?;
+                    return Ok(total + copied);
+                }
+                Err(e) => return Err(e),
+            };
+            if n == 0 {
+                break;
+            }
+            let mut pending = n;
+            while pending > 0 {
+                let written = to
+                    .as_ref()
+                    .async_io(Interest::WRITABLE, || {
+                        splice(pipe.read.as_raw_fd(), to.as_ref().as_raw_fd(), pending)
+                    })
+                    .await?;
+                pending -= written;
+            }
+            total += n as u64;
+            let pause = pace(n as u64);
+            if !pause.is_zero() {
+                tokio::time::sleep(pause).await;
+            }
+        }
+        SockRef::from(to.as_ref()).shutdown(std::net::Shutdown::Write)?;
+        Ok(total)
+    }
 }
 
 #[cfg(target_os = "linux")]
@@ -189,3 +442,59 @@ pub async fn relay(
 ) -> Result<(u64, u64), Error> {
     tokio::io::copy_bidirectional(downstream, upstream).await
 }
+
+/// relay_paced copies data between two sockets like relay, and can be used to observe the transfer or
+/// limit bandwidth.
+/// After each chunk of data, `pace` is called with whether it was read from `downstream` and its
+/// size, and that direction is paused for the returned duration.
+#[cfg(target_os = "linux")]
+pub async fn relay_paced(
+    downstream: &mut tokio::net::TcpStream,
+    upstream: &mut tokio::net::TcpStream,
+    pace: impl Fn(bool, u64) -> std::time::Duration,
+) -> Result<(u64, u64), Error> {
+    let (dr, dw) = downstream.split();
+    let (ur, uw) = upstream.split();
+    tokio::try_join!(
+        linux::splice_paced(dr, uw, |n| pace(true, n)),
+        linux::splice_paced(ur, dw, |n| pace(false, n)),
+    )
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn relay_paced(
+    downstream: &mut tokio::net::TcpStream,
+    upstream: &mut tokio::net::TcpStream,
+    pace: impl Fn(bool, u64) -> std::time::Duration,
+) -> Result<(u64, u64), Error> {
+    let (dr, dw) = downstream.split();
+    let (ur, uw) = upstream.split();
+    tokio::try_join!(
+        copy_paced(dr, uw, |n| pace(true, n)),
+        copy_paced(ur, dw, |n| pace(false, n)),
+    )
+}
+
+// copy_paced copies data from one stream to another through userspace, pausing after each chunk for
+// as long as `pace` asks. This is used where splicing is not available.
+async fn copy_paced(
+    mut from: impl io::AsyncRead + Unpin,
+    mut to: impl io::AsyncWrite + Unpin,
+    pace: impl Fn(u64) -> std::time::Duration,
+) -> Result<u64, Error> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut buf = vec![0; 16 * 1024];
+    let mut total = 0;
+    loop {
+        let n = from.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        to.write_all(&buf[..n]).await?;
+        total += n as u64;
+        tokio::time::sleep(pace(n as u64)).await;
+    }
+    to.shutdown().await?;
+    Ok(total)
+}
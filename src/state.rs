@@ -12,15 +12,17 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::identity::SecretManager;
+use crate::identity::{Identity, SecretManager};
 use crate::proxy;
 use crate::proxy::{Error, OnDemandDnsLabels};
+use crate::state::load_balancer::{LoadBalanceHints, LoadBalancerAlgorithms};
+use crate::state::outlier::{OutlierConfig, OutlierDetector};
 use crate::state::policy::PolicyStore;
 use crate::state::service::{Endpoint, LoadBalancerMode, LoadBalancerScopes, ServiceStore};
 use crate::state::service::{Service, ServiceDescription};
 use crate::state::workload::{
-    address::Address, gatewayaddress::Destination, network_addr, NamespacedHostname,
-    NetworkAddress, Protocol, WaypointError, Workload, WorkloadStore,
+    address::Address, gatewayaddress::Destination, network_addr, GatewayAddress,
+    NamespacedHostname, NetworkAddress, Protocol, WaypointError, Workload, WorkloadStore,
 };
 use crate::tls;
 use crate::xds::istio::security::Authorization as XdsAuthorization;
@@ -41,6 +43,8 @@ use std::net::{IpAddr, SocketAddr};
 use std::sync::{Arc, RwLock, RwLockReadGuard};
 use tracing::{debug, error, trace, warn};
 
+pub mod load_balancer;
+pub mod outlier;
 pub mod policy;
 pub mod service;
 pub mod workload;
@@ -123,6 +127,21 @@ pub struct ProxyRbacContext {
     pub dest_workload_info: Option<Arc<WorkloadInfo>>,
 }
 
+/// RbacExplanation describes how authorization policies apply to a connection, for debugging.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RbacExplanation {
+    pub connection: rbac::Connection,
+    pub destination_workload: String,
+    /// Every policy that applies to the destination, in the order they are considered.
+    pub policies: Vec<rbac::PolicyExplanation>,
+    /// The decision of the local policies.
+    pub decision: rbac::RbacDecision,
+    /// If a CUSTOM policy matched, the external authorization service decides as well; this is the
+    /// decision if it denies the connection.
+    pub custom: Option<rbac::RbacDecision>,
+}
+
 impl fmt::Display for ProxyRbacContext {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.conn)?;
@@ -146,6 +165,13 @@ pub struct ProxyState {
 
     #[serde(flatten)]
     pub resolved_dns: ResolvedDnsStore,
+
+    /// Passive health of service endpoints, used to avoid endpoints that are failing.
+    pub outliers: OutlierDetector,
+
+    /// Which algorithm is used to pick service endpoints.
+    #[serde(skip_serializing)]
+    pub load_balancing: LoadBalancerAlgorithms,
 }
 
 /// A ResolvedDnsStore encapsulates all resolved DNS information for workloads in the mesh
@@ -214,6 +240,19 @@ impl ProxyState {
         network: &str,
         source_workload: &Workload,
         addr: SocketAddr,
+    ) -> Option<Upstream> {
+        self.find_upstream_with_hints(network, source_workload, addr, &Default::default())
+    }
+
+    /// Like [ProxyState::find_upstream], but uses additional `hints` when picking a service endpoint.
+    /// Service endpoints for any of the excluded workload UIDs will not be selected. This allows picking
+    /// an alternate endpoint when a connection fails.
+    pub fn find_upstream_with_hints(
+        &self,
+        network: &str,
+        source_workload: &Workload,
+        addr: SocketAddr,
+        hints: &LoadBalanceHints,
     ) -> Option<Upstream> {
         if let Some(svc) = self.services.get_by_vip(&network_addr(network, addr.ip())) {
             let Some(target_port) = svc.ports.get(&addr.port()) else {
@@ -224,9 +263,7 @@ impl ProxyState {
                 );
                 return None;
             };
-            // Randomly pick an upstream
-            // TODO: do this more efficiently, and not just randomly
-            let Some(ep) = self.load_balance(source_workload, &svc) else {
+            let Some(ep) = self.load_balance(source_workload, &svc, hints) else {
                 debug!("VIP {} has no healthy endpoints", addr);
                 return None;
             };
@@ -259,14 +296,37 @@ impl ProxyState {
         None
     }
 
-    fn load_balance<'a>(&self, src: &Workload, svc: &'a Service) -> Option<&'a Endpoint> {
+    fn load_balance<'a>(
+        &self,
+        src: &Workload,
+        svc: &'a Service,
+        hints: &LoadBalanceHints,
+    ) -> Option<&'a Endpoint> {
+        let excluded = hints.excluded;
+        // Avoid endpoints ejected by outlier detection. If every endpoint is ejected, it is better to
+        // try one anyways than to fail outright.
+        self.load_balance_filtered(src, svc, hints, |ep| {
+            !excluded.contains(&ep.workload_uid) && !self.outliers.is_ejected(&ep.workload_uid)
+        })
+        .or_else(|| {
+            self.load_balance_filtered(src, svc, hints, |ep| !excluded.contains(&ep.workload_uid))
+        })
+    }
+
+    fn load_balance_filtered<'a>(
+        &self,
+        src: &Workload,
+        svc: &'a Service,
+        hints: &LoadBalanceHints,
+        filter: impl Fn(&Endpoint) -> bool,
+    ) -> Option<&'a Endpoint> {
+        let algorithm = self.load_balancing.for_service(svc);
+        let endpoints = svc.endpoints.values().filter(|ep| filter(ep));
         match svc.load_balancer {
-            None => svc.endpoints.values().choose(&mut rand::thread_rng()),
+            None => load_balancer::select(algorithm, endpoints, hints),
             Some(ref lb) => {
-                let ranks = svc
-                    .endpoints
-                    .iter()
-                    .filter_map(|(_, ep)| {
+                let ranks = endpoints
+                    .filter_map(|ep| {
                         let Some(wl) = self.workloads.find_uid(&ep.workload_uid) else {
                             debug!("failed to fetch workload for {}", ep.workload_uid);
                             return None;
@@ -306,11 +366,11 @@ impl ProxyState {
                     })
                     .collect::<Vec<_>>();
                 let max = *ranks.iter().map(|(rank, _ep)| rank).max()?;
-                ranks
+                let closest = ranks
                     .into_iter()
                     .filter(|(rank, _ep)| *rank == max)
-                    .map(|(_, ep)| ep)
-                    .choose(&mut rand::thread_rng())
+                    .map(|(_, ep)| ep);
+                load_balancer::select(algorithm, closest, hints)
             }
         }
     }
@@ -332,6 +392,10 @@ pub struct DemandProxyState {
 
     #[serde(skip_serializing)]
     dns_resolver_opts: ResolverOpts,
+
+    /// If present, used to check connections matching CUSTOM authorization policies.
+    #[serde(skip_serializing)]
+    ext_authz: Option<rbac::ext_authz::ExtAuthz>,
 }
 
 impl DemandProxyState {
@@ -346,55 +410,232 @@ impl DemandProxyState {
             demand,
             dns_resolver_cfg,
             dns_resolver_opts,
+            ext_authz: None,
         }
     }
 
+    pub fn with_ext_authz(mut self, ext_authz: Option<rbac::ext_authz::ExtAuthz>) -> Self {
+        self.ext_authz = ext_authz;
+        self
+    }
+
     pub fn read(&self) -> RwLockReadGuard<'_, ProxyState> {
         self.state.read().unwrap()
     }
 
-    pub async fn assert_rbac(&self, ctx: &ProxyRbacContext) -> bool {
+    pub fn outliers(&self) -> OutlierDetector {
+        self.state.read().unwrap().outliers.clone()
+    }
+
+    pub async fn assert_rbac(&self, ctx: &ProxyRbacContext) -> rbac::RbacDecision {
+        self.evaluate_rbac(ctx, true).await
+    }
+
+    /// reassert_rbac re-evaluates policy for an established connection after a policy update. The
+    /// external authorization service is only consulted when a connection is established, so it is
+    /// not called again; matching CUSTOM policies are left to the local policies' decision.
+    pub async fn reassert_rbac(&self, ctx: &ProxyRbacContext) -> rbac::RbacDecision {
+        self.evaluate_rbac(ctx, false).await
+    }
+
+    async fn evaluate_rbac(
+        &self,
+        ctx: &ProxyRbacContext,
+        check_external: bool,
+    ) -> rbac::RbacDecision {
         let nw_addr = network_addr(&ctx.conn.dst_network, ctx.conn.dst.ip());
         let Some(wl) = self.fetch_workload(&nw_addr).await else {
             debug!("destination workload not found {}", nw_addr);
-            return false;
+            return rbac::RbacDecision::deny();
         };
         if let Some(ref wl_info) = ctx.dest_workload_info {
             // make sure that the workload we fetched matches the workload info we got over ZDS.
             if !wl_info.matches(&wl) {
                 error!("workload does not match proxy workload uid. this is probably a bug. please report an issue");
-                return false;
+                return rbac::RbacDecision::deny();
             }
         }
         let conn = &ctx.conn;
+        let (mut decision, custom) = self.check_rbac(conn, &wl);
+
+        // If a CUSTOM policy matches, the external authorization service must also allow the
+        // connection, whatever the other policies decide.
+        if let Some(custom) = custom.filter(|_| check_external) {
+            let policy = custom.policy.clone().unwrap_or_default();
+            let allowed = match &self.ext_authz {
+                Some(ext_authz) => ext_authz.check(conn).await,
+                None => {
+                    warn!(
+                        policy,
+                        "CUSTOM policy matched, but no external authorization service is configured"
+                    );
+                    false
+                }
+            };
+            if !allowed {
+                debug!(policy, "external authorization denied the connection");
+                if let Some(shadow) = &mut decision.shadow {
+                    shadow.allowed = false;
+                    shadow.policy = Some(policy);
+                }
+                decision = rbac::RbacDecision {
+                    audit_policies: decision.audit_policies,
+                    shadow: decision.shadow,
+                    ..custom
+                };
+            }
+        }
+
+        if let Some(shadow) = &decision.shadow {
+            let describe = |allowed: bool| if allowed { "allow" } else { "deny" };
+            let policy = shadow.policy.as_deref().unwrap_or("default");
+            if shadow.allowed != decision.allowed {
+                debug!(
+                    %conn,
+                    decision = describe(decision.allowed),
+                    shadow = describe(shadow.allowed),
+                    policy,
+                    "dry-run policies would change the decision"
+                );
+            } else {
+                debug!(
+                    shadow = describe(shadow.allowed),
+                    policy, "dry-run policies agree with the decision"
+                );
+            }
+        }
+        decision
+    }
+
+    // check_rbac evaluates the local policies for a connection to `wl`. If a CUSTOM policy matches,
+    // the decision it makes if the external authorization service denies the connection is returned
+    // as well.
+    fn check_rbac(
+        &self,
+        conn: &rbac::Connection,
+        wl: &Workload,
+    ) -> (rbac::RbacDecision, Option<rbac::RbacDecision>) {
         let state = self.state.read().unwrap();
 
+        // Aggregate all of them based on type
+        let (mut allow, mut deny, mut audit, mut custom) =
+            (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        let (mut dry_run_allow, mut dry_run_deny) = (Vec::new(), Vec::new());
+        for pol in Self::applicable_policies(&state, wl) {
+            match (pol.action, pol.dry_run) {
+                (rbac::RbacAction::Allow, false) => allow.push(pol),
+                (rbac::RbacAction::Deny, false) => deny.push(pol),
+                (rbac::RbacAction::Allow, true) => dry_run_allow.push(pol),
+                (rbac::RbacAction::Deny, true) => dry_run_deny.push(pol),
+                (rbac::RbacAction::Audit, _) => audit.push(pol),
+                (rbac::RbacAction::Custom, false) => custom.push(pol),
+                // Checking dry-run CUSTOM policies would mean calling the external authorization
+                // service for decisions that are not used.
+                (rbac::RbacAction::Custom, true) => {
+                    trace!(policy = pol.to_key(), "skipping dry-run CUSTOM policy")
+                }
+            }
+        }
+
+        trace!(
+            allow = allow.len(),
+            deny = deny.len(),
+            audit = audit.len(),
+            custom = custom.len(),
+            dry_run_allow = dry_run_allow.len(),
+            dry_run_deny = dry_run_deny.len(),
+            "checking connection"
+        );
+
+        // AUDIT policies are evaluated regardless of the decision, and never change it.
+        let audit_policies: Vec<String> = audit
+            .iter()
+            .filter(|pol| pol.matches(conn))
+            .map(|pol| pol.to_key())
+            .collect();
+        if !audit_policies.is_empty() {
+            debug!(policies = ?audit_policies, "audit policy match");
+        }
+
+        let custom = custom.iter().find_map(|pol| {
+            pol.matched_rule(conn)
+                .map(|rule| rbac::RbacDecision::matched(false, pol, rule))
+        });
+        let decision = Self::check_policies(conn, &allow, &deny);
+
+        // Dry-run policies are evaluated as if they were enforced alongside the others, but the
+        // result is only reported.
+        let shadow = if dry_run_allow.is_empty() && dry_run_deny.is_empty() {
+            None
+        } else {
+            allow.extend(dry_run_allow);
+            deny.extend(dry_run_deny);
+            let shadow = Self::check_policies(conn, &allow, &deny);
+            Some(rbac::ShadowDecision {
+                allowed: shadow.allowed,
+                policy: shadow.policy,
+            })
+        };
+
+        (
+            rbac::RbacDecision {
+                audit_policies,
+                shadow,
+                ..decision
+            },
+            custom,
+        )
+    }
+
+    // applicable_policies returns the policies that apply to connections to `wl`.
+    fn applicable_policies<'a>(
+        state: &'a ProxyState,
+        wl: &Workload,
+    ) -> Vec<&'a rbac::Authorization> {
         // We can get policies from namespace, global, and workload...
         let ns = state.policies.get_by_namespace(&wl.namespace);
         let global = state.policies.get_by_namespace("");
         let workload = wl.authorization_policies.iter();
-
-        // Aggregate all of them based on type
-        let (allow, deny): (Vec<_>, Vec<_>) = ns
-            .iter()
+        ns.iter()
             .chain(global.iter())
             .chain(workload)
             .filter_map(|k| state.policies.get(k))
-            .partition(|p| p.action == rbac::RbacAction::Allow);
+            .collect()
+    }
 
-        trace!(
-            allow = allow.len(),
-            deny = deny.len(),
-            "checking connection"
-        );
+    /// explain_rbac evaluates the policies for a hypothetical connection against the current state,
+    /// explaining how each of them applies. Unlike assert_rbac, the destination workload is not
+    /// fetched on demand, and the external authorization service is not checked.
+    pub fn explain_rbac(&self, conn: &rbac::Connection) -> Option<RbacExplanation> {
+        let nw_addr = network_addr(&conn.dst_network, conn.dst.ip());
+        let wl = self.read().workloads.find_address(&nw_addr)?;
+        let (decision, custom) = self.check_rbac(conn, &wl);
+        let policies = Self::applicable_policies(&self.read(), &wl)
+            .into_iter()
+            .map(|pol| pol.explain(conn))
+            .collect();
+        Some(RbacExplanation {
+            connection: conn.clone(),
+            destination_workload: wl.uid.clone(),
+            policies,
+            decision,
+            custom,
+        })
+    }
 
+    // check_policies decides whether the connection is allowed by the given ALLOW and DENY policies.
+    fn check_policies(
+        conn: &rbac::Connection,
+        allow: &[&rbac::Authorization],
+        deny: &[&rbac::Authorization],
+    ) -> rbac::RbacDecision {
         // Allow and deny logic follows https://istio.io/latest/docs/reference/config/security/authorization-policy/
 
         // "If there are any DENY policies that match the request, deny the request."
         for pol in deny.iter() {
-            if pol.matches(conn) {
-                debug!(policy = pol.to_key(), "deny policy match");
-                return false;
+            if let Some(rule) = pol.matched_rule(conn) {
+                debug!(policy = pol.to_key(), rule, "deny policy match");
+                return rbac::RbacDecision::matched(false, pol, rule);
             } else {
                 trace!(policy = pol.to_key(), "deny policy does not match");
             }
@@ -402,20 +643,26 @@ impl DemandProxyState {
         // "If there are no ALLOW policies for the workload, allow the request."
         if allow.is_empty() {
             debug!("no allow policies, allow");
-            return true;
+            return rbac::RbacDecision {
+                allowed: true,
+                ..Default::default()
+            };
         }
         // "If any of the ALLOW policies match the request, allow the request."
         for pol in allow.iter() {
-            if pol.matches(conn) {
-                debug!(policy = pol.to_key(), "allow policy match");
-                return true;
+            if let Some(rule) = pol.matched_rule(conn) {
+                debug!(policy = pol.to_key(), rule, "allow policy match");
+                return rbac::RbacDecision::matched(true, pol, rule);
             } else {
                 trace!(policy = pol.to_key(), "allow policy does not match");
             }
         }
         // "Deny the request."
         debug!("no allow policies matched");
-        false
+        rbac::RbacDecision {
+            default_deny: true,
+            ..Default::default()
+        }
     }
 
     // this should only be called once per request (for the workload itself and potentially its waypoint)
@@ -428,7 +675,6 @@ impl DemandProxyState {
         // TODO: add more sophisticated routing logic, perhaps based on ipv4/ipv6 support underneath us.
         // if/when we support that, this function may need to move to get access to the necessary metadata.
         // Randomly pick an IP
-        // TODO: do this more efficiently, and not just randomly
         if let Some(ip) = dst_workload.workload_ips.choose(&mut rand::thread_rng()) {
             return Ok(*ip);
         }
@@ -483,7 +729,6 @@ impl DemandProxyState {
         // TODO: add more sophisticated routing logic, perhaps based on ipv4/ipv6 support underneath us.
         // if/when we support that, this function may need to move to get access to the necessary metadata.
         // Randomly pick an IP
-        // TODO: do this more efficiently, and not just randomly
         let Some(ip) = rdns.ips.iter().choose(&mut rand::thread_rng()) else {
             return Err(Error::EmptyResolvedAddresses(workload_uid));
         };
@@ -620,12 +865,23 @@ impl DemandProxyState {
         network: &str,
         source_workload: &Workload,
         addr: SocketAddr,
+    ) -> Option<Upstream> {
+        self.fetch_upstream_with_hints(network, source_workload, addr, &Default::default())
+            .await
+    }
+
+    pub async fn fetch_upstream_with_hints(
+        &self,
+        network: &str,
+        source_workload: &Workload,
+        addr: SocketAddr,
+        hints: &LoadBalanceHints<'_>,
     ) -> Option<Upstream> {
         self.fetch_address(&network_addr(network, addr.ip())).await;
         self.state
             .read()
             .unwrap()
-            .find_upstream(network, source_workload, addr)
+            .find_upstream_with_hints(network, source_workload, addr, hints)
     }
 
     pub async fn fetch_waypoint(
@@ -639,13 +895,12 @@ impl DemandProxyState {
         };
         // Even in this case, we are picking a single upstream pod and deciding if it has a remote proxy.
         // Typically this is all or nothing, but if not we should probably send to remote proxy if *any* upstream has one.
-        let wp_nw_addr = match &gw_address.destination {
-            Destination::Address(ip) => ip,
-            Destination::Hostname(_) => {
-                return Err(WaypointError::UnsupportedFeature(
-                    "hostname lookup not supported yet".to_string(),
-                ));
-            }
+        let Some(wp_nw_addr) = self
+            .fetch_gateway_address(gw_address, &source_workload.network)
+            .await
+        else {
+            debug!(%wl.name, "waypoint address not found");
+            return Err(WaypointError::FindWaypointError(wl.name.to_owned()));
         };
         let wp_socket_addr = SocketAddr::new(wp_nw_addr.address, gw_address.hbone_mtls_port);
         match self
@@ -704,6 +959,48 @@ impl DemandProxyState {
         self.state.read().unwrap().find_hostname(hostname)
     }
 
+    /// Resolves a gateway (such as a waypoint) to a network address. Gateways referenced by hostname
+    /// are looked up, on-demand if needed, and resolved to a service VIP on the given network or to a
+    /// workload IP.
+    pub async fn fetch_gateway_address(
+        &self,
+        gw_address: &GatewayAddress,
+        network: &str,
+    ) -> Option<NetworkAddress> {
+        let hostname = match &gw_address.destination {
+            Destination::Address(addr) => return Some(addr.clone()),
+            Destination::Hostname(hostname) => hostname,
+        };
+        match self.fetch_hostname(hostname).await? {
+            Address::Service(svc) => svc.vips.into_iter().find(|vip| vip.network == network),
+            Address::Workload(wl) => wl
+                .workload_ips
+                .first()
+                .map(|ip| network_addr(&wl.network, *ip)),
+        }
+    }
+
+    /// Returns the identities of the workloads that make up the gateway. For a gateway addressed by
+    /// service, this is the identity of every endpoint of the service.
+    pub async fn fetch_gateway_identities(&self, gw_address: &GatewayAddress) -> Vec<Identity> {
+        match self.fetch_destination(&gw_address.destination).await {
+            Some(Address::Workload(wl)) => vec![wl.identity()],
+            Some(Address::Service(svc)) => {
+                let mut identities = Vec::new();
+                for ep in svc.endpoints.values() {
+                    if let Some(wl) = self.fetch_workload_by_uid(&ep.workload_uid).await {
+                        let id = wl.identity();
+                        if !identities.contains(&id) {
+                            identities.push(id);
+                        }
+                    }
+                }
+                identities
+            }
+            None => Vec::new(),
+        }
+    }
+
     pub async fn fetch_on_demand(&self, key: String) {
         if let Some(demand) = &self.demand {
             debug!(%key, "sending demand request");
@@ -754,7 +1051,18 @@ impl ProxyStateManager {
         cert_manager: Arc<SecretManager>,
     ) -> anyhow::Result<ProxyStateManager> {
         let cert_fetcher = cert_fetcher::new(&config, cert_manager);
-        let state: Arc<RwLock<ProxyState>> = Arc::new(RwLock::new(ProxyState::default()));
+        let state: Arc<RwLock<ProxyState>> = Arc::new(RwLock::new(ProxyState {
+            outliers: OutlierDetector::new(OutlierConfig {
+                consecutive_failures: config.outlier_consecutive_failures,
+                base_ejection_time: config.outlier_base_ejection_time,
+                max_ejection_time: config.outlier_max_ejection_time,
+            }),
+            load_balancing: LoadBalancerAlgorithms {
+                default: config.load_balancing_algorithm,
+                overrides: config.load_balancing_overrides.clone(),
+            },
+            ..Default::default()
+        }));
         let xds_client = if config.xds_address.is_some() {
             let updater = ProxyStateUpdater::new(state.clone(), cert_fetcher.clone());
             let tls_client_fetcher = Box::new(tls::ControlPlaneAuthentication::RootCert(
@@ -769,6 +1077,7 @@ impl ProxyStateManager {
         } else {
             None
         };
+        let ext_authz = rbac::ext_authz::ExtAuthz::new(&config).await?;
         if let Some(cfg) = config.local_xds_config {
             let local_client = LocalClient {
                 cfg,
@@ -785,6 +1094,7 @@ impl ProxyStateManager {
                 demand,
                 dns_resolver_cfg: config.dns_resolver_cfg,
                 dns_resolver_opts: config.dns_resolver_opts,
+                ext_authz,
             },
         })
     }
@@ -921,38 +1231,295 @@ mod tests {
             },
             dest_workload_info: Some(Arc::new(wi.clone())),
         };
-        assert!(mock_proxy_state.assert_rbac(&ctx).await);
+        assert!(mock_proxy_state.assert_rbac(&ctx).await.allowed);
 
         // now make sure it fails when we change just one property of the workload info
         {
             let mut wi = wi.clone();
             wi.name = "not-test".to_string();
             ctx.dest_workload_info = Some(Arc::new(wi.clone()));
-            assert!(!mock_proxy_state.assert_rbac(&ctx).await);
+            assert!(!mock_proxy_state.assert_rbac(&ctx).await.allowed);
         }
         {
             let mut wi = wi.clone();
             wi.namespace = "not-test".to_string();
             ctx.dest_workload_info = Some(Arc::new(wi.clone()));
-            assert!(!mock_proxy_state.assert_rbac(&ctx).await);
+            assert!(!mock_proxy_state.assert_rbac(&ctx).await.allowed);
         }
         {
             let mut wi = wi.clone();
             wi.service_account = "not-test".to_string();
             ctx.dest_workload_info = Some(Arc::new(wi.clone()));
-            assert!(!mock_proxy_state.assert_rbac(&ctx).await);
+            assert!(!mock_proxy_state.assert_rbac(&ctx).await.allowed);
         }
         {
             let mut wi = wi.clone();
             wi.trust_domain = "not-test".to_string();
             ctx.dest_workload_info = Some(Arc::new(wi.clone()));
-            assert!(!mock_proxy_state.assert_rbac(&ctx).await);
+            assert!(!mock_proxy_state.assert_rbac(&ctx).await.allowed);
         }
     }
 
     #[tokio::test]
-    async fn test_load_balance() {
+    async fn assert_rbac_audit() {
+        let mut state = ProxyState::default();
+        let wl = Workload {
+            name: "test".to_string(),
+            namespace: "default".to_string(),
+            workload_ips: vec![IpAddr::V4(Ipv4Addr::new(192, 168, 0, 2))],
+            ..test_helpers::test_default_workload()
+        };
+        state.workloads.insert(wl);
+        state.policies.insert(rbac::Authorization {
+            name: "audit-8080".to_string(),
+            namespace: "default".to_string(),
+            scope: rbac::RbacScope::Namespace,
+            action: rbac::RbacAction::Audit,
+            rules: vec![vec![vec![rbac::RbacMatch {
+                destination_ports: vec![8080],
+                ..Default::default()
+            }]]],
+            dry_run: false,
+        });
+
+        let mock_proxy_state = DemandProxyState::new(
+            Arc::new(RwLock::new(state)),
+            None,
+            ResolverConfig::default(),
+            ResolverOpts::default(),
+        );
+
+        let ctx = |port| crate::state::ProxyRbacContext {
+            conn: rbac::Connection {
+                src_identity: None,
+                src: "192.168.0.1:1234".parse().unwrap(),
+                dst_network: "".to_string(),
+                dst: SocketAddr::from((Ipv4Addr::new(192, 168, 0, 2), port)),
+            },
+            dest_workload_info: None,
+        };
+        // An AUDIT policy flags the connection, but does not act as an ALLOW policy
+        let decision = mock_proxy_state.assert_rbac(&ctx(8080)).await;
+        assert!(decision.allowed);
+        assert_eq!(
+            decision.audit_policies,
+            vec!["default/audit-8080".to_string()]
+        );
+
+        let decision = mock_proxy_state.assert_rbac(&ctx(9090)).await;
+        assert!(decision.allowed);
+        assert!(decision.audit_policies.is_empty());
+    }
+
+    #[tokio::test]
+    async fn assert_rbac_dry_run() {
+        let mut state = ProxyState::default();
+        let wl = Workload {
+            name: "test".to_string(),
+            namespace: "default".to_string(),
+            workload_ips: vec![IpAddr::V4(Ipv4Addr::new(192, 168, 0, 2))],
+            ..test_helpers::test_default_workload()
+        };
+        state.workloads.insert(wl);
+        state.policies.insert(rbac::Authorization {
+            name: "allow-8080".to_string(),
+            namespace: "default".to_string(),
+            scope: rbac::RbacScope::Namespace,
+            action: rbac::RbacAction::Allow,
+            rules: vec![vec![vec![rbac::RbacMatch {
+                destination_ports: vec![8080],
+                ..Default::default()
+            }]]],
+            dry_run: true,
+        });
+
+        let mock_proxy_state = DemandProxyState::new(
+            Arc::new(RwLock::new(state)),
+            None,
+            ResolverConfig::default(),
+            ResolverOpts::default(),
+        );
+
+        let ctx = |port| crate::state::ProxyRbacContext {
+            conn: rbac::Connection {
+                src_identity: None,
+                src: "192.168.0.1:1234".parse().unwrap(),
+                dst_network: "".to_string(),
+                dst: SocketAddr::from((Ipv4Addr::new(192, 168, 0, 2), port)),
+            },
+            dest_workload_info: None,
+        };
+        // Dry-run policies never change the decision, only the shadow decision
+        let decision = mock_proxy_state.assert_rbac(&ctx(8080)).await;
+        assert!(decision.allowed);
+        assert_eq!(
+            decision.shadow,
+            Some(rbac::ShadowDecision {
+                allowed: true,
+                policy: Some("default/allow-8080".to_string()),
+            })
+        );
+
+        let decision = mock_proxy_state.assert_rbac(&ctx(9090)).await;
+        assert!(decision.allowed);
+        assert_eq!(
+            decision.shadow,
+            Some(rbac::ShadowDecision {
+                allowed: false,
+                policy: None,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn assert_rbac_custom() {
+        let mut state = ProxyState::default();
+        let wl = Workload {
+            name: "test".to_string(),
+            namespace: "default".to_string(),
+            workload_ips: vec![IpAddr::V4(Ipv4Addr::new(192, 168, 0, 2))],
+            ..test_helpers::test_default_workload()
+        };
+        state.workloads.insert(wl);
+        state.policies.insert(rbac::Authorization {
+            name: "ext-authz".to_string(),
+            namespace: "default".to_string(),
+            scope: rbac::RbacScope::Namespace,
+            action: rbac::RbacAction::Custom,
+            rules: vec![vec![vec![rbac::RbacMatch {
+                destination_ports: vec![8080, 9090],
+                ..Default::default()
+            }]]],
+            dry_run: false,
+        });
+        let state = Arc::new(RwLock::new(state));
+
+        let ctx = |port| crate::state::ProxyRbacContext {
+            conn: rbac::Connection {
+                src_identity: None,
+                src: "192.168.0.1:1234".parse().unwrap(),
+                dst_network: "".to_string(),
+                dst: SocketAddr::from((Ipv4Addr::new(192, 168, 0, 2), port)),
+            },
+            dest_workload_info: None,
+        };
+
+        // Without an external authorization service, matching connections are denied
+        let mock_proxy_state = DemandProxyState::new(
+            state.clone(),
+            None,
+            ResolverConfig::default(),
+            ResolverOpts::default(),
+        );
+        assert!(!mock_proxy_state.assert_rbac(&ctx(8080)).await.allowed);
+        assert!(mock_proxy_state.assert_rbac(&ctx(7070)).await.allowed);
+
+        let addr = test_helpers::ext_authz::ExtAuthzServer {
+            allowed_ports: vec![8080],
+            ..Default::default()
+        }
+        .spawn()
+        .await;
+        let mut cfg = test_helpers::test_config();
+        cfg.ext_authz_address = Some(format!("http://{addr}"));
+        let ext_authz = rbac::ext_authz::ExtAuthz::new(&cfg).await.unwrap();
+        let mock_proxy_state = DemandProxyState::new(
+            state,
+            None,
+            ResolverConfig::default(),
+            ResolverOpts::default(),
+        )
+        .with_ext_authz(ext_authz);
+        assert!(mock_proxy_state.assert_rbac(&ctx(8080)).await.allowed);
+        let decision = mock_proxy_state.assert_rbac(&ctx(9090)).await;
+        assert!(!decision.allowed);
+        assert_eq!(decision.action, Some(rbac::RbacAction::Custom));
+        assert_eq!(decision.policy.as_deref(), Some("default/ext-authz"));
+        assert_eq!(decision.rule, Some(0));
+        // Connections not matching the CUSTOM policy are not sent to the service
+        assert!(mock_proxy_state.assert_rbac(&ctx(7070)).await.allowed);
+        // Nor are connections re-evaluated after a policy update
+        assert!(mock_proxy_state.reassert_rbac(&ctx(9090)).await.allowed);
+    }
+
+    #[test]
+    fn explain_rbac() {
         let mut state = ProxyState::default();
+        let wl = Workload {
+            name: "test".to_string(),
+            namespace: "default".to_string(),
+            workload_ips: vec![IpAddr::V4(Ipv4Addr::new(192, 168, 0, 2))],
+            ..test_helpers::test_default_workload()
+        };
+        state.workloads.insert(wl);
+        let policy = |name: &str, action, port| rbac::Authorization {
+            name: name.to_string(),
+            namespace: "default".to_string(),
+            scope: rbac::RbacScope::Namespace,
+            action,
+            rules: vec![vec![vec![rbac::RbacMatch {
+                destination_ports: vec![port],
+                ..Default::default()
+            }]]],
+            dry_run: false,
+        };
+        state
+            .policies
+            .insert(policy("allow-8080", rbac::RbacAction::Allow, 8080));
+        state
+            .policies
+            .insert(policy("deny-9090", rbac::RbacAction::Deny, 9090));
+
+        let mock_proxy_state = DemandProxyState::new(
+            Arc::new(RwLock::new(state)),
+            None,
+            ResolverConfig::default(),
+            ResolverOpts::default(),
+        );
+
+        let conn = |ip, port| rbac::Connection {
+            src_identity: None,
+            src: "192.168.0.1:1234".parse().unwrap(),
+            dst_network: "".to_string(),
+            dst: SocketAddr::from((ip, port)),
+        };
+        let explanation = mock_proxy_state
+            .explain_rbac(&conn(Ipv4Addr::new(192, 168, 0, 2), 9090))
+            .unwrap();
+        assert!(!explanation.decision.allowed);
+        assert_eq!(
+            explanation.decision.policy.as_deref(),
+            Some("default/deny-9090")
+        );
+        // Every applicable policy is explained, not just the one that decided
+        let mut matched: Vec<_> = explanation
+            .policies
+            .iter()
+            .map(|p| (p.policy.as_str(), p.matched_rule))
+            .collect();
+        matched.sort();
+        assert_eq!(
+            matched,
+            vec![("default/allow-8080", None), ("default/deny-9090", Some(0))]
+        );
+        assert_eq!(explanation.custom, None);
+
+        // Unknown destinations are not explained
+        assert!(mock_proxy_state
+            .explain_rbac(&conn(Ipv4Addr::new(192, 168, 0, 3), 8080))
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_load_balance() {
+        let mut state = ProxyState {
+            outliers: OutlierDetector::new(OutlierConfig {
+                consecutive_failures: 1,
+                base_ejection_time: Duration::from_secs(60),
+                max_ejection_time: Duration::from_secs(60),
+            }),
+            ..Default::default()
+        };
         let wl_no_locality = Workload {
             uid: "cluster1//v1/Pod/default/wl_no_locality".to_string(),
             name: "wl_no_locality".to_string(),
@@ -992,7 +1559,7 @@ mod tests {
             },
             ..test_helpers::test_default_workload()
         };
-        let _ep_almost = Workload {
+        let ep_almost = Workload {
             uid: "cluster1//v1/Pod/default/ep_almost".to_string(),
             name: "wl_almost".to_string(),
             namespace: "default".to_string(),
@@ -1007,7 +1574,7 @@ mod tests {
             },
             ..test_helpers::test_default_workload()
         };
-        let _ep_no_match = Workload {
+        let ep_no_match = Workload {
             uid: "cluster1//v1/Pod/default/ep_no_match".to_string(),
             name: "wl_almost".to_string(),
             namespace: "default".to_string(),
@@ -1041,7 +1608,7 @@ mod tests {
             (
                 "cluster1//v1/Pod/default/ep_no_match".to_string(),
                 Endpoint {
-                    workload_uid: "cluster1//v1/Pod/default/ep_almost".to_string(),
+                    workload_uid: "cluster1//v1/Pod/default/ep_no_match".to_string(),
                     service: NamespacedHostname {
                         namespace: TEST_SERVICE_NAMESPACE.to_string(),
                         hostname: "example.com".to_string(),
@@ -1073,6 +1640,7 @@ mod tests {
             endpoints: endpoints.clone(),
             load_balancer: Some(LoadBalancer {
                 mode: LoadBalancerMode::Strict,
+                algorithm: None,
                 routing_preferences: vec![
                     LoadBalancerScopes::Network,
                     LoadBalancerScopes::Region,
@@ -1085,6 +1653,7 @@ mod tests {
             endpoints,
             load_balancer: Some(LoadBalancer {
                 mode: LoadBalancerMode::Failover,
+                algorithm: None,
                 routing_preferences: vec![
                     LoadBalancerScopes::Network,
                     LoadBalancerScopes::Region,
@@ -1096,12 +1665,31 @@ mod tests {
         state.workloads.insert(wl_no_locality.clone());
         state.workloads.insert(wl_match.clone());
         state.workloads.insert(wl_almost.clone());
+        state.workloads.insert(ep_almost);
+        state.workloads.insert(ep_no_match);
         state.services.insert(strict_svc.clone());
         state.services.insert(failover_svc.clone());
 
+        let assert_excluded_endpoint =
+            |src: &Workload, svc: &Service, excluded: &[String], ips: Vec<&str>, desc: &str| {
+                let hints = LoadBalanceHints {
+                    excluded,
+                    ..Default::default()
+                };
+                let got = state
+                    .load_balance(src, svc, &hints)
+                    .and_then(|ep| ep.address.clone())
+                    .map(|addr| addr.address.to_string());
+                if ips.is_empty() {
+                    assert!(got.is_none(), "{}", desc);
+                } else {
+                    let want: Vec<String> = ips.iter().map(ToString::to_string).collect();
+                    assert!(want.contains(&got.unwrap()), "{}", desc);
+                }
+            };
         let assert_endpoint = |src: &Workload, svc: &Service, ips: Vec<&str>, desc: &str| {
             let got = state
-                .load_balance(src, svc)
+                .load_balance(src, svc, &Default::default())
                 .and_then(|ep| ep.address.clone())
                 .map(|addr| addr.address.to_string());
             if ips.is_empty() {
@@ -1144,5 +1732,54 @@ mod tests {
             vec!["192.168.0.2"],
             "failover full match selects closest match",
         );
+
+        let excluded = vec!["cluster1//v1/Pod/default/wl_match".to_string()];
+        assert_excluded_endpoint(
+            &wl_match,
+            &strict_svc,
+            &excluded,
+            vec![],
+            "strict excluded match should not select",
+        );
+        assert_excluded_endpoint(
+            &wl_match,
+            &failover_svc,
+            &excluded,
+            vec!["192.168.0.4"],
+            "failover excluded match selects the next closest endpoint",
+        );
+        let excluded = vec!["cluster1//v1/Pod/default/ep_almost".to_string()];
+        assert_excluded_endpoint(
+            &wl_almost,
+            &failover_svc,
+            &excluded,
+            vec!["192.168.0.2"],
+            "failover selects a remaining endpoint",
+        );
+
+        assert!(state
+            .outliers
+            .record_failure("cluster1//v1/Pod/default/ep_almost"));
+        assert_endpoint(
+            &wl_no_locality,
+            &failover_svc,
+            vec!["192.168.0.2", "192.168.0.5"],
+            "failover skips ejected endpoints",
+        );
+        assert_endpoint(
+            &wl_almost,
+            &failover_svc,
+            vec!["192.168.0.2"],
+            "failover skips ejected endpoints even if they are closer",
+        );
+        assert!(state
+            .outliers
+            .record_failure("cluster1//v1/Pod/default/wl_match"));
+        assert_endpoint(
+            &wl_match,
+            &strict_svc,
+            vec!["192.168.0.2"],
+            "ejected endpoints are used if there are no others",
+        );
     }
 }
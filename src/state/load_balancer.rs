@@ -0,0 +1,275 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use rand::seq::{IteratorRandom, SliceRandom};
+
+use crate::proxy::connection_manager::ConnectionManager;
+use crate::state::service::{Endpoint, Service};
+
+/// The algorithm used to pick between service endpoints that are equally preferred by locality.
+#[derive(Debug, Default, Eq, PartialEq, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum LoadBalancerAlgorithm {
+    /// Pick an endpoint uniformly at random.
+    #[default]
+    Random,
+    /// Pick the endpoint with the fewest active connections.
+    LeastConnections,
+    /// Pick two endpoints at random, and use the one with fewer active connections.
+    PowerOfTwoChoices,
+    /// Consistently pick the same endpoint for a given source IP, for session affinity.
+    ConsistentHash,
+}
+
+impl FromStr for LoadBalancerAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "RANDOM" => Ok(LoadBalancerAlgorithm::Random),
+            "LEAST_CONNECTIONS" | "LEAST_CONN" => Ok(LoadBalancerAlgorithm::LeastConnections),
+            "POWER_OF_TWO_CHOICES" | "P2C" => Ok(LoadBalancerAlgorithm::PowerOfTwoChoices),
+            "CONSISTENT_HASH" => Ok(LoadBalancerAlgorithm::ConsistentHash),
+            _ => Err(format!("unknown load balancing algorithm {s}")),
+        }
+    }
+}
+
+/// LoadBalancerAlgorithms determines which algorithm is used for each service.
+#[derive(Debug, Default, Clone, serde::Serialize)]
+pub struct LoadBalancerAlgorithms {
+    pub default: LoadBalancerAlgorithm,
+    /// Per-service overrides, keyed by service hostname.
+    pub overrides: HashMap<String, LoadBalancerAlgorithm>,
+}
+
+impl LoadBalancerAlgorithms {
+    /// Returns the algorithm for the service. A local override takes precedence over the algorithm
+    /// the control plane set for the service, which takes precedence over the node default.
+    pub fn for_service(&self, svc: &Service) -> LoadBalancerAlgorithm {
+        self.overrides
+            .get(&svc.hostname)
+            .copied()
+            .or_else(|| svc.load_balancer.as_ref().and_then(|lb| lb.algorithm))
+            .unwrap_or(self.default)
+    }
+}
+
+/// Inputs used when picking a service endpoint, beyond the service and source workload.
+#[derive(Default, Clone, Copy)]
+pub struct LoadBalanceHints<'a> {
+    /// Endpoints (by workload UID) that must not be selected, for instance because connecting to them
+    /// already failed.
+    pub excluded: &'a [String],
+    /// The IP of the downstream client, used for consistent hashing.
+    pub source_ip: Option<IpAddr>,
+    /// Tracks active connections to endpoints, used for least-connection balancing.
+    pub connections: Option<&'a ConnectionManager>,
+}
+
+impl LoadBalanceHints<'_> {
+    fn active_connections(&self, ep: &Endpoint) -> usize {
+        self.connections
+            .map(|cm| cm.outbound_connections(&ep.workload_uid))
+            .unwrap_or_default()
+    }
+}
+
+/// Selects one of the candidate endpoints with the given algorithm.
+/// If the inputs an algorithm needs are not available, this falls back to random selection.
+pub fn select<'a>(
+    algorithm: LoadBalancerAlgorithm,
+    candidates: impl Iterator<Item = &'a Endpoint>,
+    hints: &LoadBalanceHints,
+) -> Option<&'a Endpoint> {
+    let rng = &mut rand::thread_rng();
+    match (algorithm, hints.connections, hints.source_ip) {
+        (LoadBalancerAlgorithm::LeastConnections, Some(_), _) => {
+            let mut candidates: Vec<_> = candidates.collect();
+            // Shuffle first so ties are broken randomly.
+            candidates.shuffle(rng);
+            candidates
+                .into_iter()
+                .min_by_key(|ep| hints.active_connections(ep))
+        }
+        (LoadBalancerAlgorithm::PowerOfTwoChoices, Some(_), _) => candidates
+            .choose_multiple(rng, 2)
+            .into_iter()
+            .min_by_key(|ep| hints.active_connections(ep)),
+        (LoadBalancerAlgorithm::ConsistentHash, _, Some(source_ip)) => {
+            // Rendezvous hashing: each source picks the endpoint with the highest hash. This keeps most
+            // sources on the same endpoint as endpoints come and go.
+            let source = source_ip.to_string();
+            candidates.max_by_key(|ep| {
+                let address = ep
+                    .address
+                    .as_ref()
+                    .map(|a| a.to_string())
+                    .unwrap_or_default();
+                stable_hash(&[&source, &ep.workload_uid, &address])
+            })
+        }
+        _ => candidates.choose(rng),
+    }
+}
+
+/// Hashes the parts with FNV-1a, followed by a final mix for better spread. Unlike the standard
+/// library hashers, the result is the same across processes and Rust releases, so every ztunnel
+/// instance maps a source to the same endpoint.
+fn stable_hash(parts: &[&str]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+    let mut h = OFFSET_BASIS;
+    for part in parts {
+        // Terminate each part, so ("ab", "c") and ("a", "bc") hash differently.
+        for b in part.bytes().chain(std::iter::once(0xff)) {
+            h ^= b as u64;
+            h = h.wrapping_mul(PRIME);
+        }
+    }
+    // Finalizer from splitmix64
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d049bb133111eb);
+    h ^ (h >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::service::{LoadBalancer, LoadBalancerMode};
+    use crate::state::workload::NamespacedHostname;
+
+    fn endpoint(uid: &str) -> Endpoint {
+        Endpoint {
+            workload_uid: uid.to_string(),
+            service: NamespacedHostname {
+                namespace: "default".to_string(),
+                hostname: "svc.default.svc.cluster.local".to_string(),
+            },
+            address: None,
+            port: Default::default(),
+        }
+    }
+
+    #[test]
+    fn least_connections() {
+        let cm = ConnectionManager::default();
+        let endpoints = [endpoint("a"), endpoint("b"), endpoint("c")];
+        let _a = cm.track_outbound("a");
+        let _c1 = cm.track_outbound("c");
+        let _c2 = cm.track_outbound("c");
+        let hints = LoadBalanceHints {
+            connections: Some(&cm),
+            ..Default::default()
+        };
+        for _ in 0..10 {
+            let got = select(
+                LoadBalancerAlgorithm::LeastConnections,
+                endpoints.iter(),
+                &hints,
+            );
+            assert_eq!(got.unwrap().workload_uid, "b");
+        }
+        for _ in 0..10 {
+            let got = select(
+                LoadBalancerAlgorithm::PowerOfTwoChoices,
+                endpoints.iter(),
+                &hints,
+            );
+            // The most loaded endpoint can never win a comparison
+            assert_ne!(got.unwrap().workload_uid, "c");
+        }
+    }
+
+    #[test]
+    fn consistent_hash() {
+        let endpoints = [endpoint("a"), endpoint("b"), endpoint("c"), endpoint("d")];
+        let pick = |ip: &str, endpoints: &[Endpoint]| {
+            let hints = LoadBalanceHints {
+                source_ip: Some(ip.parse().unwrap()),
+                ..Default::default()
+            };
+            select(
+                LoadBalancerAlgorithm::ConsistentHash,
+                endpoints.iter(),
+                &hints,
+            )
+            .unwrap()
+            .workload_uid
+            .clone()
+        };
+        let first = pick("10.0.0.1", &endpoints);
+        for _ in 0..10 {
+            assert_eq!(pick("10.0.0.1", &endpoints), first);
+        }
+        // Removing an unrelated endpoint does not move the source
+        let remaining: Vec<_> = endpoints
+            .iter()
+            .filter(|ep| ep.workload_uid == first || ep.workload_uid == "a")
+            .cloned()
+            .collect();
+        assert_eq!(pick("10.0.0.1", &remaining), first);
+    }
+
+    #[test]
+    fn stable_hash_is_fixed() {
+        // The hash must not change between releases, or sources would move between endpoints on upgrade.
+        assert_eq!(stable_hash(&["10.0.0.1", "a"]), 0x1a9fcb7f2e4e7d5e);
+        assert_ne!(stable_hash(&["ab", "c"]), stable_hash(&["a", "bc"]));
+    }
+
+    #[test]
+    fn algorithm_for_service() {
+        let mut svc = crate::test_helpers::mock_default_service();
+        let mut algorithms = LoadBalancerAlgorithms {
+            default: LoadBalancerAlgorithm::PowerOfTwoChoices,
+            overrides: HashMap::new(),
+        };
+        assert_eq!(
+            algorithms.for_service(&svc),
+            LoadBalancerAlgorithm::PowerOfTwoChoices
+        );
+        svc.load_balancer = Some(LoadBalancer {
+            routing_preferences: vec![],
+            mode: LoadBalancerMode::Failover,
+            algorithm: Some(LoadBalancerAlgorithm::ConsistentHash),
+        });
+        assert_eq!(
+            algorithms.for_service(&svc),
+            LoadBalancerAlgorithm::ConsistentHash
+        );
+        algorithms.overrides.insert(
+            svc.hostname.clone(),
+            LoadBalancerAlgorithm::LeastConnections,
+        );
+        assert_eq!(
+            algorithms.for_service(&svc),
+            LoadBalancerAlgorithm::LeastConnections
+        );
+    }
+
+    #[test]
+    fn parse_algorithm() {
+        assert_eq!(
+            "least_connections".parse(),
+            Ok(LoadBalancerAlgorithm::LeastConnections)
+        );
+        assert_eq!("P2C".parse(), Ok(LoadBalancerAlgorithm::PowerOfTwoChoices));
+        assert!("ROUND_ROBIN".parse::<LoadBalancerAlgorithm>().is_err());
+    }
+}
@@ -0,0 +1,274 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::ser::SerializeMap;
+use serde::Serializer;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OutlierConfig {
+    /// How many consecutive failures eject an endpoint. If zero, outlier detection is disabled.
+    pub consecutive_failures: u32,
+    /// How long an endpoint is ejected for the first time. Each further ejection doubles this.
+    pub base_ejection_time: Duration,
+    /// The upper bound on how long an endpoint can be ejected for.
+    pub max_ejection_time: Duration,
+}
+
+#[derive(Debug, Default)]
+struct EndpointHealth {
+    consecutive_failures: u32,
+    ejections: u32,
+    ejected_until: Option<Instant>,
+    last_failure: Option<Instant>,
+}
+
+impl EndpointHealth {
+    fn is_ejected(&self, now: Instant) -> bool {
+        self.ejected_until.map_or(false, |t| t > now)
+    }
+
+    // An endpoint is forgotten once it is not ejected, and has not failed for `quiet`.
+    fn is_stale(&self, now: Instant, quiet: Duration) -> bool {
+        !self.is_ejected(now)
+            && self
+                .last_failure
+                .map_or(true, |t| now.saturating_duration_since(t) >= quiet)
+    }
+}
+
+/// OutlierDetector passively tracks connection failures to service endpoints, keyed by workload UID.
+/// Endpoints that fail repeatedly are ejected from load balancing for an exponentially growing period.
+#[derive(Clone, Default)]
+pub struct OutlierDetector {
+    config: OutlierConfig,
+    endpoints: Arc<Mutex<HashMap<String, EndpointHealth>>>,
+}
+
+impl OutlierDetector {
+    pub fn new(config: OutlierConfig) -> Self {
+        Self {
+            config,
+            endpoints: Default::default(),
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        self.config.consecutive_failures > 0
+    }
+
+    pub fn is_ejected(&self, uid: &str) -> bool {
+        if !self.enabled() {
+            return false;
+        }
+        let now = Instant::now();
+        self.endpoints
+            .lock()
+            .unwrap()
+            .get(uid)
+            .map_or(false, |h| h.is_ejected(now))
+    }
+
+    pub fn record_success(&self, uid: &str) {
+        if !self.enabled() {
+            return;
+        }
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let Some(h) = endpoints.get_mut(uid) else {
+            return;
+        };
+        h.consecutive_failures = 0;
+        if h.is_ejected(Instant::now()) {
+            return;
+        }
+        // A healthy endpoint slowly earns back shorter ejections.
+        h.ejections = h.ejections.saturating_sub(1);
+        if h.ejections == 0 {
+            endpoints.remove(uid);
+        }
+    }
+
+    /// Records a failure for the endpoint, returning true if this ejected it.
+    pub fn record_failure(&self, uid: &str) -> bool {
+        if !self.enabled() {
+            return false;
+        }
+        let now = Instant::now();
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let h = endpoints.entry(uid.to_string()).or_default();
+        h.last_failure = Some(now);
+        if h.is_ejected(now) {
+            // Failures of connections that started before the ejection do not extend it.
+            return false;
+        }
+        h.consecutive_failures += 1;
+        if h.consecutive_failures < self.config.consecutive_failures {
+            return false;
+        }
+        let ejection = self
+            .config
+            .base_ejection_time
+            .saturating_mul(2u32.saturating_pow(h.ejections))
+            .min(self.config.max_ejection_time);
+        h.consecutive_failures = 0;
+        h.ejections = h.ejections.saturating_add(1);
+        h.ejected_until = Some(now + ejection);
+        true
+    }
+
+    /// Forgets the endpoint, once its workload is removed.
+    pub fn remove(&self, uid: &str) {
+        self.endpoints.lock().unwrap().remove(uid);
+    }
+
+    /// Returns the number of endpoints that are currently ejected. Endpoints that have been healthy
+    /// for longer than the maximum ejection time are forgotten along the way, so the state of
+    /// endpoints that no longer receive traffic does not linger.
+    pub fn ejected(&self) -> usize {
+        let now = Instant::now();
+        let quiet = self.config.max_ejection_time;
+        let mut endpoints = self.endpoints.lock().unwrap();
+        endpoints.retain(|_, h| !h.is_stale(now, quiet));
+        endpoints.values().filter(|h| h.is_ejected(now)).count()
+    }
+}
+
+impl fmt::Debug for OutlierDetector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OutlierDetector")
+            .field("config", &self.config)
+            .field("ejected", &self.ejected())
+            .finish()
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EndpointHealthDump {
+    consecutive_failures: u32,
+    ejections: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ejected_for: Option<String>,
+}
+
+impl serde::Serialize for OutlierDetector {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let now = Instant::now();
+        let endpoints = self.endpoints.lock().unwrap();
+        let mut map = serializer.serialize_map(Some(endpoints.len()))?;
+        for (uid, h) in endpoints.iter() {
+            let dump = EndpointHealthDump {
+                consecutive_failures: h.consecutive_failures,
+                ejections: h.ejections,
+                ejected_for: h
+                    .ejected_until
+                    .filter(|t| *t > now)
+                    .map(|t| format!("{:?}", t - now)),
+            };
+            map.serialize_entry(uid, &dump)?;
+        }
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detector() -> OutlierDetector {
+        OutlierDetector::new(OutlierConfig {
+            consecutive_failures: 2,
+            base_ejection_time: Duration::from_secs(10),
+            max_ejection_time: Duration::from_secs(30),
+        })
+    }
+
+    fn ejected_for(d: &OutlierDetector, uid: &str) -> Duration {
+        let until = d.endpoints.lock().unwrap()[uid].ejected_until.unwrap();
+        until - Instant::now()
+    }
+
+    #[test]
+    fn disabled() {
+        let d = OutlierDetector::default();
+        for _ in 0..10 {
+            assert!(!d.record_failure("a"));
+        }
+        assert!(!d.is_ejected("a"));
+    }
+
+    #[test]
+    fn ejects_after_consecutive_failures() {
+        let d = detector();
+        assert!(!d.record_failure("a"));
+        // A success resets the count
+        d.record_success("a");
+        assert!(!d.record_failure("a"));
+        assert!(d.record_failure("a"));
+        assert!(d.is_ejected("a"));
+        assert!(!d.is_ejected("b"));
+        assert_eq!(d.ejected(), 1);
+        // Failures while ejected do not extend the ejection
+        assert!(!d.record_failure("a"));
+        assert!(ejected_for(&d, "a") <= Duration::from_secs(10));
+    }
+
+    #[test]
+    fn forgets_endpoints() {
+        let d = OutlierDetector::new(OutlierConfig {
+            consecutive_failures: 1,
+            base_ejection_time: Duration::ZERO,
+            max_ejection_time: Duration::ZERO,
+        });
+        // Ejections that have expired are forgotten
+        assert!(d.record_failure("a"));
+        assert_eq!(d.ejected(), 0);
+        assert!(d.endpoints.lock().unwrap().is_empty());
+
+        let d = detector();
+        assert!(!d.record_failure("a"));
+        assert!(d.record_failure("b"));
+        d.remove("b");
+        assert!(!d.is_ejected("b"));
+        assert_eq!(d.ejected(), 0);
+        // Recent failures are kept
+        assert!(d.endpoints.lock().unwrap().contains_key("a"));
+    }
+
+    #[test]
+    fn ejection_time_grows() {
+        let d = detector();
+        let eject = |d: &OutlierDetector| {
+            d.endpoints
+                .lock()
+                .unwrap()
+                .entry("a".to_string())
+                .or_default()
+                .ejected_until = None;
+            assert!(!d.record_failure("a"));
+            assert!(d.record_failure("a"));
+            ejected_for(d, "a")
+        };
+        assert!(eject(&d) <= Duration::from_secs(10));
+        let second = eject(&d);
+        assert!(second > Duration::from_secs(10) && second <= Duration::from_secs(20));
+        // Capped at the max ejection time
+        assert!(eject(&d) <= Duration::from_secs(30));
+        assert!(eject(&d) > Duration::from_secs(20));
+    }
+}
@@ -21,6 +21,7 @@ use tracing::trace;
 
 use xds::istio::workload::Service as XdsService;
 
+use crate::state::load_balancer::LoadBalancerAlgorithm;
 use crate::state::workload::is_default;
 use crate::state::workload::{
     byte_to_ip, network_addr, GatewayAddress, NamespacedHostname, NetworkAddress, Workload,
@@ -70,6 +71,19 @@ impl From<xds::istio::workload::load_balancing::Mode> for LoadBalancerMode {
     }
 }
 
+impl From<xds::istio::workload::load_balancing::Algorithm> for Option<LoadBalancerAlgorithm> {
+    fn from(value: xds::istio::workload::load_balancing::Algorithm) -> Self {
+        use xds::istio::workload::load_balancing::Algorithm;
+        match value {
+            Algorithm::UnspecifiedAlgorithm => None,
+            Algorithm::Random => Some(LoadBalancerAlgorithm::Random),
+            Algorithm::LeastConnections => Some(LoadBalancerAlgorithm::LeastConnections),
+            Algorithm::PowerOfTwoChoices => Some(LoadBalancerAlgorithm::PowerOfTwoChoices),
+            Algorithm::ConsistentHash => Some(LoadBalancerAlgorithm::ConsistentHash),
+        }
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, Clone, serde::Serialize, serde::Deserialize)]
 pub enum LoadBalancerScopes {
     Region,
@@ -100,6 +114,9 @@ impl TryFrom<XdsScope> for LoadBalancerScopes {
 pub struct LoadBalancer {
     pub routing_preferences: Vec<LoadBalancerScopes>,
     pub mode: LoadBalancerMode,
+    /// The algorithm to use for this service, if it overrides the node default.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub algorithm: Option<LoadBalancerAlgorithm>,
 }
 
 impl Service {
@@ -185,6 +202,8 @@ impl TryFrom<&XdsService> for Service {
                     })
                     .collect::<Result<Vec<LoadBalancerScopes>, WorkloadError>>()?,
                 mode: xds::istio::workload::load_balancing::Mode::try_from(lb.mode)?.into(),
+                algorithm: xds::istio::workload::load_balancing::Algorithm::try_from(lb.algorithm)?
+                    .into(),
             })
         } else {
             None
@@ -70,6 +70,62 @@ pub struct Locality {
     pub subzone: String,
 }
 
+/// BandwidthLimit caps the rate, in bytes per second, of data sent (upload) and received (download)
+/// over proxied connections. A limit of zero is unlimited; unset limits fall back to the node default
+/// where one applies.
+#[derive(
+    Default, Debug, Hash, Eq, PartialEq, Clone, Copy, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "camelCase")]
+pub struct BandwidthLimit {
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub upload: Option<u64>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub download: Option<u64>,
+}
+
+impl BandwidthLimit {
+    /// or fills in limits that are not set from `other`.
+    pub fn or(self, other: BandwidthLimit) -> BandwidthLimit {
+        BandwidthLimit {
+            upload: self.upload.or(other.upload),
+            download: self.download.or(other.download),
+        }
+    }
+}
+
+impl FromStr for BandwidthLimit {
+    type Err = String;
+
+    /// Parses limits formatted as `upload:download`, where either side may be empty or zero for no
+    /// limit.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (upload, download) = s
+            .split_once(':')
+            .ok_or_else(|| format!("invalid bandwidth limit {s}, expected upload:download"))?;
+        let parse = |v: &str| match v.trim() {
+            "" => Ok(None),
+            v => v
+                .parse::<u64>()
+                .map(|r| Some(r).filter(|r| *r > 0))
+                .map_err(|e| format!("invalid bandwidth limit {s}: {e}")),
+        };
+        Ok(BandwidthLimit {
+            upload: parse(upload)?,
+            download: parse(download)?,
+        })
+    }
+}
+
+impl From<xds::istio::workload::BandwidthLimit> for BandwidthLimit {
+    fn from(value: xds::istio::workload::BandwidthLimit) -> Self {
+        BandwidthLimit {
+            upload: value.upload_bytes_per_second,
+            download: value.download_bytes_per_second,
+        }
+    }
+}
+
 impl From<xds::istio::workload::Locality> for Locality {
     fn from(value: xds::istio::workload::Locality) -> Self {
         Locality {
@@ -203,6 +259,9 @@ pub struct Workload {
 
     #[serde(default, skip_serializing_if = "is_default")]
     pub locality: Locality,
+
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub bandwidth_limit: BandwidthLimit,
 }
 
 pub fn is_default<T: Default + PartialEq>(t: &T) -> bool {
@@ -416,6 +475,11 @@ impl TryFrom<&XdsWorkload> for Workload {
 
             locality: resource.locality.map(Locality::from).unwrap_or_default(),
 
+            bandwidth_limit: resource
+                .bandwidth_limit
+                .map(BandwidthLimit::from)
+                .unwrap_or_default(),
+
             cluster_id: {
                 let result = resource.cluster_id;
                 if result.is_empty() {
@@ -763,6 +827,31 @@ mod tests {
         assert_eq!(maybe_loopback_ip.to_string(), "::1");
     }
 
+    #[test]
+    fn bandwidth_limit_from_xds() {
+        let wl = Workload::try_from(&XdsWorkload {
+            uid: "uid".to_string(),
+            bandwidth_limit: Some(xds::istio::workload::BandwidthLimit {
+                upload_bytes_per_second: Some(0),
+                download_bytes_per_second: None,
+            }),
+            ..Default::default()
+        })
+        .unwrap();
+        // An explicit zero is unlimited, and overrides the node default; unset limits inherit it
+        let node_default = BandwidthLimit {
+            upload: Some(1000),
+            download: Some(2000),
+        };
+        assert_eq!(
+            wl.bandwidth_limit.or(node_default),
+            BandwidthLimit {
+                upload: Some(0),
+                download: Some(2000),
+            }
+        );
+    }
+
     #[test]
     fn workload_information() {
         initialize_telemetry();
@@ -46,6 +46,7 @@ use tracing::trace;
 pub mod app;
 pub mod ca;
 pub mod dns;
+pub mod ext_authz;
 pub mod helpers;
 #[cfg(target_os = "linux")]
 pub mod inpod;
@@ -100,11 +101,13 @@ pub fn test_config_with_port_xds_addr_and_root_cert(
         // inbound_addr cannot do localhost since we abuse that its listening on all of 127.0.0.0/8 range.
         inbound_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
         socks5_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0),
+        http_connect_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0),
         admin_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0),
         readiness_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0),
         stats_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0),
         outbound_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0),
         inbound_plaintext_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0),
+        inbound_single_tls_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0),
         dns_proxy_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0),
         ..config::parse_config().unwrap()
     }
@@ -217,6 +220,7 @@ pub fn test_default_workload() -> Workload {
         native_tunnel: false,
         application_tunnel: None,
         locality: Default::default(),
+        bandwidth_limit: Default::default(),
     }
 }
 
@@ -189,6 +189,30 @@ impl TestApp {
         socks5_connect(stream, addr).await.unwrap()
     }
 
+    /// Opens a tunnel through the HTTP CONNECT proxy. `early_data` is sent in the same write as the
+    /// CONNECT request, without waiting for the response.
+    pub async fn http_connect(
+        &self,
+        addr: SocketAddr,
+        source: IpAddr,
+        early_data: &[u8],
+    ) -> TcpStream {
+        let proxy_addr = with_ip(
+            self.proxy_addresses.http_connect,
+            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+        );
+        // Set source IP to TEST_WORKLOAD_SOURCE
+        let socket = TcpSocket::new_v4().unwrap();
+        socket
+            .bind(SocketAddr::from((source, 0)))
+            .map_err(|e| anyhow!("{:?}. {}", e, localhost_error_message()))
+            .unwrap();
+
+        let stream = socket.connect(proxy_addr).await.unwrap();
+        stream.set_nodelay(true).unwrap();
+        http_connect(stream, addr, early_data).await.unwrap()
+    }
+
     pub async fn dns_request(
         &self,
         hostname: &str,
@@ -262,6 +286,29 @@ pub async fn socks5_connect(mut stream: TcpStream, addr: SocketAddr) -> anyhow::
     Ok(stream)
 }
 
+pub async fn http_connect(
+    mut stream: TcpStream,
+    addr: SocketAddr,
+    early_data: &[u8],
+) -> anyhow::Result<TcpStream> {
+    let authority = socket::to_canonical(addr);
+    let mut req = format!("CONNECT {authority} HTTP/1.1\r\nHost: {authority}\r\n\r\n").into_bytes();
+    req.extend_from_slice(early_data);
+    stream.write_all(&req).await?;
+
+    // Read the response headers, one byte at a time so we don't consume any of the tunnel
+    let mut resp = Vec::new();
+    while !resp.ends_with(b"\r\n\r\n") {
+        resp.push(stream.read_u8().await?);
+    }
+    let resp = String::from_utf8_lossy(&resp);
+    if !resp.starts_with("HTTP/1.1 200") {
+        anyhow::bail!("CONNECT failed: {resp}");
+    }
+
+    Ok(stream)
+}
+
 #[derive(Debug)]
 pub struct ParsedMetrics {
     scrape: Scrape,
@@ -0,0 +1,91 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use hyper_util::rt::TokioIo;
+use tracing::error;
+
+use crate::rbac::ext_authz::proto::authorization_server::{Authorization, AuthorizationServer};
+use crate::rbac::ext_authz::proto::{CheckRequest, CheckResponse, Status};
+
+/// ExtAuthzServer provides a fake external authorization service, which decides on the
+/// destination port of connections.
+#[derive(Clone, Default)]
+pub struct ExtAuthzServer {
+    /// Connections to these ports are allowed, and all others are denied.
+    pub allowed_ports: Vec<u32>,
+    /// How long to wait before responding to each check.
+    pub delay: Duration,
+    /// The number of checks received.
+    pub checks: Arc<AtomicUsize>,
+}
+
+impl ExtAuthzServer {
+    /// spawn serves plaintext gRPC in the background, returning the address it listens on.
+    pub async fn spawn(self) -> SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = listener.local_addr().unwrap();
+        let srv = AuthorizationServer::new(self);
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                let srv = srv.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = crate::hyper_util::http2_server()
+                        .serve_connection(
+                            TokioIo::new(socket),
+                            tower_hyper_http_body_compat::TowerService03HttpServiceAsHyper1HttpService::new(srv)
+                        )
+                        .await
+                    {
+                        error!("Error serving connection: {:?}", err);
+                    }
+                });
+            }
+        });
+        server_addr
+    }
+}
+
+#[async_trait]
+impl Authorization for ExtAuthzServer {
+    async fn check(
+        &self,
+        request: tonic::Request<CheckRequest>,
+    ) -> Result<tonic::Response<CheckResponse>, tonic::Status> {
+        self.checks.fetch_add(1, Ordering::SeqCst);
+        tokio::time::sleep(self.delay).await;
+        let port = request
+            .into_inner()
+            .attributes
+            .and_then(|a| a.destination)
+            .and_then(|p| p.address)
+            .and_then(|a| a.socket_address)
+            .map(|s| s.port_value);
+        let code = match port {
+            Some(port) if self.allowed_ports.contains(&port) => tonic::Code::Ok,
+            _ => tonic::Code::PermissionDenied,
+        };
+        Ok(tonic::Response::new(CheckResponse {
+            status: Some(Status {
+                code: code as i32,
+                message: String::new(),
+            }),
+        }))
+    }
+}
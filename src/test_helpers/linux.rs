@@ -120,6 +120,7 @@ impl WorkloadManager {
                 inbound: "0.0.0.0:0".parse()?,
                 outbound: "0.0.0.0:0".parse()?,
                 socks5: "0.0.0.0:0".parse()?,
+                http_connect: "0.0.0.0:0".parse()?,
             });
 
             let ta = TestApp {
@@ -131,6 +132,7 @@ impl WorkloadManager {
                     outbound: helpers::with_ip(proxy_addresses.outbound, ip),
                     inbound: helpers::with_ip(proxy_addresses.inbound, ip),
                     socks5: helpers::with_ip(proxy_addresses.socks5, ip),
+                    http_connect: helpers::with_ip(proxy_addresses.http_connect, ip),
                 },
                 tcp_dns_proxy_address: Some(helpers::with_ip(
                     app.tcp_dns_proxy_address.unwrap_or("0.0.0.0:0".parse()?),
@@ -105,6 +105,20 @@ pub async fn grpc_tls_connector(uri: String, root_cert: RootCert) -> Result<TlsG
 
 /// grpc_connector provides a client TLS channel for gRPC requests.
 pub fn grpc_connector(uri: String, cc: ClientConfig) -> Result<TlsGrpcChannel, Error> {
+    grpc_channel(uri, cc, true)
+}
+
+/// grpc_plaintext_connector provides a client channel for gRPC requests to an http:// URI.
+pub fn grpc_plaintext_connector(uri: String) -> Result<TlsGrpcChannel, Error> {
+    // The TLS configuration is never used, but the connector requires one.
+    let cc = ClientConfig::builder_with_provider(provider())
+        .with_protocol_versions(crate::tls::TLS_VERSIONS)?
+        .with_root_certificates(rustls::RootCertStore::empty())
+        .with_no_client_auth();
+    grpc_channel(uri, cc, false)
+}
+
+fn grpc_channel(uri: String, cc: ClientConfig, https_only: bool) -> Result<TlsGrpcChannel, Error> {
     let uri = Uri::try_from(uri)?;
     let _is_localhost_call = uri.host() == Some("localhost");
     let mut http: HttpConnector = HttpConnector::new();
@@ -118,11 +132,13 @@ pub fn grpc_connector(uri: String, cc: ClientConfig) -> Result<TlsGrpcChannel, E
     http.set_keepalive_retries(Some(9));
     http.set_connect_timeout(Some(Duration::from_secs(5)));
     http.enforce_http(false);
-    let https: HttpsConnector<HttpConnector> = hyper_rustls::HttpsConnectorBuilder::new()
-        .with_tls_config(cc)
-        .https_only()
-        .enable_http2()
-        .wrap_connector(http);
+    let builder = hyper_rustls::HttpsConnectorBuilder::new().with_tls_config(cc);
+    let builder = if https_only {
+        builder.https_only()
+    } else {
+        builder.https_or_http()
+    };
+    let https: HttpsConnector<HttpConnector> = builder.enable_http2().wrap_connector(http);
 
     // Configure hyper's client to be h2 only and build with the
     // correct https connector.
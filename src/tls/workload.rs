@@ -27,10 +27,12 @@ use rustls::{
 };
 use std::future::Future;
 use std::io;
+use std::net::IpAddr;
 use std::pin::Pin;
 use std::sync::Arc;
 
 use crate::tls;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpStream;
 use tokio_rustls::client;
 use tracing::{debug, trace};
@@ -174,6 +176,20 @@ impl OutboundConnector {
         let c = tokio_rustls::TlsConnector::from(self.client_config);
         c.connect(dest, stream).await
     }
+
+    /// connect_tunneled is like [OutboundConnector::connect], but runs over an existing stream, such as
+    /// an HBONE tunnel, to the given destination.
+    pub async fn connect_tunneled<IO>(
+        self,
+        stream: IO,
+        dest: IpAddr,
+    ) -> Result<client::TlsStream<IO>, io::Error>
+    where
+        IO: AsyncRead + AsyncWrite + Unpin,
+    {
+        let c = tokio_rustls::TlsConnector::from(self.client_config);
+        c.connect(ServerName::IpAddress(dest.into()), stream).await
+    }
 }
 
 #[derive(Debug)]
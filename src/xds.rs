@@ -177,6 +177,10 @@ impl ProxyStateUpdateMutator {
             if !for_insert && !state.workloads.has_identity(&prev.identity()) {
                 self.cert_fetcher.clear_cert(&prev.identity());
             }
+            // The workload is gone, so is the health of its endpoints
+            if !for_insert {
+                state.outliers.remove(&prev.uid);
+            }
             // We removed a workload, no reason to attempt to remove a service with the same name
             return;
         }
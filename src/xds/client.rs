@@ -849,6 +849,7 @@ mod tests {
                     }],
                 }],
             }],
+            dry_run: false,
         };
         ProtoResource {
             name: format!("foo{}", i),
@@ -943,7 +944,7 @@ mod tests {
                     };
 
                     // rbac should reject port 80
-                    let rbac_res = state.assert_rbac(&rbac_ctx).await;
+                    let rbac_res = state.assert_rbac(&rbac_ctx).await.allowed;
                     assert!(!rbac_res);
                     let conn = crate::rbac::Connection{
                         dst: std::net::SocketAddr::new(std::net::Ipv4Addr::new(1, 2, 3, 4).into(), 81),
@@ -955,7 +956,7 @@ mod tests {
                     };
 
                     // but allow port 81
-                    let rbac_res = state.assert_rbac(&rbac_ctx).await;
+                    let rbac_res = state.assert_rbac(&rbac_ctx).await.allowed;
                     assert!(rbac_res);
                     return;
                 }
@@ -79,6 +79,11 @@ async fn test_conflicting_bind_error_socks5() {
     test_bind_conflict(|c| &mut c.socks5_addr).await;
 }
 
+#[tokio::test]
+async fn test_conflicting_bind_error_http_connect() {
+    test_bind_conflict(|c| &mut c.http_connect_addr).await;
+}
+
 #[tokio::test]
 async fn test_conflicting_bind_error_admin() {
     test_bind_conflict(|c| &mut c.admin_addr).await;
@@ -230,6 +235,44 @@ async fn test_vip_request() {
     run_request_test(&format!("{TEST_VIP}:80"), "").await;
 }
 
+#[tokio::test]
+async fn test_http_connect_request() {
+    // Test a round trip outbound call (via HTTP CONNECT)
+    let echo = tcp::TestServer::new(tcp::Mode::ReadWrite, 0).await;
+    let echo_addr = echo.address();
+    let cfg = test_config_with_port(echo_addr.port());
+    tokio::spawn(echo.run());
+    testapp::with_app(cfg, |app| async move {
+        let dst = helpers::with_ip(echo_addr, TEST_WORKLOAD_HBONE.parse().unwrap());
+        let mut stream = app
+            .http_connect(dst, TEST_WORKLOAD_SOURCE.parse().unwrap(), &[])
+            .await;
+        read_write_stream(&mut stream).await;
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_http_connect_pipelined_request() {
+    // Data sent along with the CONNECT request, before the response, should reach the upstream
+    let echo = tcp::TestServer::new(tcp::Mode::ReadWrite, 0).await;
+    let echo_addr = echo.address();
+    let cfg = test_config_with_port(echo_addr.port());
+    tokio::spawn(echo.run());
+    testapp::with_app(cfg, |app| async move {
+        const BODY: &[u8] = b"hello world";
+        let dst = helpers::with_ip(echo_addr, TEST_WORKLOAD_HBONE.parse().unwrap());
+        let mut stream = app
+            .http_connect(dst, TEST_WORKLOAD_SOURCE.parse().unwrap(), BODY)
+            .await;
+        let mut buf = [0; BODY.len()];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(BODY, buf);
+        read_write_stream(&mut stream).await;
+    })
+    .await;
+}
+
 fn on_demand_dns_assertions(metrics: ParsedMetrics) {
     for metric in &[
         ("istio_on_demand_dns_total"),
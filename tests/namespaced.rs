@@ -519,6 +519,7 @@ mod namespaced {
                 )],
                 ..Default::default()
             }]]],
+            dry_run: false,
         });
         let ip = waypoint.ip();
         run_hbone_server(waypoint)?;